@@ -1,7 +1,7 @@
 use bevy::prelude::*;
-use bevy_rapier3d::plugin::{NoUserData, RapierPhysicsPlugin};
+use simulation::SimulationPlugin;
 
-use crate::game::{cursor::cursor, ui::ui};
+use crate::game::{cursor::cursor, network::{interpolation, prediction}, ui::ui};
 
 use super::{level::level, player::player};
 pub struct GamePlugin;
@@ -18,11 +18,13 @@ pub const VIEW_MODEL_RENDER_LAYER: usize = 1;
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((
-            RapierPhysicsPlugin::<NoUserData>::default(),
+            SimulationPlugin,
             level::LevelPlugin,
             player::PlayerPlugin,
             ui::UiPlugin,
-            cursor::CursorPlugin
+            cursor::CursorPlugin,
+            interpolation::InterpolationPlugin,
+            prediction::PredictionPlugin,
         ));
     }
-}
\ No newline at end of file
+}
@@ -1,30 +1,32 @@
 use bevy::{
-    asset::RenderAssetUsages,
     color::palettes::basic::SILVER,
     prelude::*,
-    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
 };
-use bevy_rapier3d::prelude::*;
+use simulation::level::{self, Ground, LEVEL_NAME, Map, Prop, SHAPE_COUNT, Shape};
 use snowball::uv_debug_texture;
 
-const SHAPES_X_EXTENT: f32 = 14.0;
-const EXTRUSION_X_EXTENT: f32 = 16.0;
-const Z_EXTENT: f32 = 5.0;
-use std::f32::consts::PI;
-
-
-#[derive(Component)]
-pub struct Shape;
-
 pub struct LevelPlugin;
 
 impl Plugin for LevelPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, init_level)
-        .add_systems(Update, rotate);
+        // The built in level until a server says otherwise.
+        app.insert_resource(level::builtin(LEVEL_NAME).expect("the default level is built in"))
+            .add_systems(Startup, init_level_visuals)
+            .add_systems(Update, dress_level);
     }
 }
-fn init_level(
+
+/// What level entities are drawn with.
+#[derive(Resource)]
+struct LevelAssets {
+    debug_material: Handle<StandardMaterial>,
+    ground_material: Handle<StandardMaterial>,
+    shape_meshes: [Handle<Mesh>; SHAPE_COUNT],
+    prop_mesh: Handle<Mesh>,
+}
+
+/// Makes the meshes and materials level entities use, and lights the scene.
+fn init_level_visuals(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
@@ -35,24 +37,7 @@ fn init_level(
         ..default()
     });
 
-    let ground_height = 0.1;
-    let ground_size = 200.1;
-    // Ground plane
-    commands.spawn((
-        Transform::from_xyz(0.0, -ground_height, 0.0),
-        Collider::cuboid(ground_size, ground_height, ground_size),
-        Mesh3d(
-            meshes.add(
-                Plane3d::default()
-                    .mesh()
-                    .size(ground_size, ground_size)
-                    .subdivisions(10),
-            ),
-        ),
-        MeshMaterial3d(materials.add(Color::from(SILVER))),
-    ));
-
-    let shapes = [
+    let shape_meshes: [Handle<Mesh>; SHAPE_COUNT] = [
         meshes.add(Cuboid::default()),
         meshes.add(Tetrahedron::default()),
         meshes.add(Capsule3d::default()),
@@ -64,41 +49,12 @@ fn init_level(
         meshes.add(Sphere::default().mesh().uv(32, 18)),
     ];
 
-    let num_shapes = shapes.len();
-    for (i, shape) in shapes.into_iter().enumerate() {
-        commands.spawn((
-            Mesh3d(shape),
-            MeshMaterial3d(debug_material.clone()),
-            Transform::from_xyz(
-                -SHAPES_X_EXTENT / 2. + i as f32 / (num_shapes - 1) as f32 * SHAPES_X_EXTENT,
-                2.0,
-                Z_EXTENT / 2.,
-            )
-            .with_rotation(Quat::from_rotation_x(-PI / 4.)),
-            Collider::from_bevy_mesh(
-                &Cuboid::default().mesh().build(),
-                &ComputedColliderShape::ConvexHull,
-            )
-            .unwrap(), // Collider::ball(3.0),
-            Shape
-        ));
-    }
-
-    commands
-        .spawn((
-            RigidBody::Dynamic,
-            Mesh3d(meshes.add(Cone::default())),
-            MeshMaterial3d(debug_material.clone()),
-        ))
-        .insert(Transform::from_xyz(5.0, 5.0, 5.0))
-        .insert(Velocity {
-            linvel: Vec3::new(0.0, 2.0, 0.0),
-            angvel: Vec3::new(0.2, 0.0, 0.0),
-        })
-        .insert(GravityScale(0.5))
-        .insert(Sleeping::disabled())
-        .insert(ColliderMassProperties::Density(2.0))
-        .insert(Ccd::enabled());
+    commands.insert_resource(LevelAssets {
+        debug_material,
+        ground_material: materials.add(Color::from(SILVER)),
+        shape_meshes,
+        prop_mesh: meshes.add(Cone::default()),
+    });
 
     commands.spawn((
         PointLight {
@@ -115,15 +71,44 @@ fn init_level(
     //     Camera3d::default(),
     //     Transform::from_xyz(0.0, 7., 14.0).looking_at(Vec3::new(0., 1., 0.), Vec3::Y),
     // ));
-
-    // meshes.add(Cuboid::default()),
-
-    info!("Finished making level!");
 }
 
+/// Gives the simulation's level entities something to look at, whenever the
+/// level is built.
+fn dress_level(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    assets: Res<LevelAssets>,
+    map: Res<Map>,
+    ground: Query<Entity, Added<Ground>>,
+    shapes: Query<(Entity, &Shape), Added<Shape>>,
+    props: Query<Entity, Added<Prop>>,
+) {
+    for entity in &ground {
+        // Ground plane
+        let ground_mesh = meshes.add(
+            Plane3d::default()
+                .mesh()
+                .size(map.ground_size, map.ground_size)
+                .subdivisions(10),
+        );
+        commands.entity(entity).insert((
+            Mesh3d(ground_mesh),
+            MeshMaterial3d(assets.ground_material.clone()),
+        ));
+    }
+
+    for (entity, shape) in &shapes {
+        commands.entity(entity).insert((
+            Mesh3d(assets.shape_meshes[shape.0].clone()),
+            MeshMaterial3d(assets.debug_material.clone()),
+        ));
+    }
 
-fn rotate(mut query: Query<&mut Transform, With<Shape>>, time: Res<Time>) {
-    for mut transform in &mut query {
-        transform.rotate_y(time.delta_secs() / 2.);
+    for entity in &props {
+        commands.entity(entity).insert((
+            Mesh3d(assets.prop_mesh.clone()),
+            MeshMaterial3d(assets.debug_material.clone()),
+        ));
     }
-}
\ No newline at end of file
+}
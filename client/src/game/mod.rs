@@ -2,4 +2,5 @@ pub mod level;
 pub mod player;
 pub mod game;
 pub mod ui;
-pub mod cursor;
\ No newline at end of file
+pub mod cursor;
+pub mod network;
\ No newline at end of file
@@ -0,0 +1,761 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::RigidBody;
+use protocol::{
+    ClientId, ClientMessage, ClientPacket, ComponentState, ConnectRequest, Connection,
+    ConnectionStats, EntityKind, MAX_MESSAGE_SIZE, NetEntity, PROTOCOL_VERSION, Packet,
+    RECV_BUFFER_SIZE, ResumeToken, RoomRequest, ServerMessage, SessionId, Snapshot,
+    SnapshotHistory,
+    netsim::{NetworkConditions, SimulatedSocket},
+};
+use simulation::{
+    SimulationSystems,
+    level::Prop,
+    player::{CAPSULE_END, CAPSULE_RADIUS, CAPSULE_START, PlayerInput},
+    replication::ReplicationRegistry,
+};
+
+use crate::game::{
+    network::{
+        clock::{ClockPlugin, ServerClock},
+        discovery::DiscoveryPlugin,
+        interpolation::{Interpolated, SnapshotBuffer, SnapshotReceived},
+        lobby::{Lobby, LobbyPlugin},
+        map_download::MapDownloadPlugin,
+        prediction::{AuthoritativeState, InputBuffer, record_input},
+        stats::NetworkStatsPlugin,
+    },
+    player::{player::LocalPlayer, player_throw::SnowballAssets},
+};
+
+/// How often to repeat a connect request that hasn't been answered.
+const CONNECT_RETRY: Duration = Duration::from_millis(500);
+
+/// Give up on a server that hasn't answered for this long.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Keep trying to get back in for this long after losing the server. Servers
+/// hold a player's place for about as long.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Consider the connection dead if the server goes quiet for this long.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Each tick's input is sent this many times in a row, so a lost packet
+/// doesn't lose a jump or a throw.
+const INPUT_REDUNDANCY: usize = 3;
+
+/// Connects to a server and keeps the world in sync with it. Without a server
+/// address it does nothing and the game runs single-player.
+pub struct NetworkClientPlugin {
+    /// `host:port` to connect to, if any.
+    pub server: Option<String>,
+    pub name: String,
+    /// Which room on the server to join.
+    pub room: RoomRequest,
+    /// For servers that have one.
+    pub password: Option<String>,
+    /// Artificial latency, loss and so on, for testing.
+    pub network: NetworkConditions,
+    /// Where maps downloaded from servers are kept.
+    pub map_cache: PathBuf,
+}
+
+impl Plugin for NetworkClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<ConnectionState>()
+            .insert_resource(NetworkSettings {
+                server: self.server.clone(),
+                name: self.name.clone(),
+                room: self.room,
+                password: self.password.clone(),
+                network: self.network,
+            })
+            .add_plugins((
+                LobbyPlugin,
+                MapDownloadPlugin {
+                    cache: self.map_cache.clone(),
+                },
+                DiscoveryPlugin,
+                ClockPlugin,
+                NetworkStatsPlugin,
+            ))
+            .init_resource::<RemoteEntities>()
+            .init_resource::<RemoteProps>()
+            .add_message::<ServerMessageReceived>()
+            .add_systems(Startup, (init_remote_player_assets, start_connecting))
+            .add_systems(
+                PreUpdate,
+                (
+                    receive_packets,
+                    handle_connection,
+                    detect_lost_connection,
+                    apply_snapshots,
+                    sync_remote_entities,
+                    sync_level_props,
+                    apply_replicated_components,
+                )
+                    .chain()
+                    .distributive_run_if(resource_exists::<NetworkClient>),
+            )
+            .add_systems(
+                Update,
+                retry_connect.run_if(
+                    in_state(ConnectionState::Connecting)
+                        .or(in_state(ConnectionState::Reconnecting)),
+                ),
+            )
+            .add_systems(
+                FixedUpdate,
+                send_input
+                    .after(record_input)
+                    .before(SimulationSystems)
+                    .run_if(in_state(ConnectionState::Connected)),
+            )
+            .add_systems(
+                Last,
+                (disconnect_on_exit, flush_packets)
+                    .chain()
+                    .distributive_run_if(resource_exists::<NetworkClient>),
+            )
+            .add_systems(OnExit(ConnectionState::Connected), clear_remote_entities);
+    }
+}
+
+#[derive(States, Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    /// Playing offline, or the server said goodbye.
+    #[default]
+    Disconnected,
+    Connecting,
+    Connected,
+    /// Lost the server, and asking it for our player back.
+    Reconnecting,
+    /// Rejected, timed out, or the server couldn't be reached at all.
+    Failed,
+}
+
+#[derive(Resource)]
+struct NetworkSettings {
+    server: Option<String>,
+    name: String,
+    room: RoomRequest,
+    password: Option<String>,
+    network: NetworkConditions,
+}
+
+/// A decoded message from the server, for the systems that act on it.
+#[derive(Message, Clone, Debug)]
+pub struct ServerMessageReceived(pub ServerMessage);
+
+/// The live connection. Only exists while connecting or connected.
+#[derive(Resource)]
+pub struct NetworkClient {
+    socket: SimulatedSocket,
+    server: SocketAddr,
+    connection: Connection,
+    session: SessionId,
+    client: Option<ClientId>,
+    /// Which room to ask for when connecting.
+    room: RoomRequest,
+    /// Gets our player back if the connection drops.
+    resume: Option<ResumeToken>,
+    snapshots: SnapshotHistory,
+    started: Instant,
+    last_connect: Option<Instant>,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+impl NetworkClient {
+    fn new(socket: SimulatedSocket, server: SocketAddr, room: RoomRequest) -> Self {
+        let now = Instant::now();
+        let mut connection = Connection::new(now);
+        // The server decides how big its messages get.
+        connection.set_max_message_size(MAX_MESSAGE_SIZE);
+        Self {
+            socket,
+            server,
+            connection,
+            session: SessionId::NONE,
+            client: None,
+            room,
+            resume: None,
+            snapshots: SnapshotHistory::default(),
+            started: now,
+            last_connect: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+        }
+    }
+
+    /// Our id on the server, once it has accepted us.
+    pub fn client_id(&self) -> Option<ClientId> {
+        self.client
+    }
+
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.connection.stats()
+    }
+
+    /// Datagram bytes sent and received since connecting, not counting UDP
+    /// and IP headers.
+    pub fn bytes(&self) -> (u64, u64) {
+        (self.bytes_sent, self.bytes_received)
+    }
+
+    pub fn send(&mut self, message: &ClientMessage) {
+        if let Err(e) = self.connection.send_message(message.channel(), message) {
+            warn!("Not sending {:?}: {}", message, e);
+        }
+    }
+}
+
+/// Client entities standing in for the server's, by server id.
+#[derive(Resource, Default)]
+struct RemoteEntities(HashMap<NetEntity, Entity>);
+
+/// The level's own props the server is moving, by server id.
+#[derive(Resource, Default)]
+struct RemoteProps(HashMap<NetEntity, Entity>);
+
+#[derive(Resource)]
+struct RemotePlayerAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+fn init_remote_player_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(RemotePlayerAssets {
+        mesh: meshes.add(Capsule3d::new(
+            CAPSULE_RADIUS,
+            CAPSULE_START.distance(CAPSULE_END),
+        )),
+        material: materials.add(Color::srgb(0.8, 0.2, 0.2)),
+    });
+}
+
+fn start_connecting(
+    mut commands: Commands,
+    settings: Res<NetworkSettings>,
+    mut state: ResMut<NextState<ConnectionState>>,
+) {
+    let Some(server) = &settings.server else {
+        return;
+    };
+
+    match open_socket(server, settings.network) {
+        Ok((socket, addr)) => {
+            info!("Connecting to {} as {}", addr, settings.name);
+            commands.insert_resource(NetworkClient::new(socket, addr, settings.room));
+            state.set(ConnectionState::Connecting);
+        }
+        Err(e) => {
+            error!("Can't reach {}: {}", server, e);
+            state.set(ConnectionState::Failed);
+        }
+    }
+}
+
+fn open_socket(
+    server: &str,
+    network: NetworkConditions,
+) -> io::Result<(SimulatedSocket, SocketAddr)> {
+    let addr = server
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no address for server"))?;
+    Ok((bind_for(addr, network)?, addr))
+}
+
+/// A socket on any free port to talk to `server` from.
+fn bind_for(server: SocketAddr, network: NetworkConditions) -> io::Result<SimulatedSocket> {
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    SimulatedSocket::new(UdpSocket::bind(local)?, network)
+}
+
+fn retry_connect(
+    mut commands: Commands,
+    settings: Res<NetworkSettings>,
+    mut client: ResMut<NetworkClient>,
+    mut state: ResMut<NextState<ConnectionState>>,
+) {
+    let now = Instant::now();
+    let timeout = match client.resume {
+        Some(_) => RECONNECT_TIMEOUT,
+        None => CONNECT_TIMEOUT,
+    };
+    if now.duration_since(client.started) > timeout {
+        error!("{} didn't answer", client.server);
+        commands.remove_resource::<NetworkClient>();
+        state.set(ConnectionState::Failed);
+        return;
+    }
+
+    if client
+        .last_connect
+        .is_none_or(|last| now.duration_since(last) >= CONNECT_RETRY)
+    {
+        client.last_connect = Some(now);
+        let request = ConnectRequest {
+            protocol_version: PROTOCOL_VERSION,
+            name: settings.name.clone(),
+            room: client.room,
+            password: settings.password.clone(),
+            resume: client.resume,
+        };
+        client.send(&ClientMessage::Connect(request));
+    }
+}
+
+/// Drains the socket and hands every message from the server on.
+fn receive_packets(
+    mut client: ResMut<NetworkClient>,
+    mut received: MessageWriter<ServerMessageReceived>,
+) {
+    let now = Instant::now();
+    let mut buf = [0; RECV_BUFFER_SIZE];
+
+    loop {
+        let (len, addr) = match client.socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+            // Windows reports ICMP port unreachable from an earlier send here.
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+            Err(e) => {
+                error!("Receiving from {}: {}", client.server, e);
+                return;
+            }
+        };
+        if addr != client.server {
+            continue;
+        }
+        client.bytes_received += len as u64;
+
+        let packet = match protocol::decode::<Packet>(&buf[..len]) {
+            Ok(packet) => packet,
+            Err(e) => {
+                warn!("Dropping packet from {}: {}", addr, e);
+                continue;
+            }
+        };
+        for (_, payload) in client.connection.receive(packet, now) {
+            match protocol::decode_message::<ServerMessage>(&payload) {
+                Ok(message) => {
+                    received.write(ServerMessageReceived(message));
+                }
+                Err(e) => warn!("Dropping message from {}: {}", addr, e),
+            }
+        }
+    }
+}
+
+fn handle_connection(
+    mut commands: Commands,
+    mut received: MessageReader<ServerMessageReceived>,
+    mut client: ResMut<NetworkClient>,
+    current: Res<State<ConnectionState>>,
+    mut state: ResMut<NextState<ConnectionState>>,
+    mut fixed: ResMut<Time<Fixed>>,
+    mut buffer: ResMut<SnapshotBuffer>,
+) {
+    for ServerMessageReceived(message) in received.read() {
+        match message {
+            ServerMessage::ConnectAccepted {
+                client: id,
+                session,
+                tick_rate,
+                resume,
+                ..
+            } => {
+                let connecting = match current.get() {
+                    ConnectionState::Connecting => true,
+                    ConnectionState::Reconnecting => false,
+                    _ => continue,
+                };
+                if client.client.is_some() {
+                    continue;
+                }
+                if connecting {
+                    info!("Connected to {} as {:?}", client.server, id);
+                } else {
+                    info!("Back on {} as {:?}", client.server, id);
+                }
+                client.client = Some(*id);
+                client.session = *session;
+                client.resume = Some(*resume);
+                // Predict at the same rate the server simulates.
+                fixed.set_timestep_hz(f64::from(*tick_rate));
+                buffer.tick_rate = f64::from(*tick_rate);
+                state.set(ConnectionState::Connected);
+            }
+            ServerMessage::ConnectRejected(reason) => {
+                error!("{} turned us away: {:?}", client.server, reason);
+                commands.remove_resource::<NetworkClient>();
+                state.set(ConnectionState::Failed);
+                return;
+            }
+            ServerMessage::Disconnected(reason) => {
+                info!("Disconnected from {}: {:?}", client.server, reason);
+                commands.remove_resource::<NetworkClient>();
+                state.set(ConnectionState::Disconnected);
+                return;
+            }
+            ServerMessage::Chat { from, text } => info!("{:?}: {}", from, text),
+            ServerMessage::Event(event) => info!("{:?}", event),
+            ServerMessage::Snapshot(_)
+            | ServerMessage::RoomState(_)
+            | ServerMessage::RoomList(_)
+            | ServerMessage::Map(_)
+            | ServerMessage::MapChunk(_)
+            | ServerMessage::Pong { .. } => {}
+        }
+    }
+}
+
+/// Notices the server has gone quiet, and asks for our player back on a new
+/// connection if we can.
+fn detect_lost_connection(
+    mut commands: Commands,
+    client: Res<NetworkClient>,
+    settings: Res<NetworkSettings>,
+    lobby: Res<Lobby>,
+    current: Res<State<ConnectionState>>,
+    mut state: ResMut<NextState<ConnectionState>>,
+) {
+    if *current.get() != ConnectionState::Connected
+        || client.connection.last_received().elapsed() <= SERVER_TIMEOUT
+    {
+        return;
+    }
+
+    let reconnecting = client.resume.and_then(|resume| {
+        let socket = bind_for(client.server, settings.network)
+            .inspect_err(|e| error!("Can't reconnect to {}: {}", client.server, e))
+            .ok()?;
+        // Should our place be gone, the room we were in is the next best thing.
+        let room = lobby
+            .room
+            .as_ref()
+            .map_or(client.room, |room| RoomRequest::Join(room.code));
+        let mut fresh = NetworkClient::new(socket, client.server, room);
+        fresh.resume = Some(resume);
+        Some(fresh)
+    });
+    match reconnecting {
+        Some(fresh) => {
+            warn!(
+                "Lost connection to {}, trying to get back in",
+                client.server
+            );
+            commands.insert_resource(fresh);
+            state.set(ConnectionState::Reconnecting);
+        }
+        None => {
+            error!("Lost connection to {}", client.server);
+            commands.remove_resource::<NetworkClient>();
+            state.set(ConnectionState::Failed);
+        }
+    }
+}
+
+/// Rebuilds each snapshot from its delta, acknowledges it, and passes it on to
+/// prediction and interpolation.
+fn apply_snapshots(
+    mut received: MessageReader<ServerMessageReceived>,
+    mut client: ResMut<NetworkClient>,
+    mut snapshots: MessageWriter<SnapshotReceived>,
+    mut authoritative: MessageWriter<AuthoritativeState>,
+) {
+    for ServerMessageReceived(message) in received.read() {
+        let ServerMessage::Snapshot(delta) = message else {
+            continue;
+        };
+        let Some(me) = client.client else {
+            continue;
+        };
+        let Some(snapshot) = client.snapshots.decode(delta) else {
+            // Its baseline is gone; the server will fall back to a full state.
+            continue;
+        };
+        client.send(&ClientMessage::SnapshotAck {
+            tick: snapshot.tick,
+        });
+
+        if let Some(state) = snapshot
+            .entities
+            .iter()
+            .find(|entity| entity.kind == EntityKind::Player(me))
+        {
+            authoritative.write(AuthoritativeState {
+                last_input: snapshot.last_input,
+                translation: Vec3::from_array(state.translation),
+                velocity: Vec3::from_array(state.velocity),
+            });
+        }
+
+        snapshots.write(SnapshotReceived(snapshot));
+    }
+}
+
+/// Spawns a stand-in for everything new in each snapshot and despawns whatever
+/// has gone, once interpolation has drawn the last of it. Entities come and
+/// go as they move in and out of the server's view of us as well as when
+/// they're created and destroyed. Our own player is simulated locally, and
+/// props are part of the level everyone loads; `sync_level_props` looks after
+/// those.
+fn sync_remote_entities(
+    mut commands: Commands,
+    mut received: MessageReader<SnapshotReceived>,
+    client: Res<NetworkClient>,
+    mut remote: ResMut<RemoteEntities>,
+    buffer: Res<SnapshotBuffer>,
+    player_assets: Res<RemotePlayerAssets>,
+    snowball_assets: Res<SnowballAssets>,
+) {
+    let Some(me) = client.client else {
+        return;
+    };
+    let mut arrived = HashSet::new();
+    for SnapshotReceived(snapshot) in received.read() {
+        spawn_new_entities(
+            &mut commands,
+            &mut remote,
+            snapshot,
+            me,
+            &player_assets,
+            &snowball_assets,
+        );
+        arrived.extend(snapshot.entities.iter().map(|state| state.id));
+    }
+
+    // Fresh snapshots reach the buffer later this frame.
+    remote.0.retain(|id, entity| {
+        let alive = arrived.contains(id) || buffer.contains(*id);
+        if !alive {
+            commands.entity(*entity).despawn();
+        }
+        alive
+    });
+}
+
+/// Brings the registered components of every entity in each snapshot up to
+/// date. Runs after the stand-ins are spawned, so components that point at
+/// entities new in the same snapshot find them there. Our own player is
+/// simulated locally, but still takes the server's word for these.
+fn apply_replicated_components(
+    mut commands: Commands,
+    mut received: MessageReader<SnapshotReceived>,
+    client: Res<NetworkClient>,
+    remote: Res<RemoteEntities>,
+    local_player: Option<Single<Entity, With<LocalPlayer>>>,
+) {
+    let Some(me) = client.client else {
+        return;
+    };
+    let local_player = local_player.map(|entity| *entity);
+    for SnapshotReceived(snapshot) in received.read() {
+        let updates: Vec<(Entity, Vec<ComponentState>)> = snapshot
+            .entities
+            .iter()
+            .filter_map(|state| {
+                let entity = match state.kind {
+                    EntityKind::Player(client) if client == me => local_player?,
+                    _ => *remote.0.get(&state.id)?,
+                };
+                Some((entity, state.components.clone()))
+            })
+            .collect();
+        commands.queue(move |world: &mut World| {
+            world.resource_scope(|world, registry: Mut<ReplicationRegistry>| {
+                for (entity, components) in updates {
+                    if let Ok(mut entity) = world.get_entity_mut(entity) {
+                        registry.write(&mut entity, &components);
+                    }
+                }
+            });
+        });
+    }
+}
+
+/// Stand-ins start hidden; interpolation shows them when it gets to them.
+fn spawn_new_entities(
+    commands: &mut Commands,
+    remote: &mut RemoteEntities,
+    snapshot: &Snapshot,
+    me: ClientId,
+    player_assets: &RemotePlayerAssets,
+    snowball_assets: &SnowballAssets,
+) {
+    for state in &snapshot.entities {
+        if remote.0.contains_key(&state.id) {
+            continue;
+        }
+        let transform = Transform::from_translation(Vec3::from_array(state.translation))
+            .with_rotation(Quat::from_array(state.rotation));
+
+        let entity = match state.kind {
+            EntityKind::Player(client) if client != me => commands
+                .spawn((
+                    Interpolated(state.id),
+                    transform,
+                    Visibility::Hidden,
+                    children![(
+                        Mesh3d(player_assets.mesh.clone()),
+                        MeshMaterial3d(player_assets.material.clone()),
+                        Transform::from_translation((CAPSULE_START + CAPSULE_END) / 2.),
+                    )],
+                ))
+                .id(),
+            EntityKind::Snowball { .. } => commands
+                .spawn((
+                    Interpolated(state.id),
+                    transform,
+                    Visibility::Hidden,
+                    snowball_assets.visuals(),
+                ))
+                .id(),
+            EntityKind::Player(_) | EntityKind::Prop { .. } => continue,
+        };
+        remote.0.insert(state.id, entity);
+    }
+}
+
+/// Hands the level's props over to the server while they're in its view of
+/// us, so they move as they do there, and back to local physics after.
+fn sync_level_props(
+    mut commands: Commands,
+    mut received: MessageReader<SnapshotReceived>,
+    mut remote: ResMut<RemoteProps>,
+    buffer: Res<SnapshotBuffer>,
+    props: Query<(Entity, &Prop)>,
+) {
+    let mut arrived = HashSet::new();
+    for SnapshotReceived(snapshot) in received.read() {
+        for state in &snapshot.entities {
+            let EntityKind::Prop { index } = state.kind else {
+                continue;
+            };
+            arrived.insert(state.id);
+            if remote.0.contains_key(&state.id) {
+                continue;
+            }
+            // Not there if the level is still being rebuilt for a new map;
+            // the next snapshot will find it.
+            let Some((entity, _)) = props.iter().find(|(_, Prop(prop))| *prop == index) else {
+                continue;
+            };
+            commands
+                .entity(entity)
+                .insert((Interpolated(state.id), RigidBody::KinematicPositionBased));
+            remote.0.insert(state.id, entity);
+        }
+    }
+
+    remote.0.retain(|id, entity| {
+        if !props.contains(*entity) {
+            return false;
+        }
+        let alive = arrived.contains(id) || buffer.contains(*id);
+        if !alive {
+            release_prop(commands.entity(*entity));
+        }
+        alive
+    });
+}
+
+fn release_prop(mut entity: EntityCommands) {
+    entity
+        .remove::<Interpolated>()
+        .insert((RigidBody::Dynamic, Visibility::Inherited));
+}
+
+fn clear_remote_entities(
+    mut commands: Commands,
+    mut remote: ResMut<RemoteEntities>,
+    mut props: ResMut<RemoteProps>,
+    mut buffer: ResMut<SnapshotBuffer>,
+) {
+    for (_, entity) in remote.0.drain() {
+        commands.entity(entity).despawn();
+    }
+    for (_, entity) in props.0.drain() {
+        // Gone with the old level if that's been rebuilt since.
+        if let Ok(entity) = commands.get_entity(entity) {
+            release_prop(entity);
+        }
+    }
+    buffer.clear();
+}
+
+/// Sends this tick's input, along with the last few in case those got lost.
+fn send_input(
+    time: Res<Time<Real>>,
+    mut client: ResMut<NetworkClient>,
+    buffer: Res<InputBuffer>,
+    snapshots: Res<SnapshotBuffer>,
+    clock: Res<ServerClock>,
+    mut player: Single<&mut PlayerInput, With<LocalPlayer>>,
+) {
+    // Before there's anything on screen there's nothing to have aimed at,
+    // so the server might as well judge by the present.
+    let view_tick = snapshots
+        .render_tick()
+        .or_else(|| clock.server_tick(time.elapsed_secs_f64()))
+        .map_or(0, |tick| tick as u32);
+    for input in buffer.recent(INPUT_REDUNDANCY) {
+        client.send(&ClientMessage::Input(
+            input.input.to_command(input.sequence, view_tick),
+        ));
+    }
+
+    // Snowballs are the server's; ours will come back in a snapshot.
+    player.throw = false;
+}
+
+fn disconnect_on_exit(
+    mut exit: MessageReader<AppExit>,
+    mut client: ResMut<NetworkClient>,
+    state: Res<State<ConnectionState>>,
+) {
+    if exit.read().next().is_some() && *state.get() == ConnectionState::Connected {
+        client.send(&ClientMessage::Disconnect);
+    }
+}
+
+fn flush_packets(mut client: ResMut<NetworkClient>) {
+    let client = &mut *client;
+    for packet in client.connection.flush(Instant::now()) {
+        let packet = ClientPacket {
+            session: client.session,
+            packet,
+        };
+        let bytes = match protocol::encode(&packet) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!("Not sending packet: {}", e);
+                continue;
+            }
+        };
+
+        match client.socket.send_to(&bytes, client.server) {
+            Ok(len) => client.bytes_sent += len as u64,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => error!("Sending to {}: {}", client.server, e),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use protocol::{ClientMessage, ServerMessage};
+
+use crate::game::network::client::{ConnectionState, NetworkClient, ServerMessageReceived};
+
+/// How often to ping the server while connected.
+const PING_INTERVAL: Duration = Duration::from_millis(500);
+
+/// If a pong puts the server this far (in seconds) from where we thought it
+/// was, believe it outright instead of easing towards it.
+const CLOCK_SNAP: f64 = 0.25;
+
+/// Pings the server to keep track of the round trip and of what tick the
+/// server is on right now.
+pub struct ClockPlugin;
+
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerClock>()
+            .add_systems(
+                Update,
+                (track_server_clock, send_ping)
+                    .chain()
+                    .run_if(resource_exists::<NetworkClient>),
+            )
+            .add_systems(OnExit(ConnectionState::Connected), reset_clock);
+    }
+}
+
+/// Round trip time, jitter and the server's clock, as seen from here. Times
+/// are in seconds; local ones on the `Time<Real>` clock, server ones counted
+/// from the server's tick 0.
+#[derive(Resource)]
+pub struct ServerClock {
+    tick_rate: f64,
+    rtt: Option<f64>,
+    /// Smoothed deviation of round trips from `rtt`.
+    jitter: f64,
+    /// Server time minus local time.
+    offset: Option<f64>,
+    last_ping: Option<Instant>,
+}
+
+impl Default for ServerClock {
+    fn default() -> Self {
+        Self {
+            tick_rate: 60.0,
+            rtt: None,
+            jitter: 0.0,
+            offset: None,
+            last_ping: None,
+        }
+    }
+}
+
+impl ServerClock {
+    /// Smoothed round trip time, once a pong has come back.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt.map(Duration::from_secs_f64)
+    }
+
+    pub fn jitter(&self) -> Duration {
+        Duration::from_secs_f64(self.jitter)
+    }
+
+    /// Where the server is at local time `now`.
+    pub fn server_time(&self, now: f64) -> Option<f64> {
+        Some(now + self.offset?)
+    }
+
+    /// `server_time` in ticks, with the fraction of the way to the next.
+    pub fn server_tick(&self, now: f64) -> Option<f64> {
+        Some(self.server_time(now)? * self.tick_rate)
+    }
+
+    /// Server time of the newest snapshot that can have reached us by `now`.
+    pub fn arrival_time(&self, now: f64) -> Option<f64> {
+        Some(self.server_time(now)? - self.rtt? / 2.0)
+    }
+
+    /// Takes in one round trip. The pong left the server at `server_time` and
+    /// got here at local time `received`.
+    fn sample(&mut self, rtt: f64, server_time: f64, received: f64) {
+        let fast = self.rtt.is_none_or(|smoothed| rtt <= smoothed);
+        match self.rtt {
+            Some(smoothed) => {
+                self.jitter += ((rtt - smoothed).abs() - self.jitter) / 16.0;
+                self.rtt = Some(smoothed + (rtt - smoothed) / 8.0);
+            }
+            None => self.rtt = Some(rtt),
+        }
+
+        // Assume the trip was as long both ways. Slow trips are more likely
+        // to have been held up in one direction only, so count for less.
+        let offset = server_time + rtt / 2.0 - received;
+        self.offset = Some(match self.offset {
+            Some(current) if (offset - current).abs() < CLOCK_SNAP => {
+                let weight = if fast { 1.0 / 8.0 } else { 1.0 / 32.0 };
+                current + (offset - current) * weight
+            }
+            _ => offset,
+        });
+    }
+}
+
+fn track_server_clock(
+    time: Res<Time<Real>>,
+    mut received: MessageReader<ServerMessageReceived>,
+    mut clock: ResMut<ServerClock>,
+) {
+    let now = Instant::now();
+    for ServerMessageReceived(message) in received.read() {
+        match message {
+            ServerMessage::ConnectAccepted { tick_rate, .. } => {
+                clock.tick_rate = f64::from(*tick_rate);
+            }
+            ServerMessage::Pong { sent, held, tick } => {
+                let round_trip = client_clock(&time, now).wrapping_sub(*sent);
+                let rtt = f64::from(round_trip.saturating_sub(*held)) / 1_000_000.0;
+                let server_time = f64::from(*tick) / clock.tick_rate;
+                let received = now.duration_since(time.startup()).as_secs_f64();
+                clock.sample(rtt, server_time, received);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn send_ping(
+    time: Res<Time<Real>>,
+    state: Res<State<ConnectionState>>,
+    mut clock: ResMut<ServerClock>,
+    mut client: ResMut<NetworkClient>,
+) {
+    if *state.get() != ConnectionState::Connected {
+        return;
+    }
+    let now = Instant::now();
+    if clock
+        .last_ping
+        .is_some_and(|last| now.duration_since(last) < PING_INTERVAL)
+    {
+        return;
+    }
+    clock.last_ping = Some(now);
+    client.send(&ClientMessage::Ping {
+        sent: client_clock(&time, now),
+    });
+}
+
+/// Microseconds since startup, wrapping. Only ever compared with itself.
+fn client_clock(time: &Time<Real>, now: Instant) -> u32 {
+    now.duration_since(time.startup()).as_micros() as u32
+}
+
+fn reset_clock(mut clock: ResMut<ServerClock>) {
+    *clock = ServerClock::default();
+}
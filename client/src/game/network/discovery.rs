@@ -0,0 +1,69 @@
+use std::time::Instant;
+
+use bevy::prelude::*;
+use protocol::discovery::{DISCOVERY_PORT, DiscoveredServer, Discovery};
+
+use crate::game::network::client::ConnectionState;
+
+/// Looks for servers on the local network whenever we're not in a match.
+pub struct DiscoveryPlugin;
+
+impl Plugin for DiscoveryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, start_discovery).add_systems(
+            Update,
+            discover_servers.run_if(
+                resource_exists::<DiscoveredServers>.and(not(in_state(ConnectionState::Connected))),
+            ),
+        );
+    }
+}
+
+/// Servers that answered our recent discovery queries.
+#[derive(Resource)]
+pub struct DiscoveredServers {
+    discovery: Discovery,
+}
+
+impl DiscoveredServers {
+    pub fn servers(&self) -> impl Iterator<Item = &DiscoveredServer> {
+        self.discovery.servers()
+    }
+}
+
+fn start_discovery(mut commands: Commands) {
+    match Discovery::new(Discovery::lan_targets(DISCOVERY_PORT)) {
+        Ok(discovery) => commands.insert_resource(DiscoveredServers { discovery }),
+        Err(e) => warn!("Can't look for servers on the local network: {}", e),
+    }
+}
+
+fn discover_servers(mut discovered: ResMut<DiscoveredServers>) {
+    let before = discovered.servers().count();
+    let found = match discovered.discovery.poll(Instant::now()) {
+        Ok(found) => found,
+        Err(e) => {
+            warn!("Looking for servers on the local network: {}", e);
+            return;
+        }
+    };
+
+    for server in &found {
+        let note = match server.is_compatible() {
+            true => "",
+            false => ", needs a different version",
+        };
+        info!(
+            "Found {:?} at {} playing {}, {}/{} players{}",
+            server.name, server.addr, server.map, server.players, server.max_players, note
+        );
+    }
+    let lost = (before + found.len()).saturating_sub(discovered.servers().count());
+    if lost > 0 {
+        info!(
+            "{} server(s) stopped answering, {} left on the local network",
+            lost,
+            discovered.servers().count()
+        );
+    }
+}
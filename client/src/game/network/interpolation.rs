@@ -0,0 +1,296 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+use protocol::{EntityState, NetEntity, Snapshot};
+
+use crate::game::network::clock::ServerClock;
+
+/// Snapshots kept around. Only the ones either side of the render time are
+/// used; the rest cover bursts.
+const MAX_BUFFERED_SNAPSHOTS: usize = 32;
+
+/// Never draw remote entities closer to the present than this...
+const MIN_DELAY: f64 = 0.05;
+/// ...or further in the past than this.
+const MAX_DELAY: f64 = 0.3;
+
+/// Snapshots we'd like to have buffered ahead of the render time.
+const DELAY_SNAPSHOTS: f64 = 2.0;
+
+/// How many jitters of extra delay to add on top.
+const JITTER_MARGIN: f64 = 3.0;
+
+/// How far past the newest snapshot we're willing to guess.
+const MAX_EXTRAPOLATION: f64 = 0.25;
+
+/// If the render clock drifts further than this from where it should be, jump.
+const CLOCK_SNAP: f64 = 0.25;
+
+/// The most the render clock speeds up or slows down to catch up.
+const CLOCK_ADJUST: f64 = 0.05;
+
+pub struct InterpolationPlugin;
+
+impl Plugin for InterpolationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SnapshotBuffer>()
+            .add_message::<SnapshotReceived>()
+            .add_systems(Update, (buffer_snapshots, interpolate).chain());
+    }
+}
+
+/// A snapshot fresh off the wire.
+#[derive(Message, Clone, Debug)]
+pub struct SnapshotReceived(pub Snapshot);
+
+/// A remote entity, drawn where the server had it a short while ago.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Interpolated(pub NetEntity);
+
+struct BufferedSnapshot {
+    /// Server time in seconds.
+    time: f64,
+    entities: HashMap<NetEntity, Sample>,
+}
+
+/// An entity as one snapshot had it.
+struct Sample {
+    state: EntityState,
+    /// Server time the entity got to this state. Far away entities aren't
+    /// updated every tick, and are held as they were in between.
+    since: f64,
+}
+
+/// Recent snapshots and the clock remote entities are drawn at.
+#[derive(Resource)]
+pub struct SnapshotBuffer {
+    /// Server tick rate, for turning ticks into seconds.
+    pub tick_rate: f64,
+    snapshots: VecDeque<BufferedSnapshot>,
+    /// Smoothed difference between server time and our real time.
+    clock_offset: Option<f64>,
+    last_arrival: Option<(f64, f64)>,
+    /// Smoothed deviation of snapshot arrivals from their send spacing.
+    jitter: f64,
+    render_time: Option<f64>,
+}
+
+impl Default for SnapshotBuffer {
+    fn default() -> Self {
+        Self {
+            tick_rate: 60.0,
+            snapshots: VecDeque::new(),
+            clock_offset: None,
+            last_arrival: None,
+            jitter: 0.0,
+            render_time: None,
+        }
+    }
+}
+
+impl SnapshotBuffer {
+    /// How far behind the server remote entities are currently drawn.
+    pub fn delay(&self) -> f64 {
+        (DELAY_SNAPSHOTS / self.tick_rate + JITTER_MARGIN * self.jitter).clamp(MIN_DELAY, MAX_DELAY)
+    }
+
+    /// The server tick remote entities are being drawn at, with the fraction
+    /// of the way to the next.
+    pub fn render_tick(&self) -> Option<f64> {
+        Some(self.render_time? * self.tick_rate)
+    }
+
+    /// Snapshots waiting to be drawn, ahead of the render time.
+    pub fn buffered(&self) -> usize {
+        match self.render_time {
+            Some(render_time) => self
+                .snapshots
+                .iter()
+                .filter(|snapshot| snapshot.time > render_time)
+                .count(),
+            None => self.snapshots.len(),
+        }
+    }
+
+    /// Forgets everything, for when the connection goes away.
+    pub fn clear(&mut self) {
+        *self = Self {
+            tick_rate: self.tick_rate,
+            ..default()
+        };
+    }
+
+    fn push(&mut self, snapshot: Snapshot, now: f64) {
+        let time = snapshot.tick as f64 / self.tick_rate;
+
+        if let Some((last_time, last_arrival)) = self.last_arrival
+            && time > last_time
+        {
+            let deviation = ((now - last_arrival) - (time - last_time)).abs();
+            self.jitter += (deviation - self.jitter) / 16.0;
+        }
+        self.last_arrival = Some((time, now));
+
+        // Track the earliest-arriving packets; late ones are what the delay is for.
+        let offset = time - now;
+        self.clock_offset = Some(match self.clock_offset {
+            Some(current) if offset < current => current + (offset - current) / 16.0,
+            Some(current) => current + (offset - current) / 4.0,
+            None => offset,
+        });
+
+        // Out of order arrivals slot into place; duplicates are dropped.
+        let index = self
+            .snapshots
+            .partition_point(|buffered| buffered.time < time);
+        if self
+            .snapshots
+            .get(index)
+            .is_some_and(|buffered| buffered.time == time)
+        {
+            return;
+        }
+        let previous = index.checked_sub(1).map(|index| &self.snapshots[index]);
+        let entities = snapshot
+            .entities
+            .into_iter()
+            .map(|state| {
+                let since = previous
+                    .and_then(|previous| previous.entities.get(&state.id))
+                    .filter(|held| held.state == state)
+                    .map_or(time, |held| held.since);
+                (state.id, Sample { state, since })
+            })
+            .collect();
+        self.snapshots
+            .insert(index, BufferedSnapshot { time, entities });
+
+        if self.snapshots.len() > MAX_BUFFERED_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Moves the render clock on by `delta` seconds of real time, nudging it
+    /// towards the newest server time we can have heard of, less the delay.
+    /// That's `arrival`, from the synced server clock, if it's settled yet;
+    /// otherwise it's estimated from when snapshots turn up.
+    fn advance(&mut self, now: f64, delta: f64, arrival: Option<f64>) -> Option<f64> {
+        let arrival = arrival.or(self.clock_offset.map(|offset| now + offset))?;
+        let target = arrival - self.delay();
+
+        let render_time = match self.render_time {
+            Some(render_time) if (target - render_time).abs() < CLOCK_SNAP => {
+                let rate = ((target - render_time) / CLOCK_SNAP).clamp(-1.0, 1.0) * CLOCK_ADJUST;
+                render_time + delta * (1.0 + rate)
+            }
+            _ => target,
+        };
+        self.render_time = Some(render_time);
+
+        // Keep one snapshot at or before the render time to interpolate from.
+        while self.snapshots.len() > 2 && self.snapshots[1].time <= render_time {
+            self.snapshots.pop_front();
+        }
+        Some(render_time)
+    }
+
+    /// Whether any buffered snapshot has `id`, so it may yet be drawn.
+    pub fn contains(&self, id: NetEntity) -> bool {
+        self.snapshots
+            .iter()
+            .any(|snapshot| snapshot.entities.contains_key(&id))
+    }
+
+    /// Where `id` was at `time`, blending the updates either side of it.
+    /// `None` if it didn't exist then, having not arrived yet or already gone.
+    fn sample(&self, id: NetEntity, time: f64) -> Option<(Vec3, Quat)> {
+        let next = self
+            .snapshots
+            .partition_point(|snapshot| snapshot.time <= time);
+
+        if let Some(to) = self.snapshots.get(next) {
+            let to_state = to.entities.get(&id)?;
+            let Some(from) = next.checked_sub(1).map(|index| &self.snapshots[index]) else {
+                // Render time is before anything we have; hold the oldest.
+                return Some(pose(&to_state.state));
+            };
+            let Some(from) = from.entities.get(&id) else {
+                // Just spawned.
+                return Some(pose(&to_state.state));
+            };
+
+            // The next update may be a few snapshots off, if it's far away.
+            let update = self
+                .snapshots
+                .range(next..)
+                .map_while(|snapshot| snapshot.entities.get(&id))
+                .find(|sample| sample.state != from.state);
+            let Some(update) = update else {
+                // Hasn't moved since.
+                return Some(pose(&from.state));
+            };
+
+            let t = ((time - from.since) / (update.since - from.since)) as f32;
+            let (from_translation, from_rotation) = pose(&from.state);
+            let (to_translation, to_rotation) = pose(&update.state);
+            return Some((
+                from_translation.lerp(to_translation, t),
+                from_rotation.slerp(to_rotation, t),
+            ));
+        }
+
+        // We've run out of snapshots; carry on along the last known velocity for a bit.
+        let newest = self.snapshots.back()?;
+        let state = &newest.entities.get(&id)?.state;
+        let ahead = (time - newest.time).min(MAX_EXTRAPOLATION) as f32;
+        let (translation, rotation) = pose(state);
+        Some((
+            translation + Vec3::from_array(state.velocity) * ahead,
+            rotation,
+        ))
+    }
+}
+
+fn pose(state: &EntityState) -> (Vec3, Quat) {
+    (
+        Vec3::from_array(state.translation),
+        Quat::from_array(state.rotation).normalize(),
+    )
+}
+
+fn buffer_snapshots(
+    time: Res<Time<Real>>,
+    mut received: MessageReader<SnapshotReceived>,
+    mut buffer: ResMut<SnapshotBuffer>,
+) {
+    let now = time.elapsed_secs_f64();
+    for SnapshotReceived(snapshot) in received.read() {
+        buffer.push(snapshot.clone(), now);
+    }
+}
+
+fn interpolate(
+    time: Res<Time<Real>>,
+    clock: Option<Res<ServerClock>>,
+    mut buffer: ResMut<SnapshotBuffer>,
+    mut entities: Query<(&Interpolated, &mut Transform, &mut Visibility)>,
+) {
+    let now = time.elapsed_secs_f64();
+    let arrival = clock.and_then(|clock| clock.arrival_time(now));
+    let Some(render_time) = buffer.advance(now, time.delta_secs_f64(), arrival) else {
+        return;
+    };
+
+    for (Interpolated(id), mut transform, mut visibility) in &mut entities {
+        // Entities spawn as soon as they're heard of, and linger until every
+        // snapshot with them is gone, but only show while the render time is
+        // somewhere they exist.
+        let Some((translation, rotation)) = buffer.sample(*id, render_time) else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+        transform.translation = translation;
+        transform.rotation = rotation;
+        visibility.set_if_neq(Visibility::Inherited);
+    }
+}
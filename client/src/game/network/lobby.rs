@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+use protocol::{ClientMessage, RoomState, ServerMessage};
+
+use crate::game::network::client::{ConnectionState, NetworkClient, ServerMessageReceived};
+
+/// Keeps track of the room we're in, and lets players ready up (R) and the
+/// host start the match (Enter) while it's still a lobby.
+pub struct LobbyPlugin;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Lobby>()
+            .add_systems(
+                Update,
+                (track_room, lobby_input)
+                    .chain()
+                    .run_if(in_state(ConnectionState::Connected)),
+            )
+            .add_systems(OnExit(ConnectionState::Connected), leave_lobby);
+    }
+}
+
+/// The room we're in, as the server last described it.
+#[derive(Resource, Default)]
+pub struct Lobby {
+    pub room: Option<RoomState>,
+    /// What we last told the server.
+    pub ready: bool,
+}
+
+fn track_room(mut received: MessageReader<ServerMessageReceived>, mut lobby: ResMut<Lobby>) {
+    for ServerMessageReceived(message) in received.read() {
+        let ServerMessage::RoomState(room) = message else {
+            continue;
+        };
+
+        match &lobby.room {
+            None => info!("In room {}, share the code to invite friends", room.code),
+            Some(old) if room.started && !old.started => info!("The match has started"),
+            Some(_) => {}
+        }
+        if !room.started {
+            let names: Vec<String> = room
+                .players
+                .iter()
+                .map(|player| match player.ready {
+                    true => format!("{} (ready)", player.name),
+                    false => player.name.clone(),
+                })
+                .collect();
+            info!("Lobby: {}", names.join(", "));
+        }
+        lobby.room = Some(room.clone());
+    }
+}
+
+fn lobby_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut lobby: ResMut<Lobby>,
+    mut client: ResMut<NetworkClient>,
+) {
+    let Some(host) = lobby
+        .room
+        .as_ref()
+        .filter(|room| !room.started)
+        .map(|room| room.host)
+    else {
+        return;
+    };
+
+    if keys.just_pressed(KeyCode::KeyR) {
+        lobby.ready = !lobby.ready;
+        client.send(&ClientMessage::SetReady { ready: lobby.ready });
+    }
+    if keys.just_pressed(KeyCode::Enter) && host.is_some() && host == client.client_id() {
+        client.send(&ClientMessage::StartMatch);
+    }
+}
+
+fn leave_lobby(mut lobby: ResMut<Lobby>) {
+    *lobby = Lobby::default();
+}
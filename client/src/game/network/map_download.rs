@@ -0,0 +1,207 @@
+//! Gets the map the server's room is running. Built in maps and ones
+//! downloaded before are used straight away; anything else is asked for, put
+//! back together from the chunks the server sends, checked against its hash
+//! and kept in the cache for next time.
+
+use std::{
+    env, fs, io, mem,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use protocol::{ClientMessage, MapChunk, MapHash, MapInfo, ServerMessage, map::MAX_MAP_SIZE};
+use simulation::level::{self, Map};
+
+use crate::game::network::client::{ConnectionState, NetworkClient, ServerMessageReceived};
+
+pub struct MapDownloadPlugin {
+    /// Where downloaded maps are kept.
+    pub cache: PathBuf,
+}
+
+impl Plugin for MapDownloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MapCache(self.cache.clone()))
+            .init_resource::<MapDownload>()
+            .add_systems(
+                Update,
+                receive_map.run_if(in_state(ConnectionState::Connected)),
+            )
+            .add_systems(OnExit(ConnectionState::Connected), cancel_download);
+    }
+}
+
+#[derive(Resource)]
+struct MapCache(PathBuf);
+
+/// The map we're downloading, if any.
+#[derive(Resource, Default)]
+pub struct MapDownload {
+    pending: Option<MapInfo>,
+    received: Vec<u8>,
+}
+
+impl MapDownload {
+    /// The name of the map being downloaded and how much of it has arrived,
+    /// from 0 to 1.
+    pub fn progress(&self) -> Option<(&str, f32)> {
+        let info = self.pending.as_ref()?;
+        let done = self.received.len() as f32 / info.size.max(1) as f32;
+        Some((&info.name, done))
+    }
+}
+
+/// Where downloaded maps are kept unless `--map-cache` says otherwise.
+pub fn default_cache_dir() -> PathBuf {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")));
+    match base {
+        Some(base) => base.join("snowball").join("maps"),
+        None => PathBuf::from("map-cache"),
+    }
+}
+
+fn receive_map(
+    mut commands: Commands,
+    mut received: MessageReader<ServerMessageReceived>,
+    mut client: ResMut<NetworkClient>,
+    mut download: ResMut<MapDownload>,
+    cache: Res<MapCache>,
+    current: Res<Map>,
+    mut state: ResMut<NextState<ConnectionState>>,
+) {
+    for ServerMessageReceived(message) in received.read() {
+        let result = match message {
+            ServerMessage::Map(info) => start(&mut client, &mut download, &cache.0, info),
+            ServerMessage::MapChunk(chunk) => add_chunk(&mut download, &cache.0, chunk),
+            _ => continue,
+        };
+        match result {
+            Ok(Some(map)) => {
+                // Rebuilding the level resets it, so only when it's different.
+                if map != *current {
+                    commands.insert_resource(map);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Can't load the server's map: {}", e);
+                download.pending = None;
+                commands.remove_resource::<NetworkClient>();
+                state.set(ConnectionState::Failed);
+                return;
+            }
+        }
+    }
+}
+
+/// Handles the server saying which map it's running: returns it if we have
+/// it, and otherwise asks for it.
+fn start(
+    client: &mut NetworkClient,
+    download: &mut MapDownload,
+    cache: &Path,
+    info: &MapInfo,
+) -> Result<Option<Map>, String> {
+    download.pending = None;
+    download.received.clear();
+
+    if let Some(map) = find(cache, info) {
+        return Ok(Some(map));
+    }
+    if info.size as usize > MAX_MAP_SIZE {
+        return Err(format!(
+            "{} is {} bytes, more than the {} we'll download",
+            info.name, info.size, MAX_MAP_SIZE
+        ));
+    }
+
+    info!("Downloading {} ({} bytes)", info.name, info.size);
+    client.send(&ClientMessage::RequestMap { hash: info.hash });
+    download.pending = Some(info.clone());
+    Ok(None)
+}
+
+/// Adds a piece of the map being downloaded, returning it once it's all here.
+fn add_chunk(
+    download: &mut MapDownload,
+    cache: &Path,
+    chunk: &MapChunk,
+) -> Result<Option<Map>, String> {
+    // Left over from a map the room has since moved on from.
+    let Some(info) = download
+        .pending
+        .clone()
+        .filter(|info| info.hash == chunk.hash)
+    else {
+        return Ok(None);
+    };
+    let end = download.received.len() + chunk.data.len();
+    if chunk.offset as usize != download.received.len() || end > info.size as usize {
+        return Err(format!("pieces of {} arrived out of place", info.name));
+    }
+    download.received.extend_from_slice(&chunk.data);
+    if end < info.size as usize {
+        return Ok(None);
+    }
+
+    download.pending = None;
+    let bytes = mem::take(&mut download.received);
+    if MapHash::of(&bytes) != info.hash {
+        return Err(format!("{} arrived damaged", info.name));
+    }
+    let map = decode(&bytes).map_err(|e| format!("{}: {}", info.name, e))?;
+    if let Err(e) = save(cache, info.hash, &bytes) {
+        warn!("Can't cache {} in {}: {}", info.name, cache.display(), e);
+    }
+    info!("Downloaded {}", info.name);
+    Ok(Some(map))
+}
+
+/// The map the server described, if it's built in or in the cache.
+fn find(cache: &Path, info: &MapInfo) -> Option<Map> {
+    if let Some(map) = level::builtin(&info.name)
+        && map
+            .to_bytes()
+            .is_ok_and(|bytes| MapHash::of(&bytes) == info.hash)
+    {
+        return Some(map);
+    }
+
+    let path = cache_path(cache, info.hash);
+    let bytes = fs::read(&path).ok()?;
+    if MapHash::of(&bytes) != info.hash {
+        warn!("Ignoring {}, it doesn't match its hash", path.display());
+        return None;
+    }
+    decode(&bytes)
+        .inspect_err(|e| warn!("Ignoring {}: {}", path.display(), e))
+        .ok()
+}
+
+fn decode(bytes: &[u8]) -> Result<Map, String> {
+    let map: Map = protocol::decode_message(bytes).map_err(|e| e.to_string())?;
+    map.validate()?;
+    Ok(map)
+}
+
+fn cache_path(cache: &Path, hash: MapHash) -> PathBuf {
+    cache.join(format!("{hash}.map"))
+}
+
+fn save(cache: &Path, hash: MapHash, bytes: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(cache)?;
+    // Written aside and moved into place, so a crash can't leave half a map
+    // under the real name.
+    let path = cache_path(cache, hash);
+    let part = path.with_extension("part");
+    fs::write(&part, bytes)?;
+    fs::rename(&part, &path)
+}
+
+fn cancel_download(mut download: ResMut<MapDownload>) {
+    download.pending = None;
+    download.received.clear();
+}
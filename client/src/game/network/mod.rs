@@ -0,0 +1,8 @@
+pub mod client;
+pub mod clock;
+pub mod discovery;
+pub mod interpolation;
+pub mod lobby;
+pub mod map_download;
+pub mod prediction;
+pub mod stats;
\ No newline at end of file
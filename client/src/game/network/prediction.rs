@@ -0,0 +1,191 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::KinematicCharacterControllerOutput;
+use simulation::{
+    SimulationSystems,
+    player::{Player, PlayerInput, step_player},
+};
+
+use crate::game::player::{
+    camera_controller::CameraController, player::LocalPlayer, player_movement::apply_local_input,
+};
+
+/// About four seconds of inputs at 60Hz. Anything older than this the server
+/// has either acknowledged or is never going to.
+const MAX_BUFFERED_INPUTS: usize = 256;
+
+/// Errors smaller than this are left alone; the server and client never agree
+/// to the last bit.
+const CORRECTION_THRESHOLD: f32 = 0.05;
+
+/// Errors bigger than this (respawns, teleports) snap instead of smoothing.
+const SNAP_DISTANCE: f32 = 5.0;
+
+/// How quickly a smoothed correction fades, per second.
+const CORRECTION_DECAY: f32 = 10.0;
+
+pub struct PredictionPlugin;
+
+impl Plugin for PredictionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InputBuffer>()
+            .init_resource::<Correction>()
+            .add_message::<AuthoritativeState>()
+            .add_systems(
+                FixedUpdate,
+                record_input
+                    .after(apply_local_input)
+                    .before(SimulationSystems),
+            )
+            .add_systems(Update, (reconcile, smooth_correction).chain());
+    }
+}
+
+/// The server's view of our own player, as of the newest input it had applied.
+#[derive(Message, Clone, Copy, Debug)]
+pub struct AuthoritativeState {
+    pub last_input: u32,
+    pub translation: Vec3,
+    pub velocity: Vec3,
+}
+
+/// One tick of local input, along with the state the player was in just
+/// before it was applied.
+#[derive(Clone, Copy, Debug)]
+pub struct BufferedInput {
+    pub sequence: u32,
+    pub input: PlayerInput,
+    pub grounded: bool,
+    pub translation: Vec3,
+    pub velocity: Vec3,
+}
+
+/// Inputs the server hasn't acknowledged yet, oldest first.
+#[derive(Resource, Default)]
+pub struct InputBuffer {
+    next_sequence: u32,
+    inputs: VecDeque<BufferedInput>,
+}
+
+impl InputBuffer {
+    /// The newest `count` inputs, oldest first.
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &BufferedInput> {
+        self.inputs.iter().skip(self.inputs.len().saturating_sub(count))
+    }
+
+    fn push(&mut self, input: BufferedInput) {
+        if self.inputs.len() == MAX_BUFFERED_INPUTS {
+            self.inputs.pop_front();
+        }
+        self.inputs.push_back(input);
+    }
+
+    /// Forgets every input up to and including `sequence`.
+    fn acknowledge(&mut self, sequence: u32) {
+        while self
+            .inputs
+            .front()
+            .is_some_and(|input| input.sequence <= sequence)
+        {
+            self.inputs.pop_front();
+        }
+    }
+}
+
+/// Visual offset left over from the last correction. The simulated player
+/// jumps straight to the corrected position; the camera catches up gradually.
+#[derive(Resource, Default)]
+pub struct Correction {
+    pub offset: Vec3,
+    /// Corrections applied since startup.
+    pub count: u32,
+}
+
+/// Numbers this tick's input and remembers it for replay.
+pub fn record_input(
+    mut buffer: ResMut<InputBuffer>,
+    player: Single<
+        (
+            &PlayerInput,
+            &Player,
+            &Transform,
+            Option<&KinematicCharacterControllerOutput>,
+        ),
+        With<LocalPlayer>,
+    >,
+) {
+    let (input, player, transform, output) = player.into_inner();
+
+    // Sequence 0 means "nothing applied yet" on the server.
+    buffer.next_sequence += 1;
+    let sequence = buffer.next_sequence;
+    buffer.push(BufferedInput {
+        sequence,
+        input: *input,
+        grounded: output.is_some_and(|output| output.grounded),
+        translation: transform.translation,
+        velocity: player.velocity,
+    });
+}
+
+/// Compares the server's state with what we predicted for the same input, and
+/// if they disagree, rewinds to the server's state and replays everything since.
+fn reconcile(
+    mut states: MessageReader<AuthoritativeState>,
+    mut buffer: ResMut<InputBuffer>,
+    mut correction: ResMut<Correction>,
+    time: Res<Time<Fixed>>,
+    player: Single<(&mut Player, &mut Transform), With<LocalPlayer>>,
+) {
+    // Only the newest state matters.
+    let Some(state) = states.read().last().copied() else {
+        return;
+    };
+    let (mut player, mut transform) = player.into_inner();
+
+    buffer.acknowledge(state.last_input);
+
+    // What we predicted right after the acknowledged input is the state
+    // recorded just before the next one.
+    let predicted = buffer
+        .inputs
+        .front()
+        .map_or(transform.translation, |input| input.translation);
+    let error = state.translation - predicted;
+    if error.length() < CORRECTION_THRESHOLD {
+        return;
+    }
+
+    let dt = time.timestep().as_secs_f32();
+    let mut translation = state.translation;
+    player.velocity = state.velocity;
+    for input in buffer.inputs.iter_mut() {
+        input.translation = translation;
+        input.velocity = player.velocity;
+        // Replays skip collisions; the character controller resolves any
+        // overlap on the next tick.
+        translation += step_player(&mut player, &input.input, input.grounded, dt);
+    }
+
+    let jump = translation - transform.translation;
+    correction.offset = if jump.length() > SNAP_DISTANCE {
+        Vec3::ZERO
+    } else {
+        correction.offset - jump
+    };
+    correction.count += 1;
+    transform.translation = translation;
+}
+
+fn smooth_correction(
+    time: Res<Time>,
+    mut correction: ResMut<Correction>,
+    mut camera: Single<&mut Transform, With<CameraController>>,
+) {
+    correction.offset *= (-CORRECTION_DECAY * time.delta_secs()).exp();
+    if correction.offset.length_squared() < 1e-6 {
+        correction.offset = Vec3::ZERO;
+    }
+    camera.translation = correction.offset;
+}
@@ -0,0 +1,174 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use protocol::ConnectionStats;
+
+use crate::game::network::{
+    client::{ConnectionState, NetworkClient},
+    clock::ServerClock,
+    interpolation::SnapshotBuffer,
+    prediction::Correction,
+};
+
+/// How often the numbers are brought up to date and the graph moves along.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Rates are averaged over this many samples, one second's worth.
+const RATE_SAMPLES: usize = 4;
+
+/// Samples the graph keeps, thirty seconds' worth.
+pub const GRAPH_SAMPLES: usize = 120;
+
+/// Keeps connection health figures up to date for the debug view.
+pub struct NetworkStatsPlugin;
+
+impl Plugin for NetworkStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetworkStats>()
+            .add_systems(
+                Last,
+                sample_network_stats.run_if(
+                    resource_exists::<NetworkClient>.and(in_state(ConnectionState::Connected)),
+                ),
+            )
+            .add_systems(OnExit(ConnectionState::Connected), reset_network_stats);
+    }
+}
+
+/// One point on the graph.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StatsSample {
+    pub rtt: Duration,
+    /// Fraction of packets lost either way since the sample before.
+    pub loss: f32,
+}
+
+/// How the connection is doing, as of the latest sample.
+#[derive(Resource, Default)]
+pub struct NetworkStats {
+    pub rtt: Option<Duration>,
+    pub jitter: Duration,
+    /// Fractions of packets lost over the last second.
+    pub loss_in: f32,
+    pub loss_out: f32,
+    /// Bytes per second, averaged over the last second.
+    pub bytes_in: f32,
+    pub bytes_out: f32,
+    pub buffered_snapshots: usize,
+    /// Prediction corrections since startup.
+    pub corrections: u32,
+    /// Oldest first.
+    pub history: VecDeque<StatsSample>,
+    totals: VecDeque<Totals>,
+}
+
+/// Everything counted since connecting, at one moment.
+#[derive(Clone, Copy)]
+struct Totals {
+    at: Instant,
+    connection: ConnectionStats,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+impl NetworkStats {
+    fn push(&mut self, totals: Totals) {
+        if self.totals.len() > RATE_SAMPLES {
+            self.totals.pop_front();
+        }
+        self.totals.push_back(totals);
+    }
+
+    fn update_rates(&mut self) {
+        let (Some(first), Some(last)) = (self.totals.front(), self.totals.back()) else {
+            return;
+        };
+        let seconds = last.at.duration_since(first.at).as_secs_f32();
+        if seconds <= 0.0 {
+            return;
+        }
+        let (loss_in, loss_out) = loss(&first.connection, &last.connection);
+        self.loss_in = loss_in;
+        self.loss_out = loss_out;
+        self.bytes_in = (last.bytes_received - first.bytes_received) as f32 / seconds;
+        self.bytes_out = (last.bytes_sent - first.bytes_sent) as f32 / seconds;
+    }
+
+    /// Loss between the two newest samples, for the graph.
+    fn latest_loss(&self) -> f32 {
+        let mut newest = self.totals.iter().rev();
+        match (newest.next(), newest.next()) {
+            (Some(last), Some(before)) => {
+                let (loss_in, loss_out) = loss(&before.connection, &last.connection);
+                loss_in.max(loss_out)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// Fractions of packets lost coming in and going out between two sets of totals.
+fn loss(before: &ConnectionStats, after: &ConnectionStats) -> (f32, f32) {
+    let expected = after.packets_expected - before.packets_expected;
+    let received = after.packets_received - before.packets_received;
+    // Late arrivals can make up for losses counted earlier.
+    let loss_in = fraction(expected.saturating_sub(received), expected);
+
+    let resolved = after.packets_resolved - before.packets_resolved;
+    let lost = after.packets_lost - before.packets_lost;
+    (loss_in, fraction(lost, resolved))
+}
+
+fn fraction(part: u64, whole: u64) -> f32 {
+    if whole == 0 {
+        return 0.0;
+    }
+    part as f32 / whole as f32
+}
+
+fn sample_network_stats(
+    client: Res<NetworkClient>,
+    clock: Res<ServerClock>,
+    snapshots: Res<SnapshotBuffer>,
+    correction: Res<Correction>,
+    mut stats: ResMut<NetworkStats>,
+) {
+    let now = Instant::now();
+    if stats
+        .totals
+        .back()
+        .is_some_and(|last| now.duration_since(last.at) < SAMPLE_INTERVAL)
+    {
+        return;
+    }
+
+    let (bytes_sent, bytes_received) = client.bytes();
+    stats.push(Totals {
+        at: now,
+        connection: client.connection_stats(),
+        bytes_sent,
+        bytes_received,
+    });
+    stats.update_rates();
+
+    stats.rtt = clock.rtt();
+    stats.jitter = clock.jitter();
+    stats.buffered_snapshots = snapshots.buffered();
+    stats.corrections = correction.count;
+
+    let sample = StatsSample {
+        rtt: stats.rtt.unwrap_or_default(),
+        loss: stats.latest_loss(),
+    };
+    if stats.history.len() == GRAPH_SAMPLES {
+        stats.history.pop_front();
+    }
+    stats.history.push_back(sample);
+}
+
+fn reset_network_stats(mut stats: ResMut<NetworkStats>) {
+    *stats = NetworkStats::default();
+}
@@ -1,5 +1,4 @@
 pub mod player;
 pub mod camera_controller;
 pub mod player_movement;
-pub mod input;
 pub mod player_throw;
\ No newline at end of file
@@ -1,7 +1,10 @@
 use bevy::{camera::visibility::RenderLayers, color::palettes, light::NotShadowCaster, prelude::*};
-use bevy_rapier3d::prelude::*;
+use simulation::{
+    SimulationSystems,
+    player::{PlayerInput, SPAWN_POINT, player_bundle},
+};
 
-use crate::game::{game::VIEW_MODEL_RENDER_LAYER, player::{input::PlayerInput, player_throw::throw_on_click}};
+use crate::game::{game::VIEW_MODEL_RENDER_LAYER, player::player_throw::{add_snowball_visuals, init_snowball_assets, throw_on_click}};
 
 use super::{camera_controller, player_movement::*};
 pub struct PlayerPlugin;
@@ -14,24 +17,19 @@ impl Plugin for PlayerPlugin {
                 (
                     update_movement_input,
                     camera_controller::update_camera_controller,
-                    throw_on_click
+                    throw_on_click,
+                    add_snowball_visuals
                 ),
             )
             // physics timestep
-            .add_systems(FixedUpdate, update_movement)
-            .add_systems(Startup, init_player);
+            .add_systems(FixedUpdate, apply_local_input.before(SimulationSystems))
+            .add_systems(Startup, (init_player, init_snowball_assets));
     }
 }
 
+/// The player this client controls.
 #[derive(Component)]
-pub struct Player {
-    pub velocity: Vec3,
-    pub gravity: f32,
-    pub speed: f32,
-}
-
-#[derive(Component)]
-pub struct TracerSpawnSpot;
+pub struct LocalPlayer;
 
 fn init_player(
     mut commands: Commands,
@@ -61,20 +59,8 @@ fn init_player(
 
     let player_entity = commands
         .spawn((
-            Player {
-                velocity: Vec3::ZERO,
-                gravity: 9.8,
-                speed: 20.0,
-            },
-            Transform::from_translation(Vec3::new(10., 10., 10.)),
-            Collider::capsule(Vec3::new(1., 1., 1.), Vec3::new(1., 1., 1.), 1.),
-            LockedAxes::ROTATION_LOCKED,
-            RigidBody::Dynamic,
-            KinematicCharacterController {
-                up: Vec3::Y,
-                offset: CharacterLength::Absolute(0.01),
-                ..default()
-            },
+            player_bundle(SPAWN_POINT),
+            LocalPlayer,
             children![
                 (
                     Mesh3d(arm),
@@ -99,10 +85,6 @@ fn init_player(
                     }),
                     // Only render objects belonging to the view model.
                     RenderLayers::layer(VIEW_MODEL_RENDER_LAYER),
-                ),
-                (
-                    Transform::from_translation(Vec3::new(2., 2., 2.)),
-                    TracerSpawnSpot
                 )
             ],
         ))
@@ -1,11 +1,7 @@
 use bevy::prelude::*;
-use bevy_rapier3d::prelude::*;
+use simulation::player::PlayerInput;
 
-use crate::game::player::{camera_controller::CameraController, input::PlayerInput};
-
-use super::player::Player;
-
-use std::f32::consts::PI;
+use crate::game::player::{camera_controller::CameraController, player::LocalPlayer};
 
 pub fn update_movement_input(keys: Res<ButtonInput<KeyCode>>, mut input: ResMut<PlayerInput>) {
     input.movement = Vec3::ZERO;
@@ -28,46 +24,17 @@ pub fn update_movement_input(keys: Res<ButtonInput<KeyCode>>, mut input: ResMut<
     }
 }
 
-pub fn update_movement(
-    time: Res<Time<Fixed>>,
-    input: Res<PlayerInput>,
-    mut player_query: Query<(
-        &mut Player,
-        &mut KinematicCharacterController,
-        Option<&KinematicCharacterControllerOutput>,
-    )>,
-    camera_query: Single<&CameraController>,
+/// Hands this tick's keyboard, mouse and camera state to the simulated local player.
+pub fn apply_local_input(
+    mut input: ResMut<PlayerInput>,
+    camera: Single<&CameraController>,
+    mut player: Single<&mut PlayerInput, With<LocalPlayer>>,
 ) {
-    let camera = camera_query.into_inner();
-
-    for (mut player, mut controller, controller_output) in player_query.iter_mut() {
-        if let Some(output) = controller_output {
-            if output.grounded {
-                player.velocity = Vec3::ZERO;
-
-                // Can only jump on ground
-                player.velocity.y += input.movement.z * 10.0;
-            }
-        }
-
-        let camera_x = camera.rotation.x - PI;
+    input.yaw = camera.rotation.x;
+    input.pitch = camera.rotation.y;
 
-        let forward = Vec2::new(f32::sin(camera_x), f32::cos(camera_x));
+    **player = *input;
 
-        let right = Vec2::new(-forward.y, forward.x);
-
-        if let Some(movement_direction) =
-            (forward * input.movement.x + right * input.movement.y).try_normalize()
-        {
-            player.velocity.x = movement_direction.x * player.speed;
-            player.velocity.z = movement_direction.y * player.speed;
-        }
-
-        // Gravity
-        player.velocity.y -= player.gravity * time.timestep().as_secs_f32();
-
-
-        //delta
-        controller.translation = Some(player.velocity * time.timestep().as_secs_f32());
-    }
+    // Throws are latched until a tick picks them up.
+    input.throw = false;
 }
@@ -3,7 +3,18 @@ use bevy::{
     prelude::*,
 };
 
-use crate::game::player::camera_controller::CameraController;
+use crate::game::{
+    network::{
+        client::ConnectionState,
+        stats::{GRAPH_SAMPLES, NetworkStats},
+    },
+    player::camera_controller::CameraController,
+};
+
+/// Round trip at the top of the network graph. Slower ones are cut off.
+const GRAPH_MAX_RTT_MS: f32 = 250.0;
+const GRAPH_HEIGHT: f32 = 50.0;
+const GRAPH_BAR_WIDTH: f32 = 2.0;
 
 #[derive(Component)]
 pub struct FpsText;
@@ -11,6 +22,17 @@ pub struct FpsText;
 #[derive(Component)]
 pub struct DebugCameraText;
 
+/// Everything about the connection. Hidden while offline.
+#[derive(Component)]
+pub struct NetworkPanel;
+
+#[derive(Component)]
+pub struct NetworkStatsText;
+
+/// One bar of the round trip graph, oldest first.
+#[derive(Component)]
+pub struct NetworkGraphBar(pub usize);
+
 pub fn debug_view_system(
     diagnostics: Res<DiagnosticsStore>,
     mut query: Query<&mut TextSpan, With<FpsText>>,
@@ -38,6 +60,70 @@ pub fn update_debug_camera_text(
     }
 }
 
+pub fn update_network_stats_text(
+    stats: Option<Res<NetworkStats>>,
+    state: Option<Res<State<ConnectionState>>>,
+    mut panel: Query<&mut Visibility, With<NetworkPanel>>,
+    mut query: Query<&mut TextSpan, With<NetworkStatsText>>,
+) {
+    let connected = state.is_some_and(|state| *state.get() == ConnectionState::Connected);
+    for mut visibility in &mut panel {
+        *visibility = if connected {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+    let Some(stats) = stats.filter(|_| connected) else {
+        return;
+    };
+
+    let rtt = match stats.rtt {
+        Some(rtt) => format!(
+            "{:.1} ms, jitter {:.1} ms",
+            rtt.as_secs_f32() * 1000.0,
+            stats.jitter.as_secs_f32() * 1000.0
+        ),
+        None => "waiting for a pong".to_string(),
+    };
+    for mut span in &mut query {
+        **span = format!(
+            "{rtt}\nLoss: in {:.1}% out {:.1}%\nBandwidth: in {:.1} KB/s out {:.1} KB/s\nSnapshots buffered: {}\nCorrections: {}",
+            stats.loss_in * 100.0,
+            stats.loss_out * 100.0,
+            stats.bytes_in / 1024.0,
+            stats.bytes_out / 1024.0,
+            stats.buffered_snapshots,
+            stats.corrections,
+        );
+    }
+}
+
+/// Round trip over the last thirty seconds, red where packets went missing.
+pub fn update_network_graph(
+    stats: Option<Res<NetworkStats>>,
+    mut bars: Query<(&NetworkGraphBar, &mut Node, &mut BackgroundColor)>,
+) {
+    let Some(stats) = stats else {
+        return;
+    };
+    // Newest on the right, however few samples there are so far.
+    let empty = GRAPH_SAMPLES - stats.history.len();
+    for (NetworkGraphBar(index), mut node, mut color) in &mut bars {
+        let Some(sample) = index.checked_sub(empty).and_then(|i| stats.history.get(i)) else {
+            node.height = Val::Px(0.0);
+            continue;
+        };
+        let rtt = sample.rtt.as_secs_f32() * 1000.0;
+        node.height = Val::Px((rtt / GRAPH_MAX_RTT_MS).min(1.0) * GRAPH_HEIGHT);
+        color.0 = if sample.loss > 0.0 {
+            Color::srgb(0.9, 0.2, 0.2)
+        } else {
+            Color::srgb(0.3, 0.8, 0.3)
+        };
+    }
+}
+
 pub fn setup(mut commands: Commands) {
     let fps = commands
         .spawn(Text::new("FPS: "))
@@ -47,6 +133,41 @@ pub fn setup(mut commands: Commands) {
         .spawn(Text::new("Camera: "))
         .with_child((TextSpan::default(), DebugCameraText)).id();
 
+    let network = commands
+        .spawn((
+            NetworkPanel,
+            Node {
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            Visibility::Hidden,
+        ))
+        .with_children(|panel| {
+            panel
+                .spawn(Text::new("RTT: "))
+                .with_child((TextSpan::default(), NetworkStatsText));
+            panel
+                .spawn(Node {
+                    height: Val::Px(GRAPH_HEIGHT),
+                    align_items: AlignItems::FlexEnd,
+                    ..default()
+                })
+                .with_children(|graph| {
+                    for index in 0..GRAPH_SAMPLES {
+                        graph.spawn((
+                            NetworkGraphBar(index),
+                            Node {
+                                width: Val::Px(GRAPH_BAR_WIDTH),
+                                height: Val::Px(0.0),
+                                ..default()
+                            },
+                            BackgroundColor(Color::NONE),
+                        ));
+                    }
+                });
+        })
+        .id();
+
     let mut root = commands.spawn((
         Name::new("Root"),
         Node {
@@ -57,6 +178,7 @@ pub fn setup(mut commands: Commands) {
 
     root.add_child(fps);
     root.add_child(camera);
+    root.add_child(network);
 }
 
 // fn toggle_debug(
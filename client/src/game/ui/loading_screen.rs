@@ -0,0 +1,46 @@
+use bevy::prelude::*;
+
+use crate::game::network::map_download::MapDownload;
+
+/// Covers the screen while the server's map downloads.
+#[derive(Component)]
+pub struct LoadingScreen;
+
+#[derive(Component)]
+pub struct LoadingText;
+
+pub fn setup_loading_screen(mut commands: Commands) {
+    commands
+        .spawn((
+            LoadingScreen,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(Color::BLACK.with_alpha(0.8)),
+            GlobalZIndex(1),
+            Visibility::Hidden,
+        ))
+        .with_child((Text::default(), LoadingText));
+}
+
+pub fn update_loading_screen(
+    download: Res<MapDownload>,
+    mut screen: Single<&mut Visibility, With<LoadingScreen>>,
+    mut text: Single<&mut Text, With<LoadingText>>,
+) {
+    if !download.is_changed() {
+        return;
+    }
+    match download.progress() {
+        Some((name, done)) => {
+            **screen = Visibility::Visible;
+            text.0 = format!("Loading {}... {:.0}%", name, done * 100.0);
+        }
+        None => **screen = Visibility::Hidden,
+    }
+}
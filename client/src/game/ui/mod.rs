@@ -1,2 +1,3 @@
 pub mod debug_view;
+pub mod loading_screen;
 pub mod ui;
\ No newline at end of file
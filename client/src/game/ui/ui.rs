@@ -1,13 +1,27 @@
 use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*};
 
-use crate::game::ui::debug_view::{debug_view_system, setup, update_debug_camera_text};
+use crate::game::ui::{
+    debug_view::{
+        debug_view_system, setup, update_debug_camera_text, update_network_graph,
+        update_network_stats_text,
+    },
+    loading_screen::{setup_loading_screen, update_loading_screen},
+};
 
 pub struct UiPlugin;
 
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(FrameTimeDiagnosticsPlugin::default())
-            .add_systems(Startup, setup)
-            .add_systems(FixedUpdate, (debug_view_system, update_debug_camera_text));
+            .add_systems(Startup, (setup, setup_loading_screen))
+            .add_systems(FixedUpdate, (debug_view_system, update_debug_camera_text))
+            .add_systems(
+                Update,
+                (
+                    update_network_stats_text,
+                    update_network_graph,
+                    update_loading_screen,
+                ),
+            );
     }
 }
@@ -13,7 +13,7 @@
 //! You can toggle wireframes with the space bar except on wasm. Wasm does not support
 //! `POLYGON_MODE_LINE` on the gpu.
 
-use std::{io, net::UdpSocket, time::Duration};
+use std::{env, io};
 
 #[cfg(not(target_arch = "wasm32"))]
 use bevy::pbr::wireframe::{WireframeConfig, WireframePlugin};
@@ -22,56 +22,73 @@ use bevy::{
     prelude::*,
     render::render_resource::{Extent3d, TextureDimension, TextureFormat},
 };
+use protocol::{JoinCode, RoomRequest, netsim::NetworkConditions};
 use snowball::{GameState, pause_screen};
 
-use crate::game::game::GamePlugin;
+use crate::game::{
+    game::GamePlugin,
+    network::{client::NetworkClientPlugin, map_download},
+};
 
 pub mod game;
 
-fn main() {
-    println!("Hello??");
-    App::new()
-        .add_plugins((
-            DefaultPlugins.set(ImagePlugin::default_nearest()),
-            WireframePlugin::default(),
-        ))
-        .add_plugins(GamePlugin)
-        .add_systems(
-            Update,
-            (
-                #[cfg(not(target_arch = "wasm32"))]
-                toggle_wireframe,
-            ),
-        )
-        .init_state::<GameState>()
-        .add_plugins(pause_screen::menu_plugin)
-        .run();
-}
-
+fn main() -> io::Result<()> {
+    let mut network = NetworkClientPlugin {
+        server: None,
+        name: "player".to_string(),
+        room: RoomRequest::QuickPlay,
+        password: None,
+        network: NetworkConditions::NONE,
+        map_cache: map_download::default_cache_dir(),
+    };
 
-// fn main() -> io::Result<()> {
-    // let local = "0.0.0.0:8081";
-    // let host = "0.0.0.0:8080";
-    // if let Ok(server) = UdpSocket::bind(local) {
-    //     match server.connect(host) {
-    //         Ok(_) => {
-    //             run_client(server);
-    //         },
-    //         Err(e) => {return Err(e)},
-    //     }
-    // }
-    
-    // Ok(())
-// }
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--connect", Some(server)) => network.server = Some(server),
+            ("--name", Some(name)) => network.name = name,
+            ("--password", Some(password)) => network.password = Some(password),
+            ("--map-cache", Some(dir)) => network.map_cache = dir.into(),
+            ("--room", Some(room)) => {
+                network.room = match room.as_str() {
+                    "quick" => RoomRequest::QuickPlay,
+                    "public" => RoomRequest::Create { private: false },
+                    "private" => RoomRequest::Create { private: true },
+                    code => RoomRequest::Join(JoinCode::parse(code).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "--room expects quick, public, private or a join code",
+                        )
+                    })?),
+                };
+            }
+            ("--netsim", Some(preset)) => {
+                network.network = NetworkConditions::preset(&preset).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "--netsim expects one of {}",
+                            NetworkConditions::PRESETS.join(", ")
+                        ),
+                    )
+                })?;
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown argument {arg:?}"),
+                ));
+            }
+        }
+    }
 
-fn run_client(server: UdpSocket) {
-    info!("Starting client!");
     App::new()
         .add_plugins((
             DefaultPlugins.set(ImagePlugin::default_nearest()),
             WireframePlugin::default(),
         ))
         .add_plugins(GamePlugin)
+        .add_plugins(network)
         .add_systems(
             Update,
             (
@@ -82,6 +99,8 @@ fn run_client(server: UdpSocket) {
         .init_state::<GameState>()
         .add_plugins(pause_screen::menu_plugin)
         .run();
+
+    Ok(())
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -0,0 +1,206 @@
+use crate::codec::{Decode, DecodeError, Encode, EncodeError, Reader, Writer};
+
+/// Most messages a single packet may carry.
+pub const MAX_PACKET_MESSAGES: usize = 64;
+
+/// Set in a message's channel byte when it's a fragment.
+const FRAGMENT_FLAG: u8 = 0x80;
+
+/// Delivery guarantee for a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Channel {
+    /// Delivered at most once, in whatever order packets arrive.
+    Unreliable,
+    /// Like `Unreliable`, but anything older than the newest message already
+    /// delivered on this channel is dropped.
+    UnreliableSequenced,
+    /// Resent until acknowledged and delivered exactly once, in send order.
+    ReliableOrdered,
+}
+
+impl Channel {
+    /// Whether messages on this channel carry a per-channel id.
+    pub fn is_numbered(self) -> bool {
+        self != Channel::Unreliable
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            Channel::Unreliable => 0,
+            Channel::UnreliableSequenced => 1,
+            Channel::ReliableOrdered => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self, DecodeError> {
+        match tag {
+            0 => Ok(Channel::Unreliable),
+            1 => Ok(Channel::UnreliableSequenced),
+            2 => Ok(Channel::ReliableOrdered),
+            tag => Err(DecodeError::UnknownTag {
+                kind: "Channel",
+                tag,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PacketHeader {
+    pub sequence: u16,
+    /// Newest packet sequence received from the other side.
+    pub ack: u16,
+    /// Bit `n` set means packet `ack - 1 - n` was received as well.
+    pub ack_bits: u32,
+}
+
+/// Where a piece of a message too big for one packet goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fragment {
+    pub index: u8,
+    pub count: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelMessage {
+    pub channel: Channel,
+    /// Per-channel message id. Every fragment of a reliable message has its
+    /// own; on the unreliable channels they share the id of the message they
+    /// make up. Otherwise always 0 on `Channel::Unreliable`.
+    pub id: u16,
+    pub fragment: Option<Fragment>,
+    pub payload: Vec<u8>,
+}
+
+impl ChannelMessage {
+    /// Bytes this message takes up inside a packet.
+    pub fn encoded_len(&self) -> usize {
+        let id = if self.has_id() { 2 } else { 0 };
+        let fragment = if self.fragment.is_some() { 2 } else { 0 };
+        1 + id + fragment + varint_len(self.payload.len() as u32) + self.payload.len()
+    }
+
+    fn has_id(&self) -> bool {
+        self.channel.is_numbered() || self.fragment.is_some()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Packet {
+    pub header: PacketHeader,
+    pub messages: Vec<ChannelMessage>,
+}
+
+fn varint_len(mut value: u32) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+/// Returns true if sequence `a` is newer than `b`, allowing for wraparound.
+pub fn sequence_greater_than(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+impl Encode for Channel {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u8(self.tag());
+        Ok(())
+    }
+}
+
+impl Decode for Channel {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Channel::from_tag(reader.read_u8()?)
+    }
+}
+
+impl Encode for PacketHeader {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u16(self.sequence);
+        writer.write_u16(self.ack);
+        writer.write_u32(self.ack_bits);
+        Ok(())
+    }
+}
+
+impl Decode for PacketHeader {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(PacketHeader {
+            sequence: reader.read_u16()?,
+            ack: reader.read_u16()?,
+            ack_bits: reader.read_u32()?,
+        })
+    }
+}
+
+impl Encode for ChannelMessage {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        let flag = if self.fragment.is_some() {
+            FRAGMENT_FLAG
+        } else {
+            0
+        };
+        writer.write_u8(self.channel.tag() | flag);
+        if self.has_id() {
+            writer.write_u16(self.id);
+        }
+        if let Some(fragment) = self.fragment {
+            writer.write_u8(fragment.index);
+            writer.write_u8(fragment.count);
+        }
+        writer.write_len(self.payload.len(), crate::MAX_FRAGMENT_SIZE)?;
+        writer.write_bytes(&self.payload);
+        Ok(())
+    }
+}
+
+impl Decode for ChannelMessage {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let tag = reader.read_u8()?;
+        let channel = Channel::from_tag(tag & !FRAGMENT_FLAG)?;
+        let fragmented = tag & FRAGMENT_FLAG != 0;
+        let id = if channel.is_numbered() || fragmented {
+            reader.read_u16()?
+        } else {
+            0
+        };
+        let fragment = match fragmented {
+            true => {
+                let index = reader.read_u8()?;
+                let count = reader.read_u8()?;
+                if index >= count {
+                    return Err(DecodeError::BadFragment { index, count });
+                }
+                Some(Fragment { index, count })
+            }
+            false => None,
+        };
+        let len = reader.read_len(crate::MAX_FRAGMENT_SIZE)?;
+        Ok(ChannelMessage {
+            channel,
+            id,
+            fragment,
+            payload: reader.read_bytes(len)?.to_vec(),
+        })
+    }
+}
+
+impl Encode for Packet {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        self.header.encode(writer)?;
+        crate::codec::encode_list(writer, &self.messages, MAX_PACKET_MESSAGES)
+    }
+}
+
+impl Decode for Packet {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Packet {
+            header: PacketHeader::decode(reader)?,
+            messages: crate::codec::decode_list(reader, MAX_PACKET_MESSAGES)?,
+        })
+    }
+}
@@ -0,0 +1,502 @@
+use std::fmt;
+
+/// Longest string we accept on the wire (chat lines, player names, ...).
+pub const MAX_STRING_LEN: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    PacketTooLarge { size: usize, max: usize },
+    StringTooLong { len: usize, max: usize },
+    TooManyItems { len: usize, max: usize },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::PacketTooLarge { size, max } => {
+                write!(f, "packet is {size} bytes, limit is {max}")
+            }
+            EncodeError::StringTooLong { len, max } => {
+                write!(f, "string is {len} bytes, limit is {max}")
+            }
+            EncodeError::TooManyItems { len, max } => {
+                write!(f, "list has {len} items, limit is {max}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEnd { needed: usize, remaining: usize },
+    TrailingBytes(usize),
+    PacketTooLarge { size: usize, max: usize },
+    BadProtocolId(u32),
+    UnknownTag { kind: &'static str, tag: u8 },
+    LengthTooLarge { len: usize, max: usize },
+    InvalidUtf8,
+    VarintOverflow,
+    BadFragment { index: u8, count: u8 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd { needed, remaining } => {
+                write!(f, "needed {needed} more bytes but only {remaining} remain")
+            }
+            DecodeError::TrailingBytes(n) => write!(f, "{n} unread bytes after message"),
+            DecodeError::PacketTooLarge { size, max } => {
+                write!(f, "packet is {size} bytes, limit is {max}")
+            }
+            DecodeError::BadProtocolId(id) => write!(f, "unknown protocol id {id:#010x}"),
+            DecodeError::UnknownTag { kind, tag } => write!(f, "unknown {kind} tag {tag}"),
+            DecodeError::LengthTooLarge { len, max } => {
+                write!(f, "length prefix {len} exceeds limit {max}")
+            }
+            DecodeError::InvalidUtf8 => write!(f, "string is not valid utf-8"),
+            DecodeError::VarintOverflow => write!(f, "varint does not fit in 32 bits"),
+            DecodeError::BadFragment { index, count } => {
+                write!(f, "fragment index {index} is past its count of {count}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub trait Encode {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError>;
+}
+
+pub trait Decode: Sized {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError>;
+}
+
+/// Little-endian byte writer. Variable sized values (lengths, ids) use LEB128 varints.
+#[derive(Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_varint(&mut self, mut value: u32) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.write_u8(byte);
+                return;
+            }
+            self.write_u8(byte | 0x80);
+        }
+    }
+
+    /// Signed varint; small values of either sign stay small.
+    pub fn write_zigzag(&mut self, value: i32) {
+        self.write_varint(((value << 1) ^ (value >> 31)) as u32);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn write_len(&mut self, len: usize, max: usize) -> Result<(), EncodeError> {
+        if len > max {
+            return Err(EncodeError::TooManyItems { len, max });
+        }
+        self.write_varint(len as u32);
+        Ok(())
+    }
+
+    pub fn write_string(&mut self, value: &str) -> Result<(), EncodeError> {
+        if value.len() > MAX_STRING_LEN {
+            return Err(EncodeError::StringTooLong {
+                len: value.len(),
+                max: MAX_STRING_LEN,
+            });
+        }
+        self.write_varint(value.len() as u32);
+        self.write_bytes(value.as_bytes());
+        Ok(())
+    }
+
+    pub fn write_vec3(&mut self, value: [f32; 3]) {
+        for v in value {
+            self.write_f32(v);
+        }
+    }
+
+    pub fn write_quat(&mut self, value: [f32; 4]) {
+        for v in value {
+            self.write_f32(v);
+        }
+    }
+
+    /// Returns the written bytes, failing if they don't fit in `max` bytes.
+    pub fn finish(self, max: usize) -> Result<Vec<u8>, EncodeError> {
+        if self.bytes.len() > max {
+            return Err(EncodeError::PacketTooLarge {
+                size: self.bytes.len(),
+                max,
+            });
+        }
+        Ok(self.bytes)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if len > self.remaining() {
+            return Err(DecodeError::UnexpectedEnd {
+                needed: len,
+                remaining: self.remaining(),
+            });
+        }
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, DecodeError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::UnknownTag { kind: "bool", tag }),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, DecodeError> {
+        Ok(i32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, DecodeError> {
+        Ok(f32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_varint(&mut self) -> Result<u32, DecodeError> {
+        let mut value: u32 = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = self.read_u8()?;
+            let bits = (byte & 0x7f) as u32;
+            if shift == 28 && bits > 0x0f {
+                return Err(DecodeError::VarintOverflow);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::VarintOverflow)
+    }
+
+    pub fn read_zigzag(&mut self) -> Result<i32, DecodeError> {
+        let value = self.read_varint()?;
+        Ok((value >> 1) as i32 ^ -((value & 1) as i32))
+    }
+
+    /// Reads a length prefix, rejecting anything above `max` or longer than the
+    /// bytes left in the packet so a hostile prefix can't force a huge allocation.
+    pub fn read_len(&mut self, max: usize) -> Result<usize, DecodeError> {
+        let len = self.read_varint()? as usize;
+        if len > max {
+            return Err(DecodeError::LengthTooLarge { len, max });
+        }
+        if len > self.remaining() {
+            return Err(DecodeError::UnexpectedEnd {
+                needed: len,
+                remaining: self.remaining(),
+            });
+        }
+        Ok(len)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, DecodeError> {
+        let len = self.read_len(MAX_STRING_LEN)?;
+        let bytes = self.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    pub fn read_vec3(&mut self) -> Result<[f32; 3], DecodeError> {
+        Ok([self.read_f32()?, self.read_f32()?, self.read_f32()?])
+    }
+
+    pub fn read_quat(&mut self) -> Result<[f32; 4], DecodeError> {
+        Ok([
+            self.read_f32()?,
+            self.read_f32()?,
+            self.read_f32()?,
+            self.read_f32()?,
+        ])
+    }
+
+    /// Fails if anything is left unread.
+    pub fn finish(self) -> Result<(), DecodeError> {
+        match self.remaining() {
+            0 => Ok(()),
+            n => Err(DecodeError::TrailingBytes(n)),
+        }
+    }
+}
+
+pub fn encode_list<T: Encode>(
+    writer: &mut Writer,
+    items: &[T],
+    max: usize,
+) -> Result<(), EncodeError> {
+    writer.write_len(items.len(), max)?;
+    for item in items {
+        item.encode(writer)?;
+    }
+    Ok(())
+}
+
+pub fn decode_list<T: Decode>(reader: &mut Reader, max: usize) -> Result<Vec<T>, DecodeError> {
+    let len = reader.read_len(max)?;
+    let mut items = Vec::with_capacity(len);
+    for _ in 0..len {
+        items.push(T::decode(reader)?);
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(write: impl FnOnce(&mut Writer)) -> Vec<u8> {
+        let mut writer = Writer::new();
+        write(&mut writer);
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u32::MAX - 1, u32::MAX] {
+            let bytes = written(|writer| writer.write_varint(value));
+            let mut reader = Reader::new(&bytes);
+            assert_eq!(reader.read_varint(), Ok(value));
+            assert_eq!(reader.finish(), Ok(()));
+        }
+        assert_eq!(written(|writer| writer.write_varint(0)), [0]);
+        assert_eq!(written(|writer| writer.write_varint(u32::MAX)).len(), 5);
+    }
+
+    #[test]
+    fn zigzags_round_trip() {
+        for value in [0, -1, 1, -64, 64, i32::MIN, i32::MAX] {
+            let bytes = written(|writer| writer.write_zigzag(value));
+            let mut reader = Reader::new(&bytes);
+            assert_eq!(reader.read_zigzag(), Ok(value));
+            assert_eq!(reader.finish(), Ok(()));
+        }
+        // Small either way round is small on the wire.
+        assert_eq!(written(|writer| writer.write_zigzag(-1)), [1]);
+    }
+
+    #[test]
+    fn fixed_width_values_round_trip() {
+        let bytes = written(|writer| {
+            writer.write_u64(u64::MAX);
+            writer.write_u64(0);
+            writer.write_i32(i32::MIN);
+            writer.write_u16(u16::MAX);
+            writer.write_f32(-1.5);
+            writer.write_bool(true);
+        });
+        let mut reader = Reader::new(&bytes);
+        assert_eq!(reader.read_u64(), Ok(u64::MAX));
+        assert_eq!(reader.read_u64(), Ok(0));
+        assert_eq!(reader.read_i32(), Ok(i32::MIN));
+        assert_eq!(reader.read_u16(), Ok(u16::MAX));
+        assert_eq!(reader.read_f32(), Ok(-1.5));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn varints_past_32_bits_are_rejected() {
+        let mut reader = Reader::new(&[0xff, 0xff, 0xff, 0xff, 0x1f]);
+        assert_eq!(reader.read_varint(), Err(DecodeError::VarintOverflow));
+        let mut reader = Reader::new(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]);
+        assert_eq!(reader.read_varint(), Err(DecodeError::VarintOverflow));
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let bytes = written(|writer| {
+            writer.write_u64(u64::MAX);
+            writer.write_varint(u32::MAX);
+            writer.write_string("snowball").unwrap();
+        });
+        for len in 0..bytes.len() {
+            let mut reader = Reader::new(&bytes[..len]);
+            let result = (|| {
+                reader.read_u64()?;
+                reader.read_varint()?;
+                reader.read_string()
+            })();
+            assert!(
+                matches!(result, Err(DecodeError::UnexpectedEnd { .. })),
+                "{len} bytes: {result:?}"
+            );
+        }
+        assert_eq!(
+            Reader::new(&[1, 2]).read_u32(),
+            Err(DecodeError::UnexpectedEnd {
+                needed: 4,
+                remaining: 2
+            })
+        );
+    }
+
+    #[test]
+    fn lengths_past_the_end_are_rejected() {
+        let mut reader = Reader::new(&[1, 2, 3]);
+        assert_eq!(
+            reader.read_bytes(4),
+            Err(DecodeError::UnexpectedEnd {
+                needed: 4,
+                remaining: 3
+            })
+        );
+        assert_eq!(reader.read_bytes(3), Ok(&[1, 2, 3][..]));
+
+        // Says 10 but only 2 follow.
+        let mut reader = Reader::new(&[10, 0, 0]);
+        assert_eq!(
+            reader.read_len(100),
+            Err(DecodeError::UnexpectedEnd {
+                needed: 10,
+                remaining: 2
+            })
+        );
+        let mut reader = Reader::new(&[10, 0, 0]);
+        assert_eq!(
+            reader.read_len(5),
+            Err(DecodeError::LengthTooLarge { len: 10, max: 5 })
+        );
+        let bytes = written(|writer| writer.write_varint(u32::MAX));
+        assert!(decode_list::<crate::ClientId>(&mut Reader::new(&bytes), usize::MAX).is_err());
+    }
+
+    #[test]
+    fn strings_are_limited() {
+        let longest = "x".repeat(MAX_STRING_LEN);
+        let bytes = written(|writer| writer.write_string(&longest).unwrap());
+        assert_eq!(Reader::new(&bytes).read_string(), Ok(longest));
+
+        let too_long = "x".repeat(MAX_STRING_LEN + 1);
+        assert_eq!(
+            Writer::new().write_string(&too_long),
+            Err(EncodeError::StringTooLong {
+                len: MAX_STRING_LEN + 1,
+                max: MAX_STRING_LEN
+            })
+        );
+        // Written by hand, as a hostile peer would.
+        let bytes = written(|writer| {
+            writer.write_varint(too_long.len() as u32);
+            writer.write_bytes(too_long.as_bytes());
+        });
+        assert_eq!(
+            Reader::new(&bytes).read_string(),
+            Err(DecodeError::LengthTooLarge {
+                len: MAX_STRING_LEN + 1,
+                max: MAX_STRING_LEN
+            })
+        );
+        assert_eq!(
+            Reader::new(&[2, 0xc3, 0x28]).read_string(),
+            Err(DecodeError::InvalidUtf8)
+        );
+    }
+
+    #[test]
+    fn unread_bytes_are_an_error() {
+        let mut reader = Reader::new(&[1, 2, 3]);
+        reader.read_u8().unwrap();
+        assert_eq!(reader.finish(), Err(DecodeError::TrailingBytes(2)));
+    }
+}
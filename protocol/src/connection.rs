@@ -0,0 +1,840 @@
+//! Reliability layered over unreliable datagrams. Every packet is numbered and
+//! acknowledges the last 33 packets received from the other side; reliable
+//! messages are resent until a packet carrying them is acknowledged. Messages
+//! too big for one packet go in fragments and are put back together on the
+//! other side.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    DEFAULT_MAX_MESSAGE_SIZE, MAX_FRAGMENT_SIZE, MAX_MESSAGE_SIZE, MAX_PACKET_PAYLOAD,
+    channel::{
+        Channel, ChannelMessage, Fragment, MAX_PACKET_MESSAGES, Packet, PacketHeader,
+        sequence_greater_than,
+    },
+    codec::{Encode, EncodeError},
+};
+
+/// How many sent packets we remember while waiting for their acks.
+const SENT_PACKET_BUFFER: usize = 1024;
+
+/// Most reliable messages that may be in flight at once. The receiver buffers
+/// at most this many out of order.
+const RELIABLE_WINDOW: u16 = 256;
+
+const INITIAL_RTO: Duration = Duration::from_millis(250);
+const MIN_RTO: Duration = Duration::from_millis(50);
+const MAX_RTO: Duration = Duration::from_secs(2);
+
+/// How long the fragments of an unreliable message wait for the rest.
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Most unreliable messages we'll hold fragments of at once. Past this the
+/// oldest is given up on.
+const MAX_PARTIAL_MESSAGES: usize = 4;
+
+struct SentPacket {
+    sequence: u16,
+    sent_at: Instant,
+    acked: bool,
+    /// Ids of the reliable messages this packet carried.
+    reliable: Vec<u16>,
+}
+
+struct PendingMessage {
+    id: u16,
+    fragment: Option<Fragment>,
+    payload: Vec<u8>,
+    last_sent: Option<Instant>,
+    acked: bool,
+}
+
+/// A message we have some of the fragments of.
+struct Partial {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    size: usize,
+    started: Instant,
+}
+
+impl Partial {
+    fn new(count: u8, now: Instant) -> Self {
+        Self {
+            fragments: vec![None; count as usize],
+            missing: count as usize,
+            size: 0,
+            started: now,
+        }
+    }
+
+    /// Adds a fragment. False if it can't be part of the same message as the
+    /// ones already here, or would make it bigger than `max_size`.
+    fn add(&mut self, fragment: Fragment, payload: Vec<u8>, max_size: usize) -> bool {
+        if fragment.count as usize != self.fragments.len() || self.size + payload.len() > max_size {
+            return false;
+        }
+        let slot = &mut self.fragments[fragment.index as usize];
+        if slot.is_none() {
+            self.size += payload.len();
+            self.missing -= 1;
+            *slot = Some(payload);
+        }
+        true
+    }
+
+    fn assemble(self) -> Vec<u8> {
+        self.fragments.into_iter().flatten().flatten().collect()
+    }
+}
+
+/// Smoothed round trip time and retransmission timeout, as in RFC 6298.
+#[derive(Clone, Copy, Debug)]
+pub struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+}
+
+impl Default for RttEstimator {
+    fn default() -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: INITIAL_RTO,
+        }
+    }
+}
+
+impl RttEstimator {
+    pub fn sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                self.rttvar = self.rttvar * 3 / 4 + srtt.abs_diff(rtt) / 4;
+                self.srtt = Some(srtt * 7 / 8 + rtt / 8);
+            }
+        }
+        let srtt = self.srtt.unwrap_or(rtt);
+        self.rto = (srtt + self.rttvar * 4).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Smoothed round trip time, or zero before the first sample.
+    pub fn rtt(&self) -> Duration {
+        self.srtt.unwrap_or(Duration::ZERO)
+    }
+
+    pub fn rto(&self) -> Duration {
+        self.rto
+    }
+}
+
+/// Running totals since the connection was made. Take the difference between
+/// two to get rates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub packets_sent: u64,
+    /// Sent packets whose fate we know: acknowledged, or too far behind the
+    /// newest ack to ever be acknowledged now.
+    pub packets_resolved: u64,
+    /// Resolved packets that were never acknowledged.
+    pub packets_lost: u64,
+    pub packets_received: u64,
+    /// Packets the other side must have sent for us to have seen the
+    /// sequence numbers we have.
+    pub packets_expected: u64,
+}
+
+/// One side of a connection. Queue messages with `send`, feed every packet
+/// from the other side to `receive`, and call `flush` once per tick to get the
+/// packets to put on the wire.
+pub struct Connection {
+    local_sequence: u16,
+    /// Newest packet sequence received, and which of the 32 before it arrived.
+    remote_sequence: Option<u16>,
+    received_bits: u32,
+    /// Something arrived that we haven't acknowledged yet.
+    ack_pending: bool,
+    sent: Vec<Option<SentPacket>>,
+    /// Newest sent packet counted in `stats.packets_resolved`.
+    resolved: u16,
+    rtt: RttEstimator,
+    last_received: Instant,
+    stats: ConnectionStats,
+    max_message_size: usize,
+
+    unreliable: Vec<ChannelMessage>,
+    /// Shared by the fragments of each unreliable message that has them.
+    next_fragmented_id: u16,
+    sequenced: Vec<ChannelMessage>,
+    next_sequenced_id: u16,
+    newest_sequenced: Option<u16>,
+    /// Unreliable and sequenced messages still missing fragments.
+    partial: HashMap<(Channel, u16), Partial>,
+
+    pending: VecDeque<PendingMessage>,
+    next_reliable_id: u16,
+    next_expected: u16,
+    received_reliable: HashMap<u16, ChannelMessage>,
+    /// The fragmented reliable message being delivered. Its fragments come
+    /// in order, so there's only ever one.
+    reliable_partial: Option<Partial>,
+}
+
+impl Connection {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            // Until we've heard from the other side our acks read 0, so don't
+            // let that name a packet we actually sent.
+            local_sequence: 1,
+            remote_sequence: None,
+            received_bits: 0,
+            ack_pending: false,
+            sent: (0..SENT_PACKET_BUFFER).map(|_| None).collect(),
+            resolved: 0,
+            rtt: RttEstimator::default(),
+            last_received: now,
+            stats: ConnectionStats::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            unreliable: Vec::new(),
+            next_fragmented_id: 0,
+            sequenced: Vec::new(),
+            next_sequenced_id: 0,
+            newest_sequenced: None,
+            partial: HashMap::new(),
+            pending: VecDeque::new(),
+            next_reliable_id: 0,
+            next_expected: 0,
+            received_reliable: HashMap::new(),
+            reliable_partial: None,
+        }
+    }
+
+    /// Largest message this side will send or put back together, between
+    /// `MAX_FRAGMENT_SIZE` and `MAX_MESSAGE_SIZE`.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size.clamp(MAX_FRAGMENT_SIZE, MAX_MESSAGE_SIZE);
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Queues an encoded message for the next `flush`, in fragments if it
+    /// won't fit in one packet.
+    pub fn send(&mut self, channel: Channel, payload: Vec<u8>) -> Result<(), EncodeError> {
+        if payload.len() > self.max_message_size {
+            return Err(EncodeError::PacketTooLarge {
+                size: payload.len(),
+                max: self.max_message_size,
+            });
+        }
+        let pieces = split(payload);
+
+        match channel {
+            Channel::Unreliable => {
+                let id = if pieces.len() > 1 {
+                    let id = self.next_fragmented_id;
+                    self.next_fragmented_id = id.wrapping_add(1);
+                    id
+                } else {
+                    0
+                };
+                self.unreliable
+                    .extend(
+                        pieces
+                            .into_iter()
+                            .map(|(fragment, payload)| ChannelMessage {
+                                channel,
+                                id,
+                                fragment,
+                                payload,
+                            }),
+                    );
+            }
+            Channel::UnreliableSequenced => {
+                let id = self.next_sequenced_id;
+                self.next_sequenced_id = id.wrapping_add(1);
+                self.sequenced
+                    .extend(
+                        pieces
+                            .into_iter()
+                            .map(|(fragment, payload)| ChannelMessage {
+                                channel,
+                                id,
+                                fragment,
+                                payload,
+                            }),
+                    );
+            }
+            Channel::ReliableOrdered => {
+                for (fragment, payload) in pieces {
+                    let id = self.next_reliable_id;
+                    self.next_reliable_id = id.wrapping_add(1);
+                    self.pending.push_back(PendingMessage {
+                        id,
+                        fragment,
+                        payload,
+                        last_sent: None,
+                        acked: false,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn send_message<T: Encode>(
+        &mut self,
+        channel: Channel,
+        message: &T,
+    ) -> Result<(), EncodeError> {
+        self.send(channel, crate::encode_message(message)?)
+    }
+
+    /// Processes a packet from the other side and returns the payloads that are
+    /// ready for the application, in delivery order.
+    pub fn receive(&mut self, packet: Packet, now: Instant) -> Vec<(Channel, Vec<u8>)> {
+        let Packet { header, messages } = packet;
+        if !self.record_received(header.sequence) {
+            // A duplicate; its acks and messages have been seen already.
+            return Vec::new();
+        }
+        self.last_received = now;
+        self.stats.packets_received += 1;
+        self.process_acks(header, now);
+        self.partial
+            .retain(|_, partial| now.duration_since(partial.started) < FRAGMENT_TIMEOUT);
+
+        let mut delivered = Vec::new();
+        for message in messages {
+            match message.channel {
+                Channel::Unreliable => {
+                    if let Some(payload) = self.reassemble(message, now) {
+                        delivered.push((Channel::Unreliable, payload));
+                    }
+                }
+                Channel::UnreliableSequenced => {
+                    let id = message.id;
+                    let newer = self
+                        .newest_sequenced
+                        .is_none_or(|newest| sequence_greater_than(id, newest));
+                    if !newer {
+                        continue;
+                    }
+                    if let Some(payload) = self.reassemble(message, now) {
+                        self.newest_sequenced = Some(id);
+                        // Nothing older can be delivered now.
+                        self.partial.retain(|(channel, partial), _| {
+                            *channel != Channel::UnreliableSequenced
+                                || sequence_greater_than(*partial, id)
+                        });
+                        delivered.push((Channel::UnreliableSequenced, payload));
+                    }
+                }
+                Channel::ReliableOrdered => {
+                    // Anything outside the window is a resend of something already delivered.
+                    if message.id.wrapping_sub(self.next_expected) < RELIABLE_WINDOW {
+                        self.received_reliable.insert(message.id, message);
+                    }
+                    while let Some(message) = self.received_reliable.remove(&self.next_expected) {
+                        self.next_expected = self.next_expected.wrapping_add(1);
+                        if let Some(payload) = self.reassemble_reliable(message, now) {
+                            delivered.push((Channel::ReliableOrdered, payload));
+                        }
+                    }
+                }
+            }
+        }
+        delivered
+    }
+
+    /// Packs everything queued, plus any reliable messages due for a resend,
+    /// into packets. Sends a bare ack if there's nothing else to say but we
+    /// have received something since the last flush.
+    pub fn flush(&mut self, now: Instant) -> Vec<Packet> {
+        let rto = self.rtt.rto();
+        let mut messages = Vec::new();
+
+        // The window is counted from the oldest unacked message, so the
+        // receiver never sees an id it can't place.
+        for pending in self.pending.iter_mut().take(RELIABLE_WINDOW as usize) {
+            let due = pending
+                .last_sent
+                .is_none_or(|sent| now.duration_since(sent) >= rto);
+            if !pending.acked && due {
+                pending.last_sent = Some(now);
+                messages.push(ChannelMessage {
+                    channel: Channel::ReliableOrdered,
+                    id: pending.id,
+                    fragment: pending.fragment,
+                    payload: pending.payload.clone(),
+                });
+            }
+        }
+        messages.append(&mut self.sequenced);
+        messages.append(&mut self.unreliable);
+
+        let mut packets = Vec::new();
+        let mut current = Vec::new();
+        let mut size = 0;
+        for message in messages {
+            let len = message.encoded_len();
+            if !current.is_empty()
+                && (size + len > MAX_PACKET_PAYLOAD || current.len() == MAX_PACKET_MESSAGES)
+            {
+                packets.push(self.seal(std::mem::take(&mut current), now));
+                size = 0;
+            }
+            size += len;
+            current.push(message);
+        }
+        if !current.is_empty() || (packets.is_empty() && self.ack_pending) {
+            packets.push(self.seal(current, now));
+        }
+
+        self.ack_pending = false;
+        packets
+    }
+
+    pub fn rtt(&self) -> &RttEstimator {
+        &self.rtt
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats
+    }
+
+    /// When the last new packet arrived from the other side.
+    pub fn last_received(&self) -> Instant {
+        self.last_received
+    }
+
+    /// Reliable messages sent or queued but not yet acknowledged.
+    pub fn unacked(&self) -> usize {
+        self.pending.iter().filter(|pending| !pending.acked).count()
+    }
+
+    /// Returns an unreliable message's payload once all of it is here.
+    fn reassemble(&mut self, message: ChannelMessage, now: Instant) -> Option<Vec<u8>> {
+        let Some(fragment) = message.fragment else {
+            return Some(message.payload);
+        };
+        if !self.fragment_fits(fragment, message.payload.len()) {
+            return None;
+        }
+
+        let key = (message.channel, message.id);
+        if !self.partial.contains_key(&key) && self.partial.len() >= MAX_PARTIAL_MESSAGES {
+            let oldest = self
+                .partial
+                .iter()
+                .min_by_key(|(_, partial)| partial.started)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.partial.remove(&oldest);
+            }
+        }
+        let partial = self
+            .partial
+            .entry(key)
+            .or_insert_with(|| Partial::new(fragment.count, now));
+        if !partial.add(fragment, message.payload, self.max_message_size) {
+            self.partial.remove(&key);
+            return None;
+        }
+        if partial.missing > 0 {
+            return None;
+        }
+        self.partial.remove(&key).map(Partial::assemble)
+    }
+
+    /// Like `reassemble`, for reliable messages as they're delivered in order.
+    fn reassemble_reliable(&mut self, message: ChannelMessage, now: Instant) -> Option<Vec<u8>> {
+        let Some(fragment) = message.fragment else {
+            // The sender never interleaves messages, so whatever was being
+            // put together is never going to be finished.
+            self.reliable_partial = None;
+            return Some(message.payload);
+        };
+        if !self.fragment_fits(fragment, message.payload.len()) {
+            self.reliable_partial = None;
+            return None;
+        }
+
+        if fragment.index == 0 {
+            self.reliable_partial = Some(Partial::new(fragment.count, now));
+        }
+        let partial = self.reliable_partial.as_mut()?;
+        if !partial.add(fragment, message.payload, self.max_message_size) {
+            self.reliable_partial = None;
+            return None;
+        }
+        if partial.missing > 0 {
+            return None;
+        }
+        self.reliable_partial.take().map(Partial::assemble)
+    }
+
+    /// Whether a fragment could have come from splitting a message we'd
+    /// accept: every fragment but the last is full, and there aren't more of
+    /// them than our size limit allows.
+    fn fragment_fits(&self, fragment: Fragment, len: usize) -> bool {
+        if fragment.index >= fragment.count {
+            return false;
+        }
+        let max_fragments = self.max_message_size.div_ceil(MAX_FRAGMENT_SIZE);
+        let last = fragment.index + 1 == fragment.count;
+        fragment.count as usize <= max_fragments && (last || len == MAX_FRAGMENT_SIZE)
+    }
+
+    fn seal(&mut self, messages: Vec<ChannelMessage>, now: Instant) -> Packet {
+        let sequence = self.local_sequence;
+        self.local_sequence = sequence.wrapping_add(1);
+        self.stats.packets_sent += 1;
+
+        let reliable = messages
+            .iter()
+            .filter(|message| message.channel == Channel::ReliableOrdered)
+            .map(|message| message.id)
+            .collect();
+        self.sent[sequence as usize % SENT_PACKET_BUFFER] = Some(SentPacket {
+            sequence,
+            sent_at: now,
+            acked: false,
+            reliable,
+        });
+
+        Packet {
+            header: PacketHeader {
+                sequence,
+                ack: self.remote_sequence.unwrap_or(0),
+                ack_bits: self.received_bits,
+            },
+            messages,
+        }
+    }
+
+    /// Marks `sequence` as received. Returns false if we've already seen it.
+    fn record_received(&mut self, sequence: u16) -> bool {
+        let Some(remote) = self.remote_sequence else {
+            self.remote_sequence = Some(sequence);
+            self.ack_pending = true;
+            self.stats.packets_expected += 1;
+            return true;
+        };
+
+        if sequence_greater_than(sequence, remote) {
+            let shift = u32::from(sequence.wrapping_sub(remote));
+            self.stats.packets_expected += u64::from(shift);
+            // The old newest becomes bit `shift - 1`.
+            self.received_bits = if shift > 32 {
+                0
+            } else {
+                (self.received_bits << 1 | 1)
+                    .checked_shl(shift - 1)
+                    .unwrap_or(0)
+            };
+            self.remote_sequence = Some(sequence);
+        } else {
+            let behind = u32::from(remote.wrapping_sub(sequence));
+            if behind == 0 {
+                return false;
+            }
+            if behind <= 32 {
+                let bit = 1 << (behind - 1);
+                if self.received_bits & bit != 0 {
+                    return false;
+                }
+                self.received_bits |= bit;
+            }
+            // Too old to tell; let the channels sort it out.
+        }
+
+        self.ack_pending = true;
+        true
+    }
+
+    fn process_acks(&mut self, header: PacketHeader, now: Instant) {
+        self.ack_packet(header.ack, now);
+        for bit in 0..32 {
+            if header.ack_bits & (1 << bit) != 0 {
+                self.ack_packet(header.ack.wrapping_sub(bit + 1), now);
+            }
+        }
+
+        while self.pending.front().is_some_and(|pending| pending.acked) {
+            self.pending.pop_front();
+        }
+
+        self.resolve_sent(header.ack);
+    }
+
+    /// Counts everything the ack bits no longer reach, up to `ack`, as either
+    /// delivered or lost.
+    fn resolve_sent(&mut self, ack: u16) {
+        let oldest_reachable = ack.wrapping_sub(32);
+        // Never past what we've actually sent, whatever the other side claims.
+        let newest_sent = self.local_sequence.wrapping_sub(1);
+        let limit = if sequence_greater_than(oldest_reachable, newest_sent) {
+            newest_sent
+        } else {
+            oldest_reachable
+        };
+
+        while sequence_greater_than(limit, self.resolved) {
+            self.resolved = self.resolved.wrapping_add(1);
+            let Some(sent) = &self.sent[self.resolved as usize % SENT_PACKET_BUFFER] else {
+                continue;
+            };
+            if sent.sequence != self.resolved {
+                continue;
+            }
+            self.stats.packets_resolved += 1;
+            if !sent.acked {
+                self.stats.packets_lost += 1;
+            }
+        }
+    }
+
+    fn ack_packet(&mut self, sequence: u16, now: Instant) {
+        let Some(sent) = &mut self.sent[sequence as usize % SENT_PACKET_BUFFER] else {
+            return;
+        };
+        if sent.sequence != sequence || sent.acked {
+            return;
+        }
+        sent.acked = true;
+        self.rtt.sample(now.duration_since(sent.sent_at));
+
+        for id in std::mem::take(&mut sent.reliable) {
+            if let Some(pending) = self.pending.iter_mut().find(|pending| pending.id == id) {
+                pending.acked = true;
+            }
+        }
+    }
+}
+
+/// Cuts a payload into pieces that each fit in a packet. One that already
+/// fits goes whole.
+fn split(payload: Vec<u8>) -> Vec<(Option<Fragment>, Vec<u8>)> {
+    if payload.len() <= MAX_FRAGMENT_SIZE {
+        return vec![(None, payload)];
+    }
+    let count = payload.len().div_ceil(MAX_FRAGMENT_SIZE) as u8;
+    payload
+        .chunks(MAX_FRAGMENT_SIZE)
+        .enumerate()
+        .map(|(index, chunk)| {
+            (
+                Some(Fragment {
+                    index: index as u8,
+                    count,
+                }),
+                chunk.to_vec(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds crafted messages to a connection, a packet each, the way a
+    /// hostile or broken peer could.
+    struct Peer {
+        connection: Connection,
+        sequence: u16,
+        now: Instant,
+    }
+
+    impl Peer {
+        fn new() -> Self {
+            let now = Instant::now();
+            Self {
+                connection: Connection::new(now),
+                sequence: 0,
+                now,
+            }
+        }
+
+        fn send(&mut self, message: ChannelMessage) -> Vec<(Channel, Vec<u8>)> {
+            self.sequence = self.sequence.wrapping_add(1);
+            let packet = Packet {
+                header: PacketHeader {
+                    sequence: self.sequence,
+                    ack: 0,
+                    ack_bits: 0,
+                },
+                messages: vec![message],
+            };
+            self.connection.receive(packet, self.now)
+        }
+    }
+
+    fn fragment(channel: Channel, id: u16, index: u8, count: u8, len: usize) -> ChannelMessage {
+        ChannelMessage {
+            channel,
+            id,
+            fragment: Some(Fragment { index, count }),
+            payload: vec![index; len],
+        }
+    }
+
+    #[test]
+    fn bad_fragment_headers_are_dropped() {
+        let mut peer = Peer::new();
+        let too_many = (DEFAULT_MAX_MESSAGE_SIZE.div_ceil(MAX_FRAGMENT_SIZE) + 1) as u8;
+        let bad = [
+            // Past the end.
+            (2, 2, MAX_FRAGMENT_SIZE),
+            (255, 3, MAX_FRAGMENT_SIZE),
+            // No fragments at all.
+            (0, 0, MAX_FRAGMENT_SIZE),
+            // More than a message we'd accept could be cut into.
+            (0, too_many, MAX_FRAGMENT_SIZE),
+            (0, 255, MAX_FRAGMENT_SIZE),
+            // Short, but not the last.
+            (0, 2, 10),
+            // Longer than a fragment can be.
+            (0, 2, MAX_FRAGMENT_SIZE + 1),
+        ];
+        for channel in [
+            Channel::Unreliable,
+            Channel::UnreliableSequenced,
+            Channel::ReliableOrdered,
+        ] {
+            for (id, &(index, count, len)) in bad.iter().enumerate() {
+                let id = id as u16;
+                assert!(
+                    peer.send(fragment(channel, id, index, count, len))
+                        .is_empty()
+                );
+            }
+        }
+        assert!(peer.connection.partial.is_empty());
+        assert!(peer.connection.reliable_partial.is_none());
+    }
+
+    #[test]
+    fn duplicate_fragments_count_once() {
+        let mut peer = Peer::new();
+        let first = fragment(Channel::Unreliable, 7, 0, 2, MAX_FRAGMENT_SIZE);
+        assert!(peer.send(first.clone()).is_empty());
+        assert!(peer.send(first).is_empty());
+
+        // A second copy of the same piece with different bytes doesn't
+        // replace the first.
+        let mut overlap = fragment(Channel::Unreliable, 7, 0, 2, MAX_FRAGMENT_SIZE);
+        overlap.payload.fill(9);
+        assert!(peer.send(overlap).is_empty());
+
+        let delivered = peer.send(fragment(Channel::Unreliable, 7, 1, 2, 5));
+        assert_eq!(delivered.len(), 1);
+        let (_, payload) = &delivered[0];
+        assert_eq!(payload.len(), MAX_FRAGMENT_SIZE + 5);
+        assert!(payload[..MAX_FRAGMENT_SIZE].iter().all(|&byte| byte == 0));
+        assert!(payload[MAX_FRAGMENT_SIZE..].iter().all(|&byte| byte == 1));
+
+        // Late copies start nothing new that could be delivered twice.
+        assert!(
+            peer.send(fragment(Channel::Unreliable, 7, 1, 2, 5))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn disagreeing_counts_drop_the_message() {
+        let mut peer = Peer::new();
+        assert!(
+            peer.send(fragment(Channel::Unreliable, 1, 0, 3, MAX_FRAGMENT_SIZE))
+                .is_empty()
+        );
+        assert!(
+            peer.send(fragment(Channel::Unreliable, 1, 1, 2, 5))
+                .is_empty()
+        );
+        assert!(peer.connection.partial.is_empty());
+    }
+
+    #[test]
+    fn oversized_messages_are_dropped() {
+        let mut peer = Peer::new();
+        // Two fragments' worth, less a byte.
+        peer.connection
+            .set_max_message_size(MAX_FRAGMENT_SIZE * 2 - 1);
+        // Unreliable fragments share their message's id, reliable ones don't.
+        for (channel, ids) in [
+            (Channel::Unreliable, [5, 5]),
+            (Channel::ReliableOrdered, [0, 1]),
+        ] {
+            for (id, index) in ids.into_iter().zip([0, 1]) {
+                let delivered = peer.send(fragment(channel, id, index, 2, MAX_FRAGMENT_SIZE));
+                assert!(delivered.is_empty());
+            }
+        }
+        assert!(peer.connection.partial.is_empty());
+        assert!(peer.connection.reliable_partial.is_none());
+    }
+
+    #[test]
+    fn unfinished_messages_are_bounded() {
+        let mut peer = Peer::new();
+        for id in 0..2000 {
+            for channel in [Channel::Unreliable, Channel::UnreliableSequenced] {
+                peer.send(fragment(channel, id, 0, 2, MAX_FRAGMENT_SIZE));
+                assert!(peer.connection.partial.len() <= MAX_PARTIAL_MESSAGES);
+            }
+        }
+
+        // And they don't outlive their timeout.
+        peer.now += FRAGMENT_TIMEOUT;
+        peer.send(ChannelMessage {
+            channel: Channel::Unreliable,
+            id: 0,
+            fragment: None,
+            payload: vec![1],
+        });
+        assert!(peer.connection.partial.is_empty());
+    }
+
+    #[test]
+    fn reliable_flood_is_bounded() {
+        let mut peer = Peer::new();
+        // Message 0 never comes, so nothing after it can be delivered.
+        for id in 1..=u16::MAX {
+            let delivered = peer.send(fragment(
+                Channel::ReliableOrdered,
+                id,
+                0,
+                2,
+                MAX_FRAGMENT_SIZE,
+            ));
+            assert!(delivered.is_empty());
+        }
+        assert!(peer.connection.received_reliable.len() < RELIABLE_WINDOW as usize);
+        assert!(peer.connection.reliable_partial.is_none());
+
+        // Each piece that starts a message throws out the unfinished one
+        // before it.
+        let mut peer = Peer::new();
+        for id in 0..1000 {
+            peer.send(fragment(
+                Channel::ReliableOrdered,
+                id,
+                0,
+                2,
+                MAX_FRAGMENT_SIZE,
+            ));
+        }
+        let partial = peer.connection.reliable_partial.as_ref().unwrap();
+        assert_eq!(partial.size, MAX_FRAGMENT_SIZE);
+        assert!(peer.connection.received_reliable.is_empty());
+    }
+}
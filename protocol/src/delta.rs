@@ -0,0 +1,685 @@
+//! Snapshots travel quantized and as a delta against a snapshot the client has
+//! acknowledged. Entities that haven't changed since the baseline are left
+//! out entirely; with no usable baseline everything is sent in full.
+
+use std::{collections::VecDeque, f32::consts::SQRT_2};
+
+use crate::{
+    codec::{Decode, DecodeError, Encode, EncodeError, Reader, Writer, decode_list, encode_list},
+    message::{
+        ComponentId, ComponentState, EntityKind, EntityState, MAX_ENTITY_COMPONENTS,
+        MAX_SNAPSHOT_ENTITIES, NetEntity, Snapshot,
+    },
+};
+
+/// Snapshots each side remembers as possible baselines.
+pub const SNAPSHOT_HISTORY: usize = 32;
+
+/// Positions are sent in 1/256ths of a metre...
+const TRANSLATION_SCALE: f32 = 256.0;
+/// ...and velocities in 1/128ths of a metre per second.
+const VELOCITY_SCALE: f32 = 128.0;
+/// Bits per component of a smallest-three rotation.
+const ROTATION_BITS: u32 = 10;
+
+const HAS_KIND: u8 = 1;
+const HAS_TRANSLATION: u8 = 2;
+const HAS_ROTATION: u8 = 4;
+const HAS_VELOCITY: u8 = 8;
+const HAS_COMPONENTS: u8 = 16;
+
+/// An entity's state as it goes over the wire.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuantizedEntity {
+    pub id: NetEntity,
+    pub kind: EntityKind,
+    pub translation: [i32; 3],
+    pub rotation: u32,
+    pub velocity: [i32; 3],
+    /// Already encoded, so sent as they are.
+    pub components: Vec<ComponentState>,
+}
+
+impl QuantizedEntity {
+    pub fn from_state(state: &EntityState) -> Self {
+        Self {
+            id: state.id,
+            kind: state.kind,
+            translation: state
+                .translation
+                .map(|v| (v * TRANSLATION_SCALE).round() as i32),
+            rotation: quantize_rotation(state.rotation),
+            velocity: state.velocity.map(|v| (v * VELOCITY_SCALE).round() as i32),
+            components: state.components.clone(),
+        }
+    }
+
+    pub fn to_state(&self) -> EntityState {
+        EntityState {
+            id: self.id,
+            kind: self.kind,
+            translation: self.translation.map(|v| v as f32 / TRANSLATION_SCALE),
+            rotation: dequantize_rotation(self.rotation),
+            velocity: self.velocity.map(|v| v as f32 / VELOCITY_SCALE),
+            components: self.components.clone(),
+        }
+    }
+}
+
+/// Packs a unit quaternion as its index of largest component plus the other
+/// three, which can't exceed 1/sqrt(2) in magnitude.
+fn quantize_rotation(rotation: [f32; 4]) -> u32 {
+    let length = rotation.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if length == 0. || !length.is_finite() {
+        return quantize_rotation([0., 0., 0., 1.]);
+    }
+
+    let largest = (0..4)
+        .max_by(|&a, &b| rotation[a].abs().total_cmp(&rotation[b].abs()))
+        .unwrap_or(3);
+    // q and -q are the same rotation; make the dropped component positive.
+    let sign = if rotation[largest] < 0. { -1. } else { 1. };
+    let max = (1 << ROTATION_BITS) - 1;
+
+    let mut packed = largest as u32;
+    for (index, component) in rotation.iter().enumerate() {
+        if index == largest {
+            continue;
+        }
+        let normalized = component * sign / length * SQRT_2;
+        let bits = ((normalized + 1.) / 2. * max as f32)
+            .round()
+            .clamp(0., max as f32) as u32;
+        packed = packed << ROTATION_BITS | bits;
+    }
+    packed
+}
+
+fn dequantize_rotation(packed: u32) -> [f32; 4] {
+    let max = (1 << ROTATION_BITS) - 1;
+    let largest = (packed >> (3 * ROTATION_BITS)) as usize & 3;
+
+    let mut rotation = [0.; 4];
+    let mut shift = 3 * ROTATION_BITS;
+    let mut sum = 0.;
+    for (index, component) in rotation.iter_mut().enumerate() {
+        if index == largest {
+            continue;
+        }
+        shift -= ROTATION_BITS;
+        let bits = (packed >> shift) & max;
+        *component = (bits as f32 / max as f32 * 2. - 1.) / SQRT_2;
+        sum += *component * *component;
+    }
+    rotation[largest] = (1. - sum).max(0.).sqrt();
+    rotation
+}
+
+/// What changed about one entity. Positions and velocities are relative to
+/// the baseline's if the entity was in it, and absolute otherwise.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntityDelta {
+    pub id: NetEntity,
+    pub kind: Option<EntityKind>,
+    pub translation: Option<[i32; 3]>,
+    pub rotation: Option<u32>,
+    pub velocity: Option<[i32; 3]>,
+    /// Components added or changed since the baseline, whole.
+    pub components: Vec<ComponentState>,
+    /// Components the baseline had that the entity has lost.
+    pub removed_components: Vec<ComponentId>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeltaSnapshot {
+    pub tick: u32,
+    /// The acknowledged snapshot this one is relative to, or `None` for a full state.
+    pub baseline: Option<u32>,
+    pub last_input: u32,
+    pub entities: Vec<EntityDelta>,
+    /// Entities in the baseline that no longer exist.
+    pub removed: Vec<NetEntity>,
+}
+
+/// Recently sent (on the server) or received (on the client) snapshots, by
+/// tick, so either side can produce or apply a delta against any of them.
+#[derive(Default)]
+pub struct SnapshotHistory {
+    snapshots: VecDeque<(u32, Vec<QuantizedEntity>)>,
+}
+
+impl SnapshotHistory {
+    /// Encodes `snapshot` against `baseline` if we still have it, falling back
+    /// to a full state otherwise, and remembers it as a future baseline.
+    pub fn encode(&mut self, snapshot: &Snapshot, baseline: Option<u32>) -> DeltaSnapshot {
+        let mut current: Vec<QuantizedEntity> = snapshot
+            .entities
+            .iter()
+            .map(QuantizedEntity::from_state)
+            .collect();
+        current.sort_by_key(|entity| entity.id);
+
+        let (baseline, base) = match baseline.and_then(|tick| Some((tick, self.get(tick)?))) {
+            Some((tick, base)) => (Some(tick), base),
+            None => (None, &[][..]),
+        };
+
+        let entities = current
+            .iter()
+            .filter_map(|entity| {
+                let previous = base
+                    .binary_search_by_key(&entity.id, |previous| previous.id)
+                    .ok()
+                    .map(|index| &base[index]);
+                entity_delta(previous, entity)
+            })
+            .collect();
+        let removed = base
+            .iter()
+            .filter(|previous| {
+                current
+                    .binary_search_by_key(&previous.id, |entity| entity.id)
+                    .is_err()
+            })
+            .map(|previous| previous.id)
+            .collect();
+
+        let delta = DeltaSnapshot {
+            tick: snapshot.tick,
+            baseline,
+            last_input: snapshot.last_input,
+            entities,
+            removed,
+        };
+        self.insert(snapshot.tick, current);
+        delta
+    }
+
+    /// Rebuilds the full snapshot from a delta and remembers it as a future
+    /// baseline. Returns `None` if the baseline has already been forgotten or
+    /// the delta doesn't fit it.
+    pub fn decode(&mut self, delta: &DeltaSnapshot) -> Option<Snapshot> {
+        let base = match delta.baseline {
+            Some(tick) => self.get(tick)?,
+            None => &[],
+        };
+
+        let mut entities: Vec<QuantizedEntity> = base
+            .iter()
+            .filter(|entity| !delta.removed.contains(&entity.id))
+            .cloned()
+            .collect();
+        for change in &delta.entities {
+            match entities.binary_search_by_key(&change.id, |entity| entity.id) {
+                Ok(index) => apply_delta(&mut entities[index], change),
+                Err(index) => entities.insert(index, new_entity(change)?),
+            }
+        }
+
+        let snapshot = Snapshot {
+            tick: delta.tick,
+            last_input: delta.last_input,
+            entities: entities.iter().map(QuantizedEntity::to_state).collect(),
+        };
+        self.insert(delta.tick, entities);
+        Some(snapshot)
+    }
+
+    fn get(&self, tick: u32) -> Option<&[QuantizedEntity]> {
+        self.snapshots
+            .iter()
+            .find(|(snapshot_tick, _)| *snapshot_tick == tick)
+            .map(|(_, entities)| entities.as_slice())
+    }
+
+    fn insert(&mut self, tick: u32, entities: Vec<QuantizedEntity>) {
+        if self.snapshots.len() == SNAPSHOT_HISTORY {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((tick, entities));
+    }
+}
+
+fn entity_delta(
+    previous: Option<&QuantizedEntity>,
+    entity: &QuantizedEntity,
+) -> Option<EntityDelta> {
+    let Some(previous) = previous else {
+        return Some(EntityDelta {
+            id: entity.id,
+            kind: Some(entity.kind),
+            translation: Some(entity.translation),
+            rotation: Some(entity.rotation),
+            velocity: Some(entity.velocity),
+            components: entity.components.clone(),
+            removed_components: Vec::new(),
+        });
+    };
+
+    let delta = EntityDelta {
+        id: entity.id,
+        kind: (entity.kind != previous.kind).then_some(entity.kind),
+        translation: (entity.translation != previous.translation)
+            .then(|| subtract(entity.translation, previous.translation)),
+        rotation: (entity.rotation != previous.rotation).then_some(entity.rotation),
+        velocity: (entity.velocity != previous.velocity)
+            .then(|| subtract(entity.velocity, previous.velocity)),
+        components: entity
+            .components
+            .iter()
+            .filter(|component| !previous.components.contains(component))
+            .cloned()
+            .collect(),
+        removed_components: previous
+            .components
+            .iter()
+            .filter(|old| {
+                !entity
+                    .components
+                    .iter()
+                    .any(|component| component.id == old.id)
+            })
+            .map(|old| old.id)
+            .collect(),
+    };
+    let changed = delta.kind.is_some()
+        || delta.translation.is_some()
+        || delta.rotation.is_some()
+        || delta.velocity.is_some()
+        || !delta.components.is_empty()
+        || !delta.removed_components.is_empty();
+    changed.then_some(delta)
+}
+
+fn apply_delta(entity: &mut QuantizedEntity, delta: &EntityDelta) {
+    if let Some(kind) = delta.kind {
+        entity.kind = kind;
+    }
+    if let Some(translation) = delta.translation {
+        entity.translation = add(entity.translation, translation);
+    }
+    if let Some(rotation) = delta.rotation {
+        entity.rotation = rotation;
+    }
+    if let Some(velocity) = delta.velocity {
+        entity.velocity = add(entity.velocity, velocity);
+    }
+    entity
+        .components
+        .retain(|component| !delta.removed_components.contains(&component.id));
+    for change in &delta.components {
+        match entity
+            .components
+            .binary_search_by_key(&change.id, |component| component.id)
+        {
+            Ok(index) => entity.components[index] = change.clone(),
+            Err(index) => entity.components.insert(index, change.clone()),
+        }
+    }
+}
+
+/// An entity the baseline didn't have must come with everything.
+fn new_entity(delta: &EntityDelta) -> Option<QuantizedEntity> {
+    Some(QuantizedEntity {
+        id: delta.id,
+        kind: delta.kind?,
+        translation: delta.translation?,
+        rotation: delta.rotation?,
+        velocity: delta.velocity?,
+        components: delta.components.clone(),
+    })
+}
+
+fn subtract(a: [i32; 3], b: [i32; 3]) -> [i32; 3] {
+    [
+        a[0].wrapping_sub(b[0]),
+        a[1].wrapping_sub(b[1]),
+        a[2].wrapping_sub(b[2]),
+    ]
+}
+
+fn add(a: [i32; 3], b: [i32; 3]) -> [i32; 3] {
+    [
+        a[0].wrapping_add(b[0]),
+        a[1].wrapping_add(b[1]),
+        a[2].wrapping_add(b[2]),
+    ]
+}
+
+fn write_ivec3(writer: &mut Writer, value: [i32; 3]) {
+    for component in value {
+        writer.write_zigzag(component);
+    }
+}
+
+fn read_ivec3(reader: &mut Reader) -> Result<[i32; 3], DecodeError> {
+    Ok([
+        reader.read_zigzag()?,
+        reader.read_zigzag()?,
+        reader.read_zigzag()?,
+    ])
+}
+
+impl Encode for EntityDelta {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        self.id.encode(writer)?;
+
+        let mut flags = 0;
+        if self.kind.is_some() {
+            flags |= HAS_KIND;
+        }
+        if self.translation.is_some() {
+            flags |= HAS_TRANSLATION;
+        }
+        if self.rotation.is_some() {
+            flags |= HAS_ROTATION;
+        }
+        if self.velocity.is_some() {
+            flags |= HAS_VELOCITY;
+        }
+        if !self.components.is_empty() || !self.removed_components.is_empty() {
+            flags |= HAS_COMPONENTS;
+        }
+        writer.write_u8(flags);
+
+        if let Some(kind) = &self.kind {
+            kind.encode(writer)?;
+        }
+        if let Some(translation) = self.translation {
+            write_ivec3(writer, translation);
+        }
+        if let Some(rotation) = self.rotation {
+            writer.write_u32(rotation);
+        }
+        if let Some(velocity) = self.velocity {
+            write_ivec3(writer, velocity);
+        }
+        if flags & HAS_COMPONENTS != 0 {
+            encode_list(writer, &self.components, MAX_ENTITY_COMPONENTS)?;
+            encode_list(writer, &self.removed_components, MAX_ENTITY_COMPONENTS)?;
+        }
+        Ok(())
+    }
+}
+
+impl Decode for EntityDelta {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let id = NetEntity::decode(reader)?;
+        let flags = reader.read_u8()?;
+        let mut delta = EntityDelta {
+            id,
+            kind: match flags & HAS_KIND {
+                0 => None,
+                _ => Some(EntityKind::decode(reader)?),
+            },
+            translation: match flags & HAS_TRANSLATION {
+                0 => None,
+                _ => Some(read_ivec3(reader)?),
+            },
+            rotation: match flags & HAS_ROTATION {
+                0 => None,
+                _ => Some(reader.read_u32()?),
+            },
+            velocity: match flags & HAS_VELOCITY {
+                0 => None,
+                _ => Some(read_ivec3(reader)?),
+            },
+            components: Vec::new(),
+            removed_components: Vec::new(),
+        };
+        if flags & HAS_COMPONENTS != 0 {
+            delta.components = decode_list(reader, MAX_ENTITY_COMPONENTS)?;
+            delta.removed_components = decode_list(reader, MAX_ENTITY_COMPONENTS)?;
+            // Looked up by binary search once applied, so they have to be in
+            // order, and once each, whatever the sender did.
+            delta.components.sort_by_key(|component| component.id);
+            delta.components.dedup_by_key(|component| component.id);
+        }
+        Ok(delta)
+    }
+}
+
+impl Encode for DeltaSnapshot {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u32(self.tick);
+        // How many ticks back the baseline is, with 0 meaning none.
+        writer.write_varint(
+            self.baseline
+                .map_or(0, |baseline| self.tick.wrapping_sub(baseline)),
+        );
+        writer.write_u32(self.last_input);
+        encode_list(writer, &self.entities, MAX_SNAPSHOT_ENTITIES)?;
+        encode_list(writer, &self.removed, MAX_SNAPSHOT_ENTITIES)
+    }
+}
+
+impl Decode for DeltaSnapshot {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let tick = reader.read_u32()?;
+        let baseline = match reader.read_varint()? {
+            0 => None,
+            ago => Some(tick.wrapping_sub(ago)),
+        };
+        Ok(DeltaSnapshot {
+            tick,
+            baseline,
+            last_input: reader.read_u32()?,
+            entities: decode_list(reader, MAX_SNAPSHOT_ENTITIES)?,
+            removed: decode_list(reader, MAX_SNAPSHOT_ENTITIES)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientId;
+
+    fn entity(id: u32, x: f32, components: &[(u8, u8)]) -> EntityState {
+        EntityState {
+            id: NetEntity(id),
+            kind: EntityKind::Snowball { owner: ClientId(1) },
+            translation: [x, 1.0, -2.5],
+            rotation: [0., 0., 0., 1.],
+            velocity: [0.5, 0., 0.],
+            components: components
+                .iter()
+                .map(|&(id, data)| ComponentState {
+                    id: ComponentId(id),
+                    data: vec![data],
+                })
+                .collect(),
+        }
+    }
+
+    fn snapshot(tick: u32, entities: Vec<EntityState>) -> Snapshot {
+        Snapshot {
+            tick,
+            last_input: tick * 2,
+            entities,
+        }
+    }
+
+    /// What `snapshot` looks like after the trip through quantization.
+    fn quantized(snapshot: &Snapshot) -> Snapshot {
+        Snapshot {
+            entities: snapshot
+                .entities
+                .iter()
+                .map(|entity| QuantizedEntity::from_state(entity).to_state())
+                .collect(),
+            ..snapshot.clone()
+        }
+    }
+
+    /// Sends `delta` over the wire to `history`.
+    fn receive(history: &mut SnapshotHistory, delta: &DeltaSnapshot) -> Option<Snapshot> {
+        let bytes = crate::encode_message(delta).unwrap();
+        history.decode(&crate::decode_message(&bytes).unwrap())
+    }
+
+    #[test]
+    fn full_snapshot_without_baseline() {
+        let (mut server, mut client) = (SnapshotHistory::default(), SnapshotHistory::default());
+        let first = snapshot(1, vec![entity(2, 1.0, &[(0, 1)]), entity(1, -3.3, &[])]);
+
+        let delta = server.encode(&first, None);
+        assert_eq!(delta.baseline, None);
+        assert!(delta.removed.is_empty());
+        assert!(delta.entities.iter().all(|entity| entity.kind.is_some()
+            && entity.translation.is_some()
+            && entity.rotation.is_some()
+            && entity.velocity.is_some()));
+
+        let decoded = receive(&mut client, &delta).unwrap();
+        let mut expected = quantized(&first);
+        expected.entities.sort_by_key(|entity| entity.id);
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn delta_against_an_acked_baseline() {
+        let (mut server, mut client) = (SnapshotHistory::default(), SnapshotHistory::default());
+        let first = snapshot(1, vec![entity(1, 0.0, &[]), entity(2, 5.0, &[(0, 1)])]);
+        receive(&mut client, &server.encode(&first, None)).unwrap();
+
+        let second = snapshot(2, vec![entity(1, 0.25, &[]), entity(2, 5.0, &[(0, 1)])]);
+        let delta = server.encode(&second, Some(1));
+        assert_eq!(delta.baseline, Some(1));
+        // Only what moved, and only how it moved.
+        assert_eq!(delta.entities.len(), 1);
+        let moved = &delta.entities[0];
+        assert_eq!(moved.id, NetEntity(1));
+        assert_eq!(moved.translation, Some([64, 0, 0]));
+        assert_eq!(
+            (moved.kind, moved.rotation, moved.velocity),
+            (None, None, None)
+        );
+
+        assert_eq!(receive(&mut client, &delta), Some(quantized(&second)));
+    }
+
+    #[test]
+    fn entities_and_components_come_and_go() {
+        let (mut server, mut client) = (SnapshotHistory::default(), SnapshotHistory::default());
+        let first = snapshot(
+            1,
+            vec![entity(1, 0.0, &[(0, 1), (1, 2)]), entity(2, 1.0, &[])],
+        );
+        receive(&mut client, &server.encode(&first, None)).unwrap();
+
+        // 2 is gone, 3 is new, and 1 lost a component and changed another.
+        let second = snapshot(
+            2,
+            vec![entity(1, 0.0, &[(1, 3)]), entity(3, 2.0, &[(4, 4)])],
+        );
+        let delta = server.encode(&second, Some(1));
+        assert_eq!(delta.removed, vec![NetEntity(2)]);
+        let changed = &delta.entities[0];
+        assert_eq!(changed.removed_components, vec![ComponentId(0)]);
+        assert_eq!(changed.components, second.entities[0].components);
+        assert_eq!(changed.translation, None);
+        assert_eq!(delta.entities[1].id, NetEntity(3));
+
+        assert_eq!(receive(&mut client, &delta), Some(quantized(&second)));
+    }
+
+    #[test]
+    fn forgotten_baselines_are_refused() {
+        let (mut server, mut client) = (SnapshotHistory::default(), SnapshotHistory::default());
+        let first = snapshot(1, vec![entity(1, 0.0, &[])]);
+        server.encode(&first, None);
+        // The client never got tick 1.
+        let delta = server.encode(&snapshot(2, vec![entity(1, 1.0, &[])]), Some(1));
+        assert_eq!(receive(&mut client, &delta), None);
+
+        // And one it had, but too long ago.
+        let mut client = SnapshotHistory::default();
+        let mut server = SnapshotHistory::default();
+        receive(&mut client, &server.encode(&first, None)).unwrap();
+        for tick in 2..2 + SNAPSHOT_HISTORY as u32 {
+            let next = snapshot(tick, vec![entity(1, tick as f32, &[])]);
+            receive(&mut client, &server.encode(&next, None)).unwrap();
+        }
+        let late = DeltaSnapshot {
+            tick: 100,
+            baseline: Some(1),
+            ..DeltaSnapshot::default()
+        };
+        assert_eq!(client.decode(&late), None);
+
+        // The server falls back to a full state when it's forgotten too.
+        let full = server.encode(&snapshot(101, vec![entity(1, 0.0, &[])]), Some(1));
+        assert_eq!(full.baseline, None);
+    }
+
+    #[test]
+    fn unsorted_components_off_the_wire_are_sorted() {
+        let mut client = SnapshotHistory::default();
+        let component = |id, data| ComponentState {
+            id: ComponentId(id),
+            data: vec![data],
+        };
+        let first = DeltaSnapshot {
+            tick: 1,
+            entities: vec![EntityDelta {
+                id: NetEntity(1),
+                kind: Some(EntityKind::Prop { index: 0 }),
+                translation: Some([0; 3]),
+                rotation: Some(quantize_rotation([0., 0., 0., 1.])),
+                velocity: Some([0; 3]),
+                components: vec![component(5, 1), component(2, 1), component(5, 9)],
+                removed_components: Vec::new(),
+            }],
+            ..DeltaSnapshot::default()
+        };
+        receive(&mut client, &first).unwrap();
+
+        let second = DeltaSnapshot {
+            tick: 2,
+            baseline: Some(1),
+            entities: vec![EntityDelta {
+                id: NetEntity(1),
+                kind: None,
+                translation: None,
+                rotation: None,
+                velocity: None,
+                components: vec![component(5, 2), component(2, 2)],
+                removed_components: Vec::new(),
+            }],
+            ..DeltaSnapshot::default()
+        };
+        let decoded = receive(&mut client, &second).unwrap();
+        assert_eq!(
+            decoded.entities[0].components,
+            vec![component(2, 2), component(5, 2)]
+        );
+    }
+
+    #[test]
+    fn rotations_survive_quantization() {
+        let rotations = [
+            [0., 0., 0., 1.],
+            [0., 0., 0., -1.],
+            [1., 0., 0., 0.],
+            [0.5, 0.5, 0.5, 0.5],
+            [0.1, -0.7, 0.2, 0.4],
+            [-0.3, 0.3, -0.8, 0.1],
+            // Not normalized, which it copes with.
+            [0., 2., 0., 2.],
+        ];
+        for rotation in rotations {
+            let length = rotation.iter().map(|v| v * v).sum::<f32>().sqrt();
+            let unit = rotation.map(|v| v / length);
+            let back = dequantize_rotation(quantize_rotation(rotation));
+            // q and -q are the same rotation.
+            let dot: f32 = unit.iter().zip(back).map(|(a, b)| a * b).sum();
+            assert!(dot.abs() > 0.9999, "{rotation:?} came back as {back:?}");
+        }
+        // Garbage comes back as no rotation rather than NaN.
+        for rotation in [[0.; 4], [f32::NAN, 0., 0., 1.]] {
+            let back = dequantize_rotation(quantize_rotation(rotation));
+            assert!(back[3] > 0.9999, "{rotation:?} came back as {back:?}");
+        }
+    }
+}
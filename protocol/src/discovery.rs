@@ -0,0 +1,257 @@
+//! Finding servers on the local network. Clients broadcast a `Query` to the
+//! discovery port and every server that hears it answers with an `Announce`.
+//!
+//! Discovery datagrams carry the usual protocol id but are never versioned,
+//! so a client can still list servers it's too old or too new to join.
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    PROTOCOL_VERSION, RECV_BUFFER_SIZE,
+    codec::{Decode, DecodeError, Encode, EncodeError, Reader, Writer},
+};
+
+/// Where servers listen for discovery queries.
+pub const DISCOVERY_PORT: u16 = 8079;
+
+/// How often `Discovery` asks again.
+const QUERY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Servers that haven't answered for this long are dropped from the list.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiscoveryMessage {
+    /// "Is anyone there?" `nonce` comes back in the answer, so replies to
+    /// somebody else's query can be told apart.
+    Query {
+        nonce: u64,
+    },
+    Announce(Announcement),
+}
+
+/// What a server says about itself in answer to a query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Announcement {
+    pub nonce: u64,
+    /// Random for each run of a server, so one that answers on several
+    /// addresses is only listed once.
+    pub server_id: u64,
+    pub protocol_version: u16,
+    /// Where to connect, on the address the answer came from.
+    pub game_port: u16,
+    pub name: String,
+    pub map: String,
+    pub players: u16,
+    pub max_players: u16,
+}
+
+/// A server `Discovery` has heard from recently.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiscoveredServer {
+    /// Game address, ready to connect to.
+    pub addr: SocketAddr,
+    pub server_id: u64,
+    pub name: String,
+    pub map: String,
+    pub players: u16,
+    pub max_players: u16,
+    pub protocol_version: u16,
+    pub last_seen: Instant,
+}
+
+impl DiscoveredServer {
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version == PROTOCOL_VERSION
+    }
+}
+
+/// Keeps a live list of the servers answering discovery queries. Call `poll`
+/// regularly; it never blocks.
+pub struct Discovery {
+    socket: UdpSocket,
+    targets: Vec<SocketAddr>,
+    nonce: u64,
+    last_query: Option<Instant>,
+    /// By server id.
+    servers: HashMap<u64, DiscoveredServer>,
+}
+
+impl Discovery {
+    /// Queries go to every address in `targets`. `Discovery::lan_targets`
+    /// covers the usual case.
+    pub fn new(targets: Vec<SocketAddr>) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket,
+            targets,
+            nonce: crate::random(),
+            last_query: None,
+            servers: HashMap::new(),
+        })
+    }
+
+    /// The local network's broadcast address, plus loopback, which broadcasts
+    /// don't always reach.
+    pub fn lan_targets(port: u16) -> Vec<SocketAddr> {
+        vec![
+            SocketAddrV4::new(Ipv4Addr::BROADCAST, port).into(),
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, port).into(),
+        ]
+    }
+
+    /// Sends a query if one is due, takes in any answers and forgets servers
+    /// that have gone quiet. Returns the servers heard from for the first time.
+    pub fn poll(&mut self, now: Instant) -> io::Result<Vec<DiscoveredServer>> {
+        if self
+            .last_query
+            .is_none_or(|last| now.duration_since(last) >= QUERY_INTERVAL)
+        {
+            self.last_query = Some(now);
+            self.query()?;
+        }
+
+        let mut found = Vec::new();
+        let mut buf = [0; RECV_BUFFER_SIZE];
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // Windows reports ICMP port unreachable from an earlier send here.
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e),
+            };
+            let Ok(DiscoveryMessage::Announce(announcement)) =
+                crate::decode::<DiscoveryMessage>(&buf[..len])
+            else {
+                continue;
+            };
+            if announcement.nonce != self.nonce {
+                continue;
+            }
+
+            // The same server can answer the broadcast and the loopback
+            // query. Whichever answered first is as good an address as any.
+            let addr = match self.servers.get(&announcement.server_id) {
+                Some(known) => known.addr,
+                None => SocketAddr::new(from.ip(), announcement.game_port),
+            };
+            let server = DiscoveredServer {
+                addr,
+                server_id: announcement.server_id,
+                name: announcement.name,
+                map: announcement.map,
+                players: announcement.players,
+                max_players: announcement.max_players,
+                protocol_version: announcement.protocol_version,
+                last_seen: now,
+            };
+            if self
+                .servers
+                .insert(announcement.server_id, server.clone())
+                .is_none()
+            {
+                found.push(server);
+            }
+        }
+
+        self.servers
+            .retain(|_, server| now.duration_since(server.last_seen) < SERVER_TIMEOUT);
+        Ok(found)
+    }
+
+    /// Every server heard from recently, in no particular order.
+    pub fn servers(&self) -> impl Iterator<Item = &DiscoveredServer> {
+        self.servers.values()
+    }
+
+    fn query(&self) -> io::Result<()> {
+        let query = crate::encode(&DiscoveryMessage::Query { nonce: self.nonce })
+            .map_err(io::Error::other)?;
+        for target in &self.targets {
+            match self.socket.send_to(&query, target) {
+                Ok(_) => {}
+                // No route for broadcasts, or nothing listening on loopback.
+                // Other targets may still work.
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock
+                            | ErrorKind::ConnectionRefused
+                            | ErrorKind::NetworkUnreachable
+                            | ErrorKind::PermissionDenied
+                    ) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Encode for DiscoveryMessage {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        match self {
+            DiscoveryMessage::Query { nonce } => {
+                writer.write_u8(0);
+                writer.write_u64(*nonce);
+                Ok(())
+            }
+            DiscoveryMessage::Announce(announcement) => {
+                writer.write_u8(1);
+                announcement.encode(writer)
+            }
+        }
+    }
+}
+
+impl Decode for DiscoveryMessage {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(DiscoveryMessage::Query {
+                nonce: reader.read_u64()?,
+            }),
+            1 => Ok(DiscoveryMessage::Announce(Announcement::decode(reader)?)),
+            tag => Err(DecodeError::UnknownTag {
+                kind: "DiscoveryMessage",
+                tag,
+            }),
+        }
+    }
+}
+
+impl Encode for Announcement {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u64(self.nonce);
+        writer.write_u64(self.server_id);
+        writer.write_u16(self.protocol_version);
+        writer.write_u16(self.game_port);
+        writer.write_string(&self.name)?;
+        writer.write_string(&self.map)?;
+        writer.write_u16(self.players);
+        writer.write_u16(self.max_players);
+        Ok(())
+    }
+}
+
+impl Decode for Announcement {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Announcement {
+            nonce: reader.read_u64()?,
+            server_id: reader.read_u64()?,
+            protocol_version: reader.read_u16()?,
+            game_port: reader.read_u16()?,
+            name: reader.read_string()?,
+            map: reader.read_string()?,
+            players: reader.read_u16()?,
+            max_players: reader.read_u16()?,
+        })
+    }
+}
@@ -0,0 +1,128 @@
+//! Asking a server how it's doing without joining it. A query is one
+//! datagram and so is the answer, and the server keeps nothing in between,
+//! so server browsers and monitoring tools can ask as often as they're
+//! allowed to.
+//!
+//! Info datagrams start with their own id rather than `PROTOCOL_ID`, which
+//! keeps them clear of the game's session handling, and aren't versioned, so
+//! any client can ask any server.
+
+use crate::{
+    DecodeError, EncodeError,
+    codec::{Decode, Encode, Reader, Writer, decode_list, encode_list},
+};
+
+/// First four bytes of every info query and answer.
+pub const INFO_ID: u32 = u32::from_be_bytes(*b"SNOQ");
+
+/// Most players a `ServerInfo` carries. The server leaves out the lowest
+/// scores when there are more, or when their names don't all fit.
+pub const MAX_INFO_PLAYERS: usize = 64;
+
+/// Zero bytes a query carries after its timestamp. Queries are cheap to send
+/// and answers aren't; padding keeps the answer from being many times larger
+/// than the query that asked for it.
+const QUERY_PADDING: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InfoQuery {
+    /// Anything the asker likes, usually its clock. It comes back unchanged
+    /// in the answer, so the round trip can be timed.
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// From the query.
+    pub timestamp: u64,
+    pub protocol_version: u16,
+    pub name: String,
+    pub map: String,
+    pub mode: String,
+    pub max_players: u16,
+    /// Everyone on the server, even if `players` had to leave some out.
+    pub player_count: u16,
+    /// Highest scores first.
+    pub players: Vec<PlayerInfo>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlayerInfo {
+    pub name: String,
+    pub score: i32,
+}
+
+/// Whether `bytes` claims to be an info query or answer, to pick them out
+/// from game traffic before decoding.
+pub fn is_info(bytes: &[u8]) -> bool {
+    bytes.starts_with(&INFO_ID.to_le_bytes())
+}
+
+pub fn encode_info<T: Encode>(message: &T) -> Result<Vec<u8>, EncodeError> {
+    crate::encode_datagram(INFO_ID, message)
+}
+
+pub fn decode_info<T: Decode>(bytes: &[u8]) -> Result<T, DecodeError> {
+    crate::decode_datagram(INFO_ID, bytes)
+}
+
+impl Encode for InfoQuery {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u64(self.timestamp);
+        writer.write_bytes(&[0; QUERY_PADDING]);
+        Ok(())
+    }
+}
+
+impl Decode for InfoQuery {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let timestamp = reader.read_u64()?;
+        reader.read_bytes(QUERY_PADDING)?;
+        Ok(InfoQuery { timestamp })
+    }
+}
+
+impl Encode for ServerInfo {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u64(self.timestamp);
+        writer.write_u16(self.protocol_version);
+        writer.write_string(&self.name)?;
+        writer.write_string(&self.map)?;
+        writer.write_string(&self.mode)?;
+        writer.write_u16(self.max_players);
+        writer.write_u16(self.player_count);
+        encode_list(writer, &self.players, MAX_INFO_PLAYERS)
+    }
+}
+
+impl Decode for ServerInfo {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(ServerInfo {
+            timestamp: reader.read_u64()?,
+            protocol_version: reader.read_u16()?,
+            name: reader.read_string()?,
+            map: reader.read_string()?,
+            mode: reader.read_string()?,
+            max_players: reader.read_u16()?,
+            player_count: reader.read_u16()?,
+            players: decode_list(reader, MAX_INFO_PLAYERS)?,
+        })
+    }
+}
+
+impl Encode for PlayerInfo {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_string(&self.name)?;
+        writer.write_zigzag(self.score);
+        Ok(())
+    }
+}
+
+impl Decode for PlayerInfo {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(PlayerInfo {
+            name: reader.read_string()?,
+            score: reader.read_zigzag()?,
+        })
+    }
+}
@@ -0,0 +1,118 @@
+//! Messages exchanged between the `snowball` client and the `server`, and the
+//! binary encoding they travel in.
+
+pub mod channel;
+pub mod codec;
+pub mod connection;
+pub mod delta;
+pub mod discovery;
+pub mod info;
+pub mod lobby;
+pub mod map;
+pub mod message;
+pub mod netsim;
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+pub use channel::{Channel, Packet};
+pub use codec::{Decode, DecodeError, Encode, EncodeError, Reader, Writer};
+pub use connection::{Connection, ConnectionStats};
+pub use delta::{DeltaSnapshot, SnapshotHistory};
+pub use lobby::{JoinCode, LobbyPlayer, RoomRequest, RoomState, RoomSummary};
+pub use map::{MapChunk, MapHash, MapInfo};
+pub use message::*;
+
+/// First four bytes of every datagram, so stray traffic is dropped before decoding.
+pub const PROTOCOL_ID: u32 = u32::from_be_bytes(*b"SNOW");
+
+/// Bump whenever the encoding of any message changes.
+pub const PROTOCOL_VERSION: u16 = 14;
+
+/// Largest datagram either side will send or accept.
+pub const MAX_PACKET_SIZE: usize = 1024;
+
+/// Room left in a datagram after the protocol id, session id and packet header.
+pub const MAX_PACKET_PAYLOAD: usize = MAX_PACKET_SIZE - 24;
+
+/// Receive buffers are a byte bigger than any datagram we accept, so one that's
+/// too big shows up as too big instead of being cut down to fit.
+pub const RECV_BUFFER_SIZE: usize = MAX_PACKET_SIZE + 1;
+
+/// Largest message payload that fits in a packet, leaving room for its channel
+/// framing. Anything bigger is split into fragments this size.
+pub const MAX_FRAGMENT_SIZE: usize = MAX_PACKET_PAYLOAD - 10;
+
+/// Most fragments a message can be split into.
+pub const MAX_FRAGMENTS: usize = u8::MAX as usize;
+
+/// Largest message there's any way to send. Connections can be held to less;
+/// see `Connection::set_max_message_size`.
+pub const MAX_MESSAGE_SIZE: usize = MAX_FRAGMENTS * MAX_FRAGMENT_SIZE;
+
+/// What connections allow unless told otherwise.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// A random number that's never 0, for ids, tokens and seeds. `RandomState`
+/// is seeded from the OS, which is plenty for something unguessable.
+pub fn random() -> u64 {
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(0);
+        let value = hasher.finish();
+        if value != 0 {
+            return value;
+        }
+    }
+}
+
+pub fn encode<T: Encode>(message: &T) -> Result<Vec<u8>, EncodeError> {
+    encode_datagram(PROTOCOL_ID, message)
+}
+
+pub fn decode<T: Decode>(bytes: &[u8]) -> Result<T, DecodeError> {
+    decode_datagram(PROTOCOL_ID, bytes)
+}
+
+/// A whole datagram: `id`, then `message`.
+fn encode_datagram<T: Encode>(id: u32, message: &T) -> Result<Vec<u8>, EncodeError> {
+    let mut writer = Writer::new();
+    writer.write_u32(id);
+    message.encode(&mut writer)?;
+    writer.finish(MAX_PACKET_SIZE)
+}
+
+fn decode_datagram<T: Decode>(expected_id: u32, bytes: &[u8]) -> Result<T, DecodeError> {
+    if bytes.len() > MAX_PACKET_SIZE {
+        return Err(DecodeError::PacketTooLarge {
+            size: bytes.len(),
+            max: MAX_PACKET_SIZE,
+        });
+    }
+
+    let mut reader = Reader::new(bytes);
+    let id = reader.read_u32()?;
+    if id != expected_id {
+        return Err(DecodeError::BadProtocolId(id));
+    }
+
+    let message = T::decode(&mut reader)?;
+    reader.finish()?;
+    Ok(message)
+}
+
+/// Encodes a message on its own, to be carried as a channel payload.
+pub fn encode_message<T: Encode>(message: &T) -> Result<Vec<u8>, EncodeError> {
+    let mut writer = Writer::new();
+    message.encode(&mut writer)?;
+    writer.finish(MAX_MESSAGE_SIZE)
+}
+
+pub fn decode_message<T: Decode>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let mut reader = Reader::new(bytes);
+    let message = T::decode(&mut reader)?;
+    reader.finish()?;
+    Ok(message)
+}
@@ -0,0 +1,220 @@
+//! Rooms as players see them before and between matches: join codes, the
+//! public room list, and who in a room is ready.
+
+use std::fmt;
+
+use crate::{
+    codec::{Decode, DecodeError, Encode, EncodeError, Reader, Writer, decode_list, encode_list},
+    message::ClientId,
+};
+
+/// Most rooms a `RoomList` reply carries.
+pub const MAX_LISTED_ROOMS: usize = 32;
+
+/// Most players a `RoomState` carries.
+pub const MAX_ROOM_PLAYERS: usize = 64;
+
+/// What a join code is written in. No 0, O, 1 or I, which get mixed up when
+/// a code is read out loud.
+const CODE_ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Characters in a written join code.
+pub const JOIN_CODE_LEN: usize = 5;
+
+/// Short code that identifies a room, for sharing with friends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct JoinCode(u32);
+
+impl JoinCode {
+    /// Every code fits in this many bits, five for each character.
+    pub const BITS: u32 = 5 * JOIN_CODE_LEN as u32;
+
+    /// Keeps the low `BITS` of `value`, so any random number makes a code.
+    pub fn from_bits(value: u32) -> Self {
+        JoinCode(value & ((1 << Self::BITS) - 1))
+    }
+
+    /// Reads a code as typed by a player. Case doesn't matter.
+    pub fn parse(code: &str) -> Option<Self> {
+        if code.len() != JOIN_CODE_LEN {
+            return None;
+        }
+        let mut value = 0;
+        for c in code.bytes() {
+            let digit = CODE_ALPHABET
+                .iter()
+                .position(|&a| a == c.to_ascii_uppercase())?;
+            value = value << 5 | digit as u32;
+        }
+        Some(JoinCode(value))
+    }
+}
+
+impl fmt::Display for JoinCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for i in (0..JOIN_CODE_LEN).rev() {
+            let digit = (self.0 >> (5 * i)) & 31;
+            write!(f, "{}", CODE_ALPHABET[digit as usize] as char)?;
+        }
+        Ok(())
+    }
+}
+
+/// Which room a connecting player wants to end up in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomRequest {
+    /// Any public match with space. The server opens one if it has to.
+    QuickPlay,
+    /// A new room with the requester as its host. Private rooms are left out
+    /// of the room list, so only people given the code can find them.
+    Create {
+        private: bool,
+    },
+    Join(JoinCode),
+}
+
+/// One entry in the public room list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoomSummary {
+    pub code: JoinCode,
+    pub players: u8,
+    pub max_players: u8,
+    /// False while the room is still waiting for its host to start.
+    pub started: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LobbyPlayer {
+    pub client: ClientId,
+    pub name: String,
+    pub ready: bool,
+}
+
+/// Everything about a room a player in it needs for the lobby screen. Sent
+/// again whenever any of it changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoomState {
+    pub code: JoinCode,
+    /// Who can start the match. Quick play rooms have no host and start on
+    /// their own.
+    pub host: Option<ClientId>,
+    pub started: bool,
+    pub players: Vec<LobbyPlayer>,
+}
+
+impl Encode for JoinCode {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_varint(self.0);
+        Ok(())
+    }
+}
+
+impl Decode for JoinCode {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(JoinCode::from_bits(reader.read_varint()?))
+    }
+}
+
+impl Encode for RoomRequest {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        match self {
+            RoomRequest::QuickPlay => writer.write_u8(0),
+            RoomRequest::Create { private } => {
+                writer.write_u8(1);
+                writer.write_bool(*private);
+            }
+            RoomRequest::Join(code) => {
+                writer.write_u8(2);
+                code.encode(writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Decode for RoomRequest {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(RoomRequest::QuickPlay),
+            1 => Ok(RoomRequest::Create {
+                private: reader.read_bool()?,
+            }),
+            2 => Ok(RoomRequest::Join(JoinCode::decode(reader)?)),
+            tag => Err(DecodeError::UnknownTag {
+                kind: "RoomRequest",
+                tag,
+            }),
+        }
+    }
+}
+
+impl Encode for RoomSummary {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        self.code.encode(writer)?;
+        writer.write_u8(self.players);
+        writer.write_u8(self.max_players);
+        writer.write_bool(self.started);
+        Ok(())
+    }
+}
+
+impl Decode for RoomSummary {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(RoomSummary {
+            code: JoinCode::decode(reader)?,
+            players: reader.read_u8()?,
+            max_players: reader.read_u8()?,
+            started: reader.read_bool()?,
+        })
+    }
+}
+
+impl Encode for LobbyPlayer {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        self.client.encode(writer)?;
+        writer.write_string(&self.name)?;
+        writer.write_bool(self.ready);
+        Ok(())
+    }
+}
+
+impl Decode for LobbyPlayer {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(LobbyPlayer {
+            client: ClientId::decode(reader)?,
+            name: reader.read_string()?,
+            ready: reader.read_bool()?,
+        })
+    }
+}
+
+impl Encode for RoomState {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        self.code.encode(writer)?;
+        match self.host {
+            Some(host) => {
+                writer.write_bool(true);
+                host.encode(writer)?;
+            }
+            None => writer.write_bool(false),
+        }
+        writer.write_bool(self.started);
+        encode_list(writer, &self.players, MAX_ROOM_PLAYERS)
+    }
+}
+
+impl Decode for RoomState {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let code = JoinCode::decode(reader)?;
+        let host = match reader.read_bool()? {
+            true => Some(ClientId::decode(reader)?),
+            false => None,
+        };
+        Ok(RoomState {
+            code,
+            host,
+            started: reader.read_bool()?,
+            players: decode_list(reader, MAX_ROOM_PLAYERS)?,
+        })
+    }
+}
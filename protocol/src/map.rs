@@ -0,0 +1,111 @@
+//! Getting a room's map to the players in it. The server says which map it's
+//! running and a hash of its encoding; a client that has a map with that hash,
+//! built in or downloaded before, uses it, and one that doesn't asks for it
+//! and is sent it in chunks.
+
+use std::fmt;
+
+use crate::codec::{Decode, DecodeError, Encode, EncodeError, Reader, Writer};
+
+/// Largest encoded map a server will send or a client will download.
+pub const MAX_MAP_SIZE: usize = 256 * 1024;
+
+/// Bytes of map in each `MapChunk`, small enough that a chunk fits in one
+/// packet.
+pub const MAP_CHUNK_SIZE: usize = 768;
+
+/// FNV-1a of a map's encoding. Tells versions of a map apart, and catches a
+/// download that went wrong; it's no defence against a server that wants to
+/// lie.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MapHash(pub u64);
+
+impl MapHash {
+    pub fn of(bytes: &[u8]) -> Self {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in bytes {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        MapHash(hash)
+    }
+}
+
+impl fmt::Display for MapHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Which map a room is running.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapInfo {
+    pub name: String,
+    pub hash: MapHash,
+    /// Bytes in its encoding.
+    pub size: u32,
+}
+
+/// A piece of a map's encoding. Chunks go out in order on the reliable
+/// channel, so each starts where the one before ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapChunk {
+    pub hash: MapHash,
+    pub offset: u32,
+    pub data: Vec<u8>,
+}
+
+impl Encode for MapHash {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u64(self.0);
+        Ok(())
+    }
+}
+
+impl Decode for MapHash {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(MapHash(reader.read_u64()?))
+    }
+}
+
+impl Encode for MapInfo {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_string(&self.name)?;
+        self.hash.encode(writer)?;
+        writer.write_varint(self.size);
+        Ok(())
+    }
+}
+
+impl Decode for MapInfo {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(MapInfo {
+            name: reader.read_string()?,
+            hash: MapHash::decode(reader)?,
+            size: reader.read_varint()?,
+        })
+    }
+}
+
+impl Encode for MapChunk {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        self.hash.encode(writer)?;
+        writer.write_varint(self.offset);
+        writer.write_len(self.data.len(), MAP_CHUNK_SIZE)?;
+        writer.write_bytes(&self.data);
+        Ok(())
+    }
+}
+
+impl Decode for MapChunk {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let hash = MapHash::decode(reader)?;
+        let offset = reader.read_varint()?;
+        let len = reader.read_len(MAP_CHUNK_SIZE)?;
+        Ok(MapChunk {
+            hash,
+            offset,
+            data: reader.read_bytes(len)?.to_vec(),
+        })
+    }
+}
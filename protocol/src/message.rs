@@ -0,0 +1,979 @@
+use crate::{
+    channel::{Channel, Packet},
+    codec::{Decode, DecodeError, Encode, EncodeError, Reader, Writer, decode_list, encode_list},
+    delta::DeltaSnapshot,
+    lobby::{MAX_LISTED_ROOMS, RoomRequest, RoomState, RoomSummary},
+    map::{MapChunk, MapHash, MapInfo},
+};
+
+/// Most entities a single snapshot may carry.
+pub const MAX_SNAPSHOT_ENTITIES: usize = 256;
+
+/// Most replicated components one entity may carry, besides its transform
+/// and velocity.
+pub const MAX_ENTITY_COMPONENTS: usize = 16;
+
+/// Largest encoding of one replicated component.
+pub const MAX_COMPONENT_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClientId(pub u32);
+
+/// Secret handed out when a connection is accepted. Every later packet from
+/// that client must carry it, so a spoofed source address isn't enough to act
+/// on someone else's behalf.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SessionId(pub u64);
+
+impl SessionId {
+    /// Used by packets sent before the server has accepted the connection.
+    pub const NONE: SessionId = SessionId(0);
+}
+
+/// Secret handed out with a session, which gets the client its player back
+/// if it has to reconnect. Unlike the session id it never goes out again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ResumeToken(pub u64);
+
+/// Server side id of a replicated entity. Stable for the entity's lifetime,
+/// and never given to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NetEntity(pub u32);
+
+/// Everything a client sends is wrapped in one of these. The server sends bare
+/// `Packet`s, whose payloads are encoded `ServerMessage`s.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientPacket {
+    pub session: SessionId,
+    pub packet: Packet,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    Connect(ConnectRequest),
+    Input(InputCommand),
+    Chat {
+        text: String,
+    },
+    /// Sent when the client has had nothing else to send for a while.
+    Heartbeat,
+    Disconnect,
+    /// The newest snapshot the client has decoded, for the server to delta against.
+    SnapshotAck {
+        tick: u32,
+    },
+    /// Asks for the public rooms. Sent without a session, from the server browser.
+    ListRooms,
+    SetReady {
+        ready: bool,
+    },
+    /// Host only. Ends the lobby and starts the match once everyone is ready.
+    StartMatch,
+    /// Back out of the room. The server confirms with `Disconnected(Left)`.
+    LeaveRoom,
+    /// Asks for a `Pong`, to time the round trip and sync clocks.
+    Ping {
+        /// Microseconds on the client's clock, wrapping, handed back in the
+        /// `Pong`.
+        sent: u32,
+    },
+    /// Asks for the map with this hash, which the client doesn't have.
+    RequestMap {
+        hash: MapHash,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMessage {
+    ConnectAccepted {
+        client: ClientId,
+        session: SessionId,
+        tick: u32,
+        /// Server ticks per second, which the client should simulate at too.
+        tick_rate: u16,
+        resume: ResumeToken,
+    },
+    ConnectRejected(RejectReason),
+    Disconnected(DisconnectReason),
+    Snapshot(DeltaSnapshot),
+    Event(GameEvent),
+    Chat {
+        from: Option<ClientId>,
+        text: String,
+    },
+    RoomList(Vec<RoomSummary>),
+    RoomState(RoomState),
+    /// Answers a `Ping`. Goes out with the next tick's snapshots.
+    Pong {
+        /// From the ping.
+        sent: u32,
+        /// Microseconds the ping waited on the server before this went out,
+        /// which isn't part of the round trip.
+        held: u32,
+        /// The tick just simulated. The server is exactly this far along
+        /// when the pong leaves.
+        tick: u32,
+    },
+    /// The map the room is running. Sent on joining and whenever it changes.
+    Map(MapInfo),
+    /// Part of the map a client asked for.
+    MapChunk(MapChunk),
+}
+
+impl ClientMessage {
+    /// The channel this kind of message is always sent on.
+    pub fn channel(&self) -> Channel {
+        match self {
+            // The client keeps retrying until it hears back.
+            ClientMessage::Connect(_) | ClientMessage::ListRooms => Channel::Unreliable,
+            ClientMessage::Input(_) => Channel::UnreliableSequenced,
+            ClientMessage::Chat { .. }
+            | ClientMessage::SetReady { .. }
+            | ClientMessage::StartMatch
+            | ClientMessage::LeaveRoom
+            | ClientMessage::RequestMap { .. } => Channel::ReliableOrdered,
+            ClientMessage::Heartbeat
+            | ClientMessage::Disconnect
+            | ClientMessage::SnapshotAck { .. }
+            | ClientMessage::Ping { .. } => Channel::Unreliable,
+        }
+    }
+}
+
+impl ServerMessage {
+    /// The channel this kind of message is always sent on.
+    pub fn channel(&self) -> Channel {
+        match self {
+            // Sent before, or after, there's a connection to be reliable over.
+            ServerMessage::ConnectAccepted { .. }
+            | ServerMessage::ConnectRejected(_)
+            | ServerMessage::Disconnected(_)
+            | ServerMessage::RoomList(_) => Channel::Unreliable,
+            // A resent pong would only mislead.
+            ServerMessage::Pong { .. } => Channel::Unreliable,
+            ServerMessage::Snapshot(_) => Channel::UnreliableSequenced,
+            ServerMessage::Event(_)
+            | ServerMessage::Chat { .. }
+            | ServerMessage::RoomState(_)
+            | ServerMessage::Map(_)
+            | ServerMessage::MapChunk(_) => Channel::ReliableOrdered,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectRequest {
+    pub protocol_version: u16,
+    pub name: String,
+    pub room: RoomRequest,
+    /// For servers that have one.
+    pub password: Option<String>,
+    /// From the session the client lost, if it's reconnecting.
+    pub resume: Option<ResumeToken>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    VersionMismatch {
+        server: u16,
+    },
+    ServerFull,
+    /// Nothing is open under the requested join code.
+    NoSuchRoom,
+    RoomFull,
+    /// An admin has banned the client's address.
+    Banned,
+    /// The server has a password and the request didn't carry it.
+    WrongPassword,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    TimedOut,
+    Kicked,
+    ServerShutdown,
+    /// The client asked to leave the room.
+    Left,
+}
+
+/// One fixed tick worth of player input.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InputCommand {
+    /// Increases by one for every command the client sends.
+    pub sequence: u32,
+    /// x is forward, y is right and z is jump, same as the client's `PlayerInput`.
+    pub movement: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub throw: bool,
+    /// The server tick remote players were drawn at when this input was
+    /// made, so hits can be judged against what the player saw.
+    pub view_tick: u32,
+}
+
+/// The world as one client sees it. Goes over the wire as a `DeltaSnapshot`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub tick: u32,
+    /// Sequence of the newest `InputCommand` the server applied for the receiving client.
+    pub last_input: u32,
+    pub entities: Vec<EntityState>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntityKind {
+    Player(ClientId),
+    Snowball {
+        owner: ClientId,
+    },
+    /// The level prop at `index` in the map's list, which the client has its
+    /// own copy of.
+    Prop {
+        index: u16,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityState {
+    pub id: NetEntity,
+    pub kind: EntityKind,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub velocity: [f32; 3],
+    /// Every other replicated component the entity has, sorted by id.
+    pub components: Vec<ComponentState>,
+}
+
+/// Which replicated component type this is. Both sides number them in the
+/// order they were registered, so they have to register the same ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ComponentId(pub u8);
+
+/// A replicated component, encoded by whoever registered it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComponentState {
+    pub id: ComponentId,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GameEvent {
+    PlayerJoined { client: ClientId, name: String },
+    PlayerLeft { client: ClientId },
+    SnowballHit { thrower: ClientId, target: ClientId },
+    ScoreChanged { client: ClientId, score: i32 },
+}
+
+impl Encode for ClientId {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_varint(self.0);
+        Ok(())
+    }
+}
+
+impl Decode for ClientId {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(ClientId(reader.read_varint()?))
+    }
+}
+
+impl Encode for NetEntity {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_varint(self.0);
+        Ok(())
+    }
+}
+
+impl Decode for NetEntity {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(NetEntity(reader.read_varint()?))
+    }
+}
+
+impl Encode for ComponentId {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u8(self.0);
+        Ok(())
+    }
+}
+
+impl Decode for ComponentId {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(ComponentId(reader.read_u8()?))
+    }
+}
+
+impl Encode for ComponentState {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        self.id.encode(writer)?;
+        writer.write_len(self.data.len(), MAX_COMPONENT_SIZE)?;
+        writer.write_bytes(&self.data);
+        Ok(())
+    }
+}
+
+impl Decode for ComponentState {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let id = ComponentId::decode(reader)?;
+        let len = reader.read_len(MAX_COMPONENT_SIZE)?;
+        Ok(ComponentState {
+            id,
+            data: reader.read_bytes(len)?.to_vec(),
+        })
+    }
+}
+
+impl Encode for SessionId {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u64(self.0);
+        Ok(())
+    }
+}
+
+impl Decode for SessionId {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(SessionId(reader.read_u64()?))
+    }
+}
+
+impl Encode for ResumeToken {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u64(self.0);
+        Ok(())
+    }
+}
+
+impl Decode for ResumeToken {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(ResumeToken(reader.read_u64()?))
+    }
+}
+
+impl Encode for ClientPacket {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        self.session.encode(writer)?;
+        self.packet.encode(writer)
+    }
+}
+
+impl Decode for ClientPacket {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(ClientPacket {
+            session: SessionId::decode(reader)?,
+            packet: Packet::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for ClientMessage {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        match self {
+            ClientMessage::Connect(request) => {
+                writer.write_u8(0);
+                request.encode(writer)
+            }
+            ClientMessage::Input(input) => {
+                writer.write_u8(1);
+                input.encode(writer)
+            }
+            ClientMessage::Chat { text } => {
+                writer.write_u8(2);
+                writer.write_string(text)
+            }
+            ClientMessage::Heartbeat => {
+                writer.write_u8(3);
+                Ok(())
+            }
+            ClientMessage::Disconnect => {
+                writer.write_u8(4);
+                Ok(())
+            }
+            ClientMessage::SnapshotAck { tick } => {
+                writer.write_u8(5);
+                writer.write_u32(*tick);
+                Ok(())
+            }
+            ClientMessage::ListRooms => {
+                writer.write_u8(6);
+                Ok(())
+            }
+            ClientMessage::SetReady { ready } => {
+                writer.write_u8(7);
+                writer.write_bool(*ready);
+                Ok(())
+            }
+            ClientMessage::StartMatch => {
+                writer.write_u8(8);
+                Ok(())
+            }
+            ClientMessage::LeaveRoom => {
+                writer.write_u8(9);
+                Ok(())
+            }
+            ClientMessage::Ping { sent } => {
+                writer.write_u8(10);
+                writer.write_u32(*sent);
+                Ok(())
+            }
+            ClientMessage::RequestMap { hash } => {
+                writer.write_u8(11);
+                hash.encode(writer)
+            }
+        }
+    }
+}
+
+impl Decode for ClientMessage {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(ClientMessage::Connect(ConnectRequest::decode(reader)?)),
+            1 => Ok(ClientMessage::Input(InputCommand::decode(reader)?)),
+            2 => Ok(ClientMessage::Chat {
+                text: reader.read_string()?,
+            }),
+            3 => Ok(ClientMessage::Heartbeat),
+            4 => Ok(ClientMessage::Disconnect),
+            5 => Ok(ClientMessage::SnapshotAck {
+                tick: reader.read_u32()?,
+            }),
+            6 => Ok(ClientMessage::ListRooms),
+            7 => Ok(ClientMessage::SetReady {
+                ready: reader.read_bool()?,
+            }),
+            8 => Ok(ClientMessage::StartMatch),
+            9 => Ok(ClientMessage::LeaveRoom),
+            10 => Ok(ClientMessage::Ping {
+                sent: reader.read_u32()?,
+            }),
+            11 => Ok(ClientMessage::RequestMap {
+                hash: MapHash::decode(reader)?,
+            }),
+            tag => Err(DecodeError::UnknownTag {
+                kind: "ClientMessage",
+                tag,
+            }),
+        }
+    }
+}
+
+impl Encode for ServerMessage {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        match self {
+            ServerMessage::ConnectAccepted {
+                client,
+                session,
+                tick,
+                tick_rate,
+                resume,
+            } => {
+                writer.write_u8(0);
+                client.encode(writer)?;
+                session.encode(writer)?;
+                writer.write_u32(*tick);
+                writer.write_u16(*tick_rate);
+                resume.encode(writer)
+            }
+            ServerMessage::ConnectRejected(reason) => {
+                writer.write_u8(1);
+                reason.encode(writer)
+            }
+            ServerMessage::Snapshot(snapshot) => {
+                writer.write_u8(2);
+                snapshot.encode(writer)
+            }
+            ServerMessage::Event(event) => {
+                writer.write_u8(3);
+                event.encode(writer)
+            }
+            ServerMessage::Chat { from, text } => {
+                writer.write_u8(4);
+                match from {
+                    Some(client) => {
+                        writer.write_bool(true);
+                        client.encode(writer)?;
+                    }
+                    None => writer.write_bool(false),
+                }
+                writer.write_string(text)
+            }
+            ServerMessage::Disconnected(reason) => {
+                writer.write_u8(5);
+                reason.encode(writer)
+            }
+            ServerMessage::RoomList(rooms) => {
+                writer.write_u8(6);
+                encode_list(writer, rooms, MAX_LISTED_ROOMS)
+            }
+            ServerMessage::RoomState(state) => {
+                writer.write_u8(7);
+                state.encode(writer)
+            }
+            ServerMessage::Pong { sent, held, tick } => {
+                writer.write_u8(8);
+                writer.write_u32(*sent);
+                writer.write_varint(*held);
+                writer.write_u32(*tick);
+                Ok(())
+            }
+            ServerMessage::Map(map) => {
+                writer.write_u8(9);
+                map.encode(writer)
+            }
+            ServerMessage::MapChunk(chunk) => {
+                writer.write_u8(10);
+                chunk.encode(writer)
+            }
+        }
+    }
+}
+
+impl Decode for ServerMessage {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(ServerMessage::ConnectAccepted {
+                client: ClientId::decode(reader)?,
+                session: SessionId::decode(reader)?,
+                tick: reader.read_u32()?,
+                tick_rate: reader.read_u16()?,
+                resume: ResumeToken::decode(reader)?,
+            }),
+            1 => Ok(ServerMessage::ConnectRejected(RejectReason::decode(
+                reader,
+            )?)),
+            2 => Ok(ServerMessage::Snapshot(DeltaSnapshot::decode(reader)?)),
+            3 => Ok(ServerMessage::Event(GameEvent::decode(reader)?)),
+            4 => {
+                let from = match reader.read_bool()? {
+                    true => Some(ClientId::decode(reader)?),
+                    false => None,
+                };
+                Ok(ServerMessage::Chat {
+                    from,
+                    text: reader.read_string()?,
+                })
+            }
+            5 => Ok(ServerMessage::Disconnected(DisconnectReason::decode(
+                reader,
+            )?)),
+            6 => Ok(ServerMessage::RoomList(decode_list(
+                reader,
+                MAX_LISTED_ROOMS,
+            )?)),
+            7 => Ok(ServerMessage::RoomState(RoomState::decode(reader)?)),
+            8 => Ok(ServerMessage::Pong {
+                sent: reader.read_u32()?,
+                held: reader.read_varint()?,
+                tick: reader.read_u32()?,
+            }),
+            9 => Ok(ServerMessage::Map(MapInfo::decode(reader)?)),
+            10 => Ok(ServerMessage::MapChunk(MapChunk::decode(reader)?)),
+            tag => Err(DecodeError::UnknownTag {
+                kind: "ServerMessage",
+                tag,
+            }),
+        }
+    }
+}
+
+impl Encode for ConnectRequest {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u16(self.protocol_version);
+        writer.write_string(&self.name)?;
+        self.room.encode(writer)?;
+        match &self.password {
+            Some(password) => {
+                writer.write_bool(true);
+                writer.write_string(password)?;
+            }
+            None => writer.write_bool(false),
+        }
+        match &self.resume {
+            Some(resume) => {
+                writer.write_bool(true);
+                resume.encode(writer)
+            }
+            None => {
+                writer.write_bool(false);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Decode for ConnectRequest {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(ConnectRequest {
+            protocol_version: reader.read_u16()?,
+            name: reader.read_string()?,
+            room: RoomRequest::decode(reader)?,
+            password: match reader.read_bool()? {
+                true => Some(reader.read_string()?),
+                false => None,
+            },
+            resume: match reader.read_bool()? {
+                true => Some(ResumeToken::decode(reader)?),
+                false => None,
+            },
+        })
+    }
+}
+
+impl Encode for RejectReason {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        match self {
+            RejectReason::VersionMismatch { server } => {
+                writer.write_u8(0);
+                writer.write_u16(*server);
+            }
+            RejectReason::ServerFull => writer.write_u8(1),
+            RejectReason::NoSuchRoom => writer.write_u8(2),
+            RejectReason::RoomFull => writer.write_u8(3),
+            RejectReason::Banned => writer.write_u8(4),
+            RejectReason::WrongPassword => writer.write_u8(5),
+        }
+        Ok(())
+    }
+}
+
+impl Decode for RejectReason {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(RejectReason::VersionMismatch {
+                server: reader.read_u16()?,
+            }),
+            1 => Ok(RejectReason::ServerFull),
+            2 => Ok(RejectReason::NoSuchRoom),
+            3 => Ok(RejectReason::RoomFull),
+            4 => Ok(RejectReason::Banned),
+            5 => Ok(RejectReason::WrongPassword),
+            tag => Err(DecodeError::UnknownTag {
+                kind: "RejectReason",
+                tag,
+            }),
+        }
+    }
+}
+
+impl Encode for DisconnectReason {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u8(match self {
+            DisconnectReason::TimedOut => 0,
+            DisconnectReason::Kicked => 1,
+            DisconnectReason::ServerShutdown => 2,
+            DisconnectReason::Left => 3,
+        });
+        Ok(())
+    }
+}
+
+impl Decode for DisconnectReason {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(DisconnectReason::TimedOut),
+            1 => Ok(DisconnectReason::Kicked),
+            2 => Ok(DisconnectReason::ServerShutdown),
+            3 => Ok(DisconnectReason::Left),
+            tag => Err(DecodeError::UnknownTag {
+                kind: "DisconnectReason",
+                tag,
+            }),
+        }
+    }
+}
+
+impl Encode for InputCommand {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u32(self.sequence);
+        writer.write_vec3(self.movement);
+        writer.write_f32(self.yaw);
+        writer.write_f32(self.pitch);
+        writer.write_bool(self.throw);
+        writer.write_u32(self.view_tick);
+        Ok(())
+    }
+}
+
+impl Decode for InputCommand {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(InputCommand {
+            sequence: reader.read_u32()?,
+            movement: reader.read_vec3()?,
+            yaw: reader.read_f32()?,
+            pitch: reader.read_f32()?,
+            throw: reader.read_bool()?,
+            view_tick: reader.read_u32()?,
+        })
+    }
+}
+
+impl Encode for EntityKind {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        match self {
+            EntityKind::Player(client) => {
+                writer.write_u8(0);
+                client.encode(writer)
+            }
+            EntityKind::Snowball { owner } => {
+                writer.write_u8(1);
+                owner.encode(writer)
+            }
+            EntityKind::Prop { index } => {
+                writer.write_u8(2);
+                writer.write_u16(*index);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Decode for EntityKind {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(EntityKind::Player(ClientId::decode(reader)?)),
+            1 => Ok(EntityKind::Snowball {
+                owner: ClientId::decode(reader)?,
+            }),
+            2 => Ok(EntityKind::Prop {
+                index: reader.read_u16()?,
+            }),
+            tag => Err(DecodeError::UnknownTag {
+                kind: "EntityKind",
+                tag,
+            }),
+        }
+    }
+}
+
+impl Encode for GameEvent {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        match self {
+            GameEvent::PlayerJoined { client, name } => {
+                writer.write_u8(0);
+                client.encode(writer)?;
+                writer.write_string(name)
+            }
+            GameEvent::PlayerLeft { client } => {
+                writer.write_u8(1);
+                client.encode(writer)
+            }
+            GameEvent::SnowballHit { thrower, target } => {
+                writer.write_u8(2);
+                thrower.encode(writer)?;
+                target.encode(writer)
+            }
+            GameEvent::ScoreChanged { client, score } => {
+                writer.write_u8(3);
+                client.encode(writer)?;
+                writer.write_i32(*score);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Decode for GameEvent {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(GameEvent::PlayerJoined {
+                client: ClientId::decode(reader)?,
+                name: reader.read_string()?,
+            }),
+            1 => Ok(GameEvent::PlayerLeft {
+                client: ClientId::decode(reader)?,
+            }),
+            2 => Ok(GameEvent::SnowballHit {
+                thrower: ClientId::decode(reader)?,
+                target: ClientId::decode(reader)?,
+            }),
+            3 => Ok(GameEvent::ScoreChanged {
+                client: ClientId::decode(reader)?,
+                score: reader.read_i32()?,
+            }),
+            tag => Err(DecodeError::UnknownTag {
+                kind: "GameEvent",
+                tag,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use super::*;
+    use crate::{JoinCode, LobbyPlayer, delta::EntityDelta, lobby::RoomRequest};
+
+    fn round_trip<T: Encode + Decode + PartialEq + Debug>(value: T) {
+        let bytes = crate::encode_message(&value).unwrap();
+        assert_eq!(crate::decode_message::<T>(&bytes), Ok(value));
+    }
+
+    #[test]
+    fn client_messages_round_trip() {
+        let messages = [
+            ClientMessage::Connect(ConnectRequest {
+                protocol_version: crate::PROTOCOL_VERSION,
+                name: "snowman".to_string(),
+                room: RoomRequest::Join(JoinCode::from_bits(12345)),
+                password: Some("hunter2".to_string()),
+                resume: Some(ResumeToken(u64::MAX)),
+            }),
+            ClientMessage::Connect(ConnectRequest {
+                protocol_version: 1,
+                name: String::new(),
+                room: RoomRequest::Create { private: true },
+                password: None,
+                resume: None,
+            }),
+            ClientMessage::Input(InputCommand {
+                sequence: u32::MAX,
+                movement: [1., -1., 1.],
+                yaw: 3.5,
+                pitch: -1.25,
+                throw: true,
+                view_tick: 99,
+            }),
+            ClientMessage::Chat {
+                text: "hello ❄".to_string(),
+            },
+            ClientMessage::Heartbeat,
+            ClientMessage::Disconnect,
+            ClientMessage::SnapshotAck { tick: 7 },
+            ClientMessage::ListRooms,
+            ClientMessage::SetReady { ready: true },
+            ClientMessage::StartMatch,
+            ClientMessage::LeaveRoom,
+            ClientMessage::Ping { sent: 123_456 },
+            ClientMessage::RequestMap {
+                hash: MapHash(0xdead_beef),
+            },
+        ];
+        for message in messages {
+            round_trip(message);
+        }
+    }
+
+    #[test]
+    fn server_messages_round_trip() {
+        let code = JoinCode::from_bits(54321);
+        let snapshot = DeltaSnapshot {
+            tick: 40,
+            baseline: Some(38),
+            last_input: 12,
+            entities: vec![
+                EntityDelta {
+                    id: NetEntity(1),
+                    kind: Some(EntityKind::Player(ClientId(3))),
+                    translation: Some([100, -200, 0]),
+                    rotation: Some(0x3ff),
+                    velocity: Some([-1, 0, 1]),
+                    components: vec![ComponentState {
+                        id: ComponentId(0),
+                        data: vec![5],
+                    }],
+                    removed_components: vec![ComponentId(1)],
+                },
+                EntityDelta {
+                    id: NetEntity(2),
+                    kind: None,
+                    translation: None,
+                    rotation: None,
+                    velocity: Some([0, 0, -3]),
+                    components: Vec::new(),
+                    removed_components: Vec::new(),
+                },
+            ],
+            removed: vec![NetEntity(9)],
+        };
+        let messages = [
+            ServerMessage::ConnectAccepted {
+                client: ClientId(4),
+                session: SessionId(u64::MAX),
+                tick: 1000,
+                tick_rate: 60,
+                resume: ResumeToken(17),
+            },
+            ServerMessage::ConnectRejected(RejectReason::VersionMismatch { server: 14 }),
+            ServerMessage::ConnectRejected(RejectReason::ServerFull),
+            ServerMessage::ConnectRejected(RejectReason::NoSuchRoom),
+            ServerMessage::ConnectRejected(RejectReason::RoomFull),
+            ServerMessage::ConnectRejected(RejectReason::Banned),
+            ServerMessage::ConnectRejected(RejectReason::WrongPassword),
+            ServerMessage::Disconnected(DisconnectReason::TimedOut),
+            ServerMessage::Disconnected(DisconnectReason::Kicked),
+            ServerMessage::Disconnected(DisconnectReason::ServerShutdown),
+            ServerMessage::Disconnected(DisconnectReason::Left),
+            ServerMessage::Snapshot(snapshot),
+            ServerMessage::Snapshot(DeltaSnapshot::default()),
+            ServerMessage::Event(GameEvent::PlayerJoined {
+                client: ClientId(1),
+                name: "frosty".to_string(),
+            }),
+            ServerMessage::Event(GameEvent::PlayerLeft {
+                client: ClientId(1),
+            }),
+            ServerMessage::Event(GameEvent::SnowballHit {
+                thrower: ClientId(1),
+                target: ClientId(2),
+            }),
+            ServerMessage::Event(GameEvent::ScoreChanged {
+                client: ClientId(2),
+                score: -3,
+            }),
+            ServerMessage::Chat {
+                from: Some(ClientId(1)),
+                text: "hi".to_string(),
+            },
+            ServerMessage::Chat {
+                from: None,
+                text: "server restarting".to_string(),
+            },
+            ServerMessage::RoomList(vec![RoomSummary {
+                code,
+                players: 3,
+                max_players: 16,
+                started: true,
+            }]),
+            ServerMessage::RoomState(RoomState {
+                code,
+                host: Some(ClientId(0)),
+                started: false,
+                players: vec![LobbyPlayer {
+                    client: ClientId(0),
+                    name: "host".to_string(),
+                    ready: true,
+                }],
+            }),
+            ServerMessage::Pong {
+                sent: 1,
+                held: 2,
+                tick: 3,
+            },
+            ServerMessage::Map(MapInfo {
+                name: "shapes".to_string(),
+                hash: MapHash(42),
+                size: 4096,
+            }),
+            ServerMessage::MapChunk(MapChunk {
+                hash: MapHash(42),
+                offset: 1024,
+                data: vec![1, 2, 3],
+            }),
+        ];
+        for message in messages {
+            round_trip(message);
+        }
+    }
+
+    #[test]
+    fn entity_kinds_round_trip() {
+        for kind in [
+            EntityKind::Player(ClientId(7)),
+            EntityKind::Snowball { owner: ClientId(7) },
+            EntityKind::Prop { index: u16::MAX },
+        ] {
+            round_trip(kind);
+        }
+    }
+}
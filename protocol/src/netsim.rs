@@ -0,0 +1,346 @@
+//! Bad network on demand. Wrap a socket in `SimulatedSocket` and everything it
+//! sends or receives is delayed, jittered, dropped, duplicated and reordered
+//! according to a `NetworkConditions`, so netcode can be exercised over loopback.
+
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    io::{self, ErrorKind},
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::RECV_BUFFER_SIZE;
+
+/// How a simulated link misbehaves, in each direction.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NetworkConditions {
+    /// One way delay added to every packet.
+    pub latency: Duration,
+    /// Up to this much extra delay, picked at random per packet.
+    pub jitter: Duration,
+    /// Chance, from 0 to 1, that a packet is dropped.
+    pub loss: f32,
+    /// Chance that a packet is delivered twice.
+    pub duplicate: f32,
+    /// Chance that a packet is held back long enough to arrive after later ones.
+    pub reorder: f32,
+}
+
+impl NetworkConditions {
+    /// A perfect link.
+    pub const NONE: NetworkConditions = NetworkConditions {
+        latency: Duration::ZERO,
+        jitter: Duration::ZERO,
+        loss: 0.,
+        duplicate: 0.,
+        reorder: 0.,
+    };
+
+    pub const GOOD_WIFI: NetworkConditions = NetworkConditions {
+        latency: Duration::from_millis(15),
+        jitter: Duration::from_millis(5),
+        loss: 0.005,
+        duplicate: 0.,
+        reorder: 0.,
+    };
+
+    pub const BAD_MOBILE: NetworkConditions = NetworkConditions {
+        latency: Duration::from_millis(120),
+        jitter: Duration::from_millis(60),
+        loss: 0.05,
+        duplicate: 0.01,
+        reorder: 0.02,
+    };
+
+    /// Names accepted by `preset`, for help text.
+    pub const PRESETS: &[&str] = &["none", "good-wifi", "bad-mobile"];
+
+    pub fn preset(name: &str) -> Option<NetworkConditions> {
+        match name {
+            "none" => Some(Self::NONE),
+            "good-wifi" => Some(Self::GOOD_WIFI),
+            "bad-mobile" => Some(Self::BAD_MOBILE),
+            _ => None,
+        }
+    }
+
+    pub fn is_perfect(&self) -> bool {
+        *self == Self::NONE
+    }
+}
+
+struct Delayed {
+    due: Instant,
+    /// Tie breaker so packets due at the same instant keep their order.
+    order: u64,
+    addr: SocketAddr,
+    bytes: Vec<u8>,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    // Reversed, so the `BinaryHeap` pops the earliest packet first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .due
+            .cmp(&self.due)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+/// One direction of a simulated link: packets go in, and come out once
+/// they're due, minus the ones that got lost.
+pub struct NetworkSimulator {
+    conditions: NetworkConditions,
+    queue: BinaryHeap<Delayed>,
+    /// When the last in-order packet is due, so jitter alone doesn't reorder.
+    last_due: Option<Instant>,
+    order: u64,
+    rng: u64,
+}
+
+impl NetworkSimulator {
+    pub fn new(conditions: NetworkConditions) -> Self {
+        Self {
+            conditions,
+            queue: BinaryHeap::new(),
+            last_due: None,
+            order: 0,
+            // Never zero, which xorshift gets stuck on.
+            rng: crate::random(),
+        }
+    }
+
+    pub fn conditions(&self) -> NetworkConditions {
+        self.conditions
+    }
+
+    pub fn push(&mut self, bytes: &[u8], addr: SocketAddr, now: Instant) {
+        if self.chance(self.conditions.loss) {
+            return;
+        }
+
+        let copies = if self.chance(self.conditions.duplicate) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let jitter = self.conditions.jitter.mul_f32(self.random());
+            let mut due = now + self.conditions.latency + jitter;
+
+            if self.chance(self.conditions.reorder) {
+                // Late enough to be overtaken by whatever is sent next.
+                due += self
+                    .conditions
+                    .latency
+                    .max(Duration::from_millis(10))
+                    .mul_f32(self.random() + 0.5);
+            } else {
+                due = due.max(self.last_due.unwrap_or(due));
+                self.last_due = Some(due);
+            }
+
+            self.order += 1;
+            self.queue.push(Delayed {
+                due,
+                order: self.order,
+                addr,
+                bytes: bytes.to_vec(),
+            });
+        }
+    }
+
+    /// The next packet whose delay is up, if any.
+    pub fn pop(&mut self, now: Instant) -> Option<(Vec<u8>, SocketAddr)> {
+        if self.queue.peek()?.due > now {
+            return None;
+        }
+        self.queue
+            .pop()
+            .map(|delayed| (delayed.bytes, delayed.addr))
+    }
+
+    /// When the next packet comes due.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.queue.peek().map(|delayed| delayed.due)
+    }
+
+    fn chance(&mut self, probability: f32) -> bool {
+        probability > 0. && self.random() < probability
+    }
+
+    /// Uniform in `0..1`.
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// A non-blocking `UdpSocket` with `NetworkConditions` applied to traffic in
+/// both directions, so wrapping just one end is enough to test against.
+/// Packets only move when `send_to` or `recv_from` is called, so poll it at
+/// least as often as the latency you want to resolve.
+pub struct SimulatedSocket {
+    socket: UdpSocket,
+    outgoing: NetworkSimulator,
+    incoming: NetworkSimulator,
+}
+
+impl SimulatedSocket {
+    pub fn new(socket: UdpSocket, conditions: NetworkConditions) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            outgoing: NetworkSimulator::new(conditions),
+            incoming: NetworkSimulator::new(conditions),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn conditions(&self) -> NetworkConditions {
+        self.outgoing.conditions()
+    }
+
+    pub fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if self.conditions().is_perfect() {
+            return self.socket.send_to(bytes, addr);
+        }
+
+        let now = Instant::now();
+        self.outgoing.push(bytes, addr, now);
+        self.flush(now)?;
+        Ok(bytes.len())
+    }
+
+    /// Like `UdpSocket::recv_from` on a non-blocking socket: `WouldBlock` means
+    /// nothing is due yet.
+    pub fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        if self.conditions().is_perfect() {
+            return self.socket.recv_from(buf);
+        }
+
+        let now = Instant::now();
+        self.flush(now)?;
+
+        let mut scratch = [0; RECV_BUFFER_SIZE];
+        loop {
+            match self.socket.recv_from(&mut scratch) {
+                Ok((len, addr)) => self.incoming.push(&scratch[..len], addr, now),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        match self.incoming.pop(now) {
+            Some((bytes, addr)) => {
+                let len = bytes.len().min(buf.len());
+                buf[..len].copy_from_slice(&bytes[..len]);
+                Ok((len, addr))
+            }
+            None => Err(ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    /// Sends every outgoing packet that has waited long enough.
+    fn flush(&mut self, now: Instant) -> io::Result<()> {
+        while let Some((bytes, addr)) = self.outgoing.pop(now) {
+            match self.socket.send_to(&bytes, addr) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Channel, Connection, Packet};
+
+    /// Sends every packet `from` has to say through `link` and feeds whatever
+    /// is due to `to`, returning what it delivers.
+    fn exchange(
+        from: &mut Connection,
+        link: &mut NetworkSimulator,
+        to: &mut Connection,
+        now: Instant,
+    ) -> Vec<(Channel, Vec<u8>)> {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1));
+        for packet in from.flush(now) {
+            link.push(&crate::encode_message(&packet).unwrap(), addr, now);
+        }
+        let mut delivered = Vec::new();
+        while let Some((bytes, _)) = link.pop(now) {
+            let packet: Packet = crate::decode_message(&bytes).unwrap();
+            delivered.extend(to.receive(packet, now));
+        }
+        delivered
+    }
+
+    #[test]
+    fn reliable_messages_survive_a_bad_link() {
+        let conditions = NetworkConditions {
+            latency: Duration::from_millis(40),
+            jitter: Duration::from_millis(30),
+            loss: 0.2,
+            duplicate: 0.05,
+            reorder: 0.2,
+        };
+        let mut now = Instant::now();
+        let (mut client, mut server) = (Connection::new(now), Connection::new(now));
+        let mut up = NetworkSimulator::new(conditions);
+        let mut down = NetworkSimulator::new(conditions);
+
+        // Every tenth is big enough to go in fragments.
+        let sent: Vec<Vec<u8>> = (0..200u32)
+            .map(|i| {
+                let len = if i % 10 == 0 { 3000 } else { 4 };
+                i.to_le_bytes().into_iter().cycle().take(len).collect()
+            })
+            .collect();
+        for payload in &sent {
+            client
+                .send(Channel::ReliableOrdered, payload.clone())
+                .unwrap();
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..10_000 {
+            for (channel, payload) in exchange(&mut client, &mut up, &mut server, now) {
+                assert_eq!(channel, Channel::ReliableOrdered);
+                received.push(payload);
+            }
+            exchange(&mut server, &mut down, &mut client, now);
+            if received.len() == sent.len() && client.unacked() == 0 {
+                break;
+            }
+            now += Duration::from_millis(16);
+        }
+
+        assert_eq!(received, sent);
+        assert_eq!(client.unacked(), 0);
+        assert!(client.stats().packets_lost > 0);
+    }
+}
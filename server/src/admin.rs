@@ -0,0 +1,465 @@
+//! Commands for whoever runs the server. Every admin frontend goes through an
+//! `Admin` handle: it turns a line of text into a request for the server's
+//! run loop, which carries it out and answers with text to show. The stdin
+//! console is one frontend; anything else that can pass lines along and show
+//! the answers, a remote console say, can be another.
+
+use std::{net::IpAddr, time::Duration};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::server::ServerConfig;
+
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+}
+
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "status",
+        usage: "status",
+        help: "List rooms, players and bans",
+    },
+    CommandInfo {
+        name: "kick",
+        usage: "kick <player>",
+        help: "Disconnect a player, by name or address",
+    },
+    CommandInfo {
+        name: "ban",
+        usage: "ban <ip>",
+        help: "Kick everyone from an address and keep it out",
+    },
+    CommandInfo {
+        name: "unban",
+        usage: "unban <ip>",
+        help: "Let an address back in",
+    },
+    CommandInfo {
+        name: "map",
+        usage: "map <name>",
+        help: "Switch maps, restarting every match",
+    },
+    CommandInfo {
+        name: "set",
+        usage: "set [<setting> [<value>]]",
+        help: "Show or change settings",
+    },
+    CommandInfo {
+        name: "say",
+        usage: "say <message>",
+        help: "Send a chat message to everyone",
+    },
+    CommandInfo {
+        name: "restart",
+        usage: "restart",
+        help: "Restart every match",
+    },
+    CommandInfo {
+        name: "help",
+        usage: "help",
+        help: "List commands",
+    },
+    CommandInfo {
+        name: "quit",
+        usage: "quit",
+        help: "Shut the server down",
+    },
+];
+
+/// Settings `set` can change while the server runs. The rest only take
+/// effect on the next start.
+pub const SETTINGS: &[&str] = &[
+    "name",
+    "mode",
+    "max_players",
+    "max_rooms",
+    "client_timeout_ms",
+    "reconnect_grace_ms",
+    "max_rewind_ms",
+    "max_message_size",
+    "kick_violations",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdminCommand {
+    Status,
+    /// A player name, or a full address when names are shared.
+    Kick(String),
+    Ban(IpAddr),
+    Unban(IpAddr),
+    Map(String),
+    /// Lists every setting, shows one or changes one.
+    Set {
+        setting: Option<String>,
+        value: Option<String>,
+    },
+    Say(String),
+    Restart,
+    Help,
+    Quit,
+}
+
+impl AdminCommand {
+    /// Parses one line. Blank lines are `None`; anything else that isn't a
+    /// command gets an error saying what's wrong.
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let line = line.trim();
+        let (name, rest) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(name, rest)| (name, rest.trim()));
+        let command = match name {
+            "" => return Ok(None),
+            "status" => AdminCommand::Status,
+            "kick" => AdminCommand::Kick(required(rest, "kick <player>")?.to_string()),
+            "ban" => AdminCommand::Ban(parse_ip(required(rest, "ban <ip>")?)?),
+            "unban" => AdminCommand::Unban(parse_ip(required(rest, "unban <ip>")?)?),
+            "map" => AdminCommand::Map(required(rest, "map <name>")?.to_string()),
+            "set" => {
+                let (setting, value) = rest
+                    .split_once(char::is_whitespace)
+                    .map_or((rest, ""), |(setting, value)| (setting, value.trim()));
+                AdminCommand::Set {
+                    setting: (!setting.is_empty()).then(|| setting.to_string()),
+                    value: (!value.is_empty()).then(|| value.to_string()),
+                }
+            }
+            "say" => AdminCommand::Say(required(rest, "say <message>")?.to_string()),
+            "restart" => AdminCommand::Restart,
+            "help" => AdminCommand::Help,
+            "quit" => AdminCommand::Quit,
+            _ => return Err(format!("Unknown command {name:?}, try help")),
+        };
+        Ok(Some(command))
+    }
+}
+
+fn required<'a>(argument: &'a str, usage: &str) -> Result<&'a str, String> {
+    if argument.is_empty() {
+        return Err(format!("Usage: {usage}"));
+    }
+    Ok(argument)
+}
+
+fn parse_ip(ip: &str) -> Result<IpAddr, String> {
+    ip.parse()
+        .map_err(|_| format!("{ip:?} isn't an IP address"))
+}
+
+pub fn help() -> String {
+    let width = COMMANDS
+        .iter()
+        .map(|command| command.usage.len())
+        .max()
+        .unwrap_or(0);
+    COMMANDS
+        .iter()
+        .map(|command| format!("{:width$}  {}", command.usage, command.help))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Ways to finish the last word of `line`. `arguments` gives the choices for
+/// a command's argument, from what the server knows right now.
+pub fn complete(line: &str, arguments: impl Fn(&str) -> Vec<String>) -> Vec<String> {
+    let (command, partial) = match line.split_once(char::is_whitespace) {
+        None => {
+            return COMMANDS
+                .iter()
+                .filter(|command| command.name.starts_with(line))
+                .map(|command| command.name.to_string())
+                .collect();
+        }
+        Some((command, rest)) => (command, rest.trim_start()),
+    };
+    // Only the first argument is worth completing; the rest are free text.
+    if partial.contains(char::is_whitespace) {
+        return Vec::new();
+    }
+    let mut choices: Vec<String> = arguments(command)
+        .into_iter()
+        .filter(|choice| choice.starts_with(partial))
+        .collect();
+    choices.sort();
+    choices.dedup();
+    choices
+}
+
+/// A setting's current value, as `set` shows it.
+pub fn setting(config: &ServerConfig, setting: &str) -> Option<String> {
+    let value = match setting {
+        "name" => config.name.clone(),
+        "mode" => config.mode.clone(),
+        "max_players" => config.max_players.to_string(),
+        "max_rooms" => config.max_rooms.to_string(),
+        "client_timeout_ms" => config.client_timeout.as_millis().to_string(),
+        "reconnect_grace_ms" => config.reconnect_grace.as_millis().to_string(),
+        "max_rewind_ms" => config.max_rewind.as_millis().to_string(),
+        "max_message_size" => config.max_message_size.to_string(),
+        "kick_violations" => config.kick_violations.unwrap_or(0).to_string(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Changes one setting in `config`, or says why it can't. Whether the new
+/// value is in range is for `config::validate`.
+pub fn change_setting(config: &mut ServerConfig, setting: &str, value: &str) -> Result<(), String> {
+    let number = || -> Result<u64, String> {
+        value
+            .parse()
+            .map_err(|_| format!("{setting} expects a number"))
+    };
+    match setting {
+        "name" => config.name = value.to_string(),
+        "mode" => config.mode = value.to_string(),
+        "max_players" => config.max_players = number()? as usize,
+        "max_rooms" => config.max_rooms = number()? as usize,
+        "client_timeout_ms" => config.client_timeout = Duration::from_millis(number()?),
+        "reconnect_grace_ms" => config.reconnect_grace = Duration::from_millis(number()?),
+        "max_rewind_ms" => config.max_rewind = Duration::from_millis(number()?),
+        "max_message_size" => config.max_message_size = number()? as usize,
+        "kick_violations" => {
+            let count = u32::try_from(number()?).unwrap_or(u32::MAX);
+            config.kick_violations = (count != 0).then_some(count);
+        }
+        _ => {
+            return Err(format!(
+                "No setting called {setting:?}, try one of {}",
+                SETTINGS.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// What a frontend asks of the server's run loop.
+pub enum AdminRequest {
+    Run {
+        command: AdminCommand,
+        reply: oneshot::Sender<String>,
+    },
+    Complete {
+        line: String,
+        reply: oneshot::Sender<Vec<String>>,
+    },
+}
+
+/// How frontends reach the server. Answers come back on a oneshot channel, to
+/// be awaited or, off the runtime, waited on with `blocking_recv`. They never
+/// arrive if the server has stopped.
+#[derive(Clone)]
+pub struct Admin {
+    requests: mpsc::UnboundedSender<AdminRequest>,
+}
+
+impl Admin {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<AdminRequest>) {
+        let (requests, receiver) = mpsc::unbounded_channel();
+        (Self { requests }, receiver)
+    }
+
+    /// Runs `command` and answers with what to show.
+    pub fn run(&self, command: AdminCommand) -> oneshot::Receiver<String> {
+        let (reply, answer) = oneshot::channel();
+        let _ = self.requests.send(AdminRequest::Run { command, reply });
+        answer
+    }
+
+    /// Parses and runs a line of text. Mistakes are answered straight away.
+    pub fn execute(&self, line: &str) -> oneshot::Receiver<String> {
+        match AdminCommand::parse(line) {
+            Ok(Some(command)) => self.run(command),
+            Ok(None) => answered(String::new()),
+            Err(e) => answered(e),
+        }
+    }
+
+    /// Ways to finish the last word of `line`.
+    pub fn complete(&self, line: &str) -> oneshot::Receiver<Vec<String>> {
+        let (reply, answer) = oneshot::channel();
+        let _ = self.requests.send(AdminRequest::Complete {
+            line: line.to_string(),
+            reply,
+        });
+        answer
+    }
+}
+
+fn answered<T>(value: T) -> oneshot::Receiver<T> {
+    let (reply, answer) = oneshot::channel();
+    let _ = reply.send(value);
+    answer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> AdminCommand {
+        AdminCommand::parse(line).unwrap().unwrap()
+    }
+
+    fn set(setting: Option<&str>, value: Option<&str>) -> AdminCommand {
+        AdminCommand::Set {
+            setting: setting.map(str::to_string),
+            value: value.map(str::to_string),
+        }
+    }
+
+    /// What the server would offer: player names for kick, settings for set.
+    fn arguments(command: &str) -> Vec<String> {
+        match command {
+            "kick" => vec!["bob".to_string(), "alice".to_string(), "bob".to_string()],
+            "set" => SETTINGS.iter().map(|setting| setting.to_string()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn blank_lines_are_nothing() {
+        assert_eq!(AdminCommand::parse(""), Ok(None));
+        assert_eq!(AdminCommand::parse("  \t "), Ok(None));
+    }
+
+    #[test]
+    fn commands_are_parsed() {
+        let ip: IpAddr = "10.0.0.7".parse().unwrap();
+        assert_eq!(parse("status"), AdminCommand::Status);
+        assert_eq!(
+            parse("  kick   bob  "),
+            AdminCommand::Kick("bob".to_string())
+        );
+        assert_eq!(
+            parse("kick 127.0.0.1:5000"),
+            AdminCommand::Kick("127.0.0.1:5000".to_string())
+        );
+        assert_eq!(parse("ban 10.0.0.7"), AdminCommand::Ban(ip));
+        assert_eq!(parse("unban 10.0.0.7"), AdminCommand::Unban(ip));
+        assert_eq!(parse("ban ::1"), AdminCommand::Ban("::1".parse().unwrap()));
+        assert_eq!(parse("map arena"), AdminCommand::Map("arena".to_string()));
+        assert_eq!(
+            parse("say hello  there"),
+            AdminCommand::Say("hello  there".to_string())
+        );
+        assert_eq!(parse("restart"), AdminCommand::Restart);
+        assert_eq!(parse("help"), AdminCommand::Help);
+        assert_eq!(parse("quit"), AdminCommand::Quit);
+    }
+
+    #[test]
+    fn set_shows_or_changes() {
+        assert_eq!(parse("set"), set(None, None));
+        assert_eq!(parse("set tick_rate"), set(Some("tick_rate"), None));
+        assert_eq!(
+            parse("set name  Snow  day "),
+            set(Some("name"), Some("Snow  day"))
+        );
+    }
+
+    #[test]
+    fn missing_arguments_get_the_usage() {
+        for (line, usage) in [
+            ("kick", "kick <player>"),
+            ("ban", "ban <ip>"),
+            ("unban  ", "unban <ip>"),
+            ("map", "map <name>"),
+            ("say", "say <message>"),
+        ] {
+            assert_eq!(AdminCommand::parse(line), Err(format!("Usage: {usage}")));
+        }
+    }
+
+    #[test]
+    fn bad_commands_and_addresses_are_errors() {
+        let error = AdminCommand::parse("reboot now").unwrap_err();
+        assert!(error.contains("\"reboot\""), "{error}");
+
+        let error = AdminCommand::parse("ban somewhere").unwrap_err();
+        assert!(error.contains("isn't an IP address"), "{error}");
+    }
+
+    #[test]
+    fn every_command_is_in_the_help() {
+        let help = help();
+        for command in COMMANDS {
+            assert!(help.contains(command.usage));
+        }
+    }
+
+    #[test]
+    fn command_names_complete() {
+        assert_eq!(complete("re", arguments), ["restart"]);
+        assert_eq!(complete("s", arguments), ["status", "set", "say"]);
+        assert_eq!(complete("", arguments).len(), COMMANDS.len());
+        assert!(complete("x", arguments).is_empty());
+    }
+
+    #[test]
+    fn arguments_complete() {
+        assert_eq!(complete("kick ", arguments), ["alice", "bob"]);
+        assert_eq!(complete("kick b", arguments), ["bob"]);
+        assert_eq!(
+            complete("set max_r", arguments),
+            ["max_rewind_ms", "max_rooms"]
+        );
+        assert!(complete("map ", arguments).is_empty());
+
+        // Only the first argument.
+        assert!(complete("set name b", arguments).is_empty());
+    }
+
+    #[test]
+    fn settings_change() {
+        let mut config = ServerConfig::default();
+        change_setting(&mut config, "name", "Snow day").unwrap();
+        change_setting(&mut config, "max_players", "4").unwrap();
+        change_setting(&mut config, "max_rewind_ms", "100").unwrap();
+        change_setting(&mut config, "kick_violations", "0").unwrap();
+
+        assert_eq!(config.name, "Snow day");
+        assert_eq!(config.max_players, 4);
+        assert_eq!(config.max_rewind, Duration::from_millis(100));
+        assert_eq!(config.kick_violations, None);
+        assert_eq!(setting(&config, "max_rewind_ms").as_deref(), Some("100"));
+        assert_eq!(setting(&config, "kick_violations").as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn every_setting_can_be_shown() {
+        let config = ServerConfig::default();
+        for name in SETTINGS {
+            assert!(setting(&config, name).is_some(), "{name}");
+        }
+        assert_eq!(setting(&config, "port"), None);
+    }
+
+    #[test]
+    fn unknown_settings_are_refused() {
+        let mut config = ServerConfig::default();
+        let error = change_setting(&mut config, "port", "9000").unwrap_err();
+        assert!(error.starts_with("No setting called \"port\""), "{error}");
+        assert_eq!(config.port, ServerConfig::default().port);
+    }
+
+    #[test]
+    fn numbers_have_to_be_numbers() {
+        let mut config = ServerConfig::default();
+        for name in SETTINGS
+            .iter()
+            .filter(|name| !["name", "mode"].contains(name))
+        {
+            assert_eq!(
+                change_setting(&mut config, name, "lots"),
+                Err(format!("{name} expects a number"))
+            );
+        }
+        assert_eq!(
+            change_setting(&mut config, "max_players", "-1"),
+            Err("max_players expects a number".to_string())
+        );
+        assert_eq!(config.max_players, ServerConfig::default().max_players);
+    }
+}
@@ -0,0 +1,697 @@
+//! Where the server's settings come from: built in defaults, then a TOML file,
+//! then command line flags, each overriding the one before. The file is read
+//! again on SIGHUP; flags keep winning over it, and settings a running server
+//! can't change are left as they were.
+
+use std::{
+    fmt, fs, io,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use protocol::{
+    MAX_FRAGMENT_SIZE, MAX_MESSAGE_SIZE, codec::MAX_STRING_LEN, netsim::NetworkConditions,
+};
+use serde::Deserialize;
+
+use crate::{maps, server::ServerConfig};
+
+/// Read if it exists and no other file was asked for.
+pub const DEFAULT_PATH: &str = "server.toml";
+
+/// Fastest tick rate the server will try to keep up.
+const MAX_TICK_RATE: u32 = 240;
+
+/// Room sizes go out as a byte.
+const MAX_PLAYERS: usize = u8::MAX as usize;
+
+/// Furthest back lag compensation may look.
+const MAX_REWIND: Duration = Duration::from_secs(1);
+
+pub const USAGE: &str = "\
+Usage: server [options]
+
+Options override the config file, which is server.toml unless --config says
+otherwise. Every option has a config file setting of the same name with
+underscores, like tick_rate for --tick-rate.
+
+  --config <path>           TOML file to read settings from
+  --bind <ip>               Address to listen on, IPv4 or IPv6 [0.0.0.0]
+                            An ip:port pair sets the port too
+  --port <port>             Game port [8080]
+  --name <name>             Shown in server browsers
+  --password <password>     Only let in players who give it
+  --mode <mode>             Shown in server browsers [free-for-all]
+  --maps <a,b,...>          Map rotation; each new room takes the next one
+  --maps-dir <path>         Where custom maps are, as <name>.toml [maps]
+  --tick-rate <hz>          Simulation and snapshot rate [60]
+  --max-players <n>         Players per room [16]
+  --max-rooms <n>           Rooms open at once [8]
+  --client-timeout-ms <ms>  Drop clients silent for this long [5000]
+  --reconnect-grace-ms <ms> Hold a dropped client's place this long [30000]
+  --max-rewind-ms <ms>      Furthest back hit detection looks [250]
+  --max-message-size <n>    Largest message to or from a client, in bytes
+                            [65536]
+  --kick-violations <n>     Kick after this much bad input, 0 never [10]
+  --discovery-port <port>   LAN discovery port, 0 turns it off [8079]
+  --netsim <preset>         Simulate a bad network, for testing
+  --help                    Show this";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Not a mistake, but there's nothing to run either.
+    Help,
+    Read {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Help => write!(f, "{USAGE}"),
+            ConfigError::Read { path, error } => {
+                write!(f, "can't read {}: {}", path.display(), error)
+            }
+            ConfigError::Parse { path, error } => write!(f, "in {}: {}", path.display(), error),
+            ConfigError::Invalid(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn invalid(message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid(message.into())
+}
+
+/// Settings as given in the config file or on the command line. Anything
+/// left out keeps its value from the layer below.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub bind: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub name: Option<String>,
+    /// Empty for none, to take away a password set further down.
+    pub password: Option<String>,
+    pub mode: Option<String>,
+    pub maps: Option<Vec<String>>,
+    pub maps_dir: Option<PathBuf>,
+    pub tick_rate: Option<u32>,
+    pub max_players: Option<usize>,
+    pub max_rooms: Option<usize>,
+    pub client_timeout_ms: Option<u64>,
+    /// 0 doesn't hold places at all.
+    pub reconnect_grace_ms: Option<u64>,
+    pub max_rewind_ms: Option<u64>,
+    pub max_message_size: Option<usize>,
+    /// 0 never kicks.
+    pub kick_violations: Option<u32>,
+    /// 0 turns discovery off.
+    pub discovery_port: Option<u16>,
+    pub netsim: Option<String>,
+}
+
+impl Settings {
+    fn apply(&self, config: &mut ServerConfig) -> Result<(), ConfigError> {
+        if let Some(bind) = self.bind {
+            config.bind = bind;
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(name) = &self.name {
+            config.name = name.clone();
+        }
+        if let Some(password) = &self.password {
+            config.password = (!password.is_empty()).then(|| password.clone());
+        }
+        if let Some(mode) = &self.mode {
+            config.mode = mode.clone();
+        }
+        if let Some(maps) = &self.maps {
+            config.maps = maps.clone();
+        }
+        if let Some(dir) = &self.maps_dir {
+            config.maps_dir = dir.clone();
+        }
+        if let Some(tick_rate) = self.tick_rate {
+            config.tick_rate = tick_rate;
+        }
+        if let Some(max_players) = self.max_players {
+            config.max_players = max_players;
+        }
+        if let Some(max_rooms) = self.max_rooms {
+            config.max_rooms = max_rooms;
+        }
+        if let Some(ms) = self.client_timeout_ms {
+            config.client_timeout = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.reconnect_grace_ms {
+            config.reconnect_grace = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.max_rewind_ms {
+            config.max_rewind = Duration::from_millis(ms);
+        }
+        if let Some(size) = self.max_message_size {
+            config.max_message_size = size;
+        }
+        if let Some(count) = self.kick_violations {
+            config.kick_violations = (count != 0).then_some(count);
+        }
+        if let Some(port) = self.discovery_port {
+            config.discovery_port = (port != 0).then_some(port);
+        }
+        if let Some(preset) = &self.netsim {
+            config.network = NetworkConditions::preset(preset).ok_or_else(|| {
+                invalid(format!(
+                    "netsim expects one of {}, not {preset:?}",
+                    NetworkConditions::PRESETS.join(", ")
+                ))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The config file and flags the server was started with, to load settings
+/// from now and again on reload.
+pub struct Source {
+    path: PathBuf,
+    /// False for the default path, which doesn't have to exist.
+    required: bool,
+    flags: Settings,
+}
+
+impl Source {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut path = None;
+        let mut flags = Settings::default();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return Err(ConfigError::Help);
+            }
+            let Some(value) = args.next() else {
+                return Err(invalid(format!("{arg} expects a value")));
+            };
+            match arg.as_str() {
+                "--config" => path = Some(PathBuf::from(value)),
+                "--bind" => {
+                    // An ip:port pair is what --bind used to take.
+                    if let Ok(addr) = value.parse::<SocketAddr>() {
+                        flags.bind = Some(addr.ip());
+                        flags.port = Some(addr.port());
+                    } else {
+                        flags.bind = Some(parse_flag(&arg, &value, "an IPv4 or IPv6 address")?);
+                    }
+                }
+                "--port" => flags.port = Some(parse_flag(&arg, &value, "a port number")?),
+                "--name" => flags.name = Some(value),
+                "--password" => flags.password = Some(value),
+                "--mode" => flags.mode = Some(value),
+                "--maps" => {
+                    flags.maps = Some(value.split(',').map(|map| map.trim().to_string()).collect());
+                }
+                "--maps-dir" => flags.maps_dir = Some(PathBuf::from(value)),
+                "--tick-rate" => flags.tick_rate = Some(parse_flag(&arg, &value, "a number")?),
+                "--max-players" => {
+                    flags.max_players = Some(parse_flag(&arg, &value, "a number")?);
+                }
+                "--max-rooms" => flags.max_rooms = Some(parse_flag(&arg, &value, "a number")?),
+                "--client-timeout-ms" => {
+                    flags.client_timeout_ms = Some(parse_flag(&arg, &value, "a number")?);
+                }
+                "--reconnect-grace-ms" => {
+                    flags.reconnect_grace_ms = Some(parse_flag(&arg, &value, "a number")?);
+                }
+                "--max-rewind-ms" => {
+                    flags.max_rewind_ms = Some(parse_flag(&arg, &value, "a number")?);
+                }
+                "--max-message-size" => {
+                    flags.max_message_size = Some(parse_flag(&arg, &value, "a number of bytes")?);
+                }
+                "--kick-violations" => {
+                    flags.kick_violations =
+                        Some(parse_flag(&arg, &value, "a number, or 0 to never kick")?);
+                }
+                "--discovery-port" => {
+                    flags.discovery_port = Some(parse_flag(
+                        &arg,
+                        &value,
+                        "a port number, or 0 to turn discovery off",
+                    )?);
+                }
+                "--netsim" => flags.netsim = Some(value),
+                _ => return Err(invalid(format!("unknown option {arg:?}"))),
+            }
+        }
+
+        Ok(Self {
+            required: path.is_some(),
+            path: path.unwrap_or_else(|| PathBuf::from(DEFAULT_PATH)),
+            flags,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Defaults, overridden by the file, overridden by the flags.
+    pub fn load(&self) -> Result<ServerConfig, ConfigError> {
+        let mut config = ServerConfig::default();
+        if let Some(file) = self.read_file()? {
+            file.apply(&mut config)?;
+        }
+        self.flags.apply(&mut config)?;
+        validate(&config)?;
+        Ok(config)
+    }
+
+    fn read_file(&self) -> Result<Option<Settings>, ConfigError> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !self.required => return Ok(None),
+            Err(error) => {
+                return Err(ConfigError::Read {
+                    path: self.path.clone(),
+                    error,
+                });
+            }
+        };
+        toml::from_str(&text)
+            .map(Some)
+            .map_err(|error| ConfigError::Parse {
+                path: self.path.clone(),
+                error,
+            })
+    }
+}
+
+fn parse_flag<T: std::str::FromStr>(
+    flag: &str,
+    value: &str,
+    expected: &str,
+) -> Result<T, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("{flag} expects {expected}, not {value:?}")))
+}
+
+/// Checks everything is in range, so mistakes show up at startup rather
+/// than as odd behaviour later.
+pub fn validate(config: &ServerConfig) -> Result<(), ConfigError> {
+    for (setting, value) in [("name", &config.name), ("mode", &config.mode)] {
+        if value.is_empty() || value.len() > MAX_STRING_LEN {
+            return Err(invalid(format!(
+                "{setting} must be 1 to {MAX_STRING_LEN} bytes long"
+            )));
+        }
+    }
+    if config
+        .password
+        .as_ref()
+        .is_some_and(|password| password.len() > MAX_STRING_LEN)
+    {
+        return Err(invalid(format!(
+            "password can be at most {MAX_STRING_LEN} bytes long"
+        )));
+    }
+    if config.maps.is_empty() {
+        return Err(invalid("maps needs at least one map"));
+    }
+    for map in &config.maps {
+        maps::load(&config.maps_dir, map).map_err(invalid)?;
+    }
+    if !(1..=MAX_TICK_RATE).contains(&config.tick_rate) {
+        return Err(invalid(format!(
+            "tick_rate must be between 1 and {MAX_TICK_RATE}"
+        )));
+    }
+    if !(1..=MAX_PLAYERS).contains(&config.max_players) {
+        return Err(invalid(format!(
+            "max_players must be between 1 and {MAX_PLAYERS}"
+        )));
+    }
+    if config.max_rooms == 0 {
+        return Err(invalid("max_rooms must be at least 1"));
+    }
+    if config.client_timeout.is_zero() {
+        return Err(invalid("client_timeout_ms must be more than 0"));
+    }
+    if config.max_rewind > MAX_REWIND {
+        return Err(invalid(format!(
+            "max_rewind_ms can be at most {}",
+            MAX_REWIND.as_millis()
+        )));
+    }
+    if !(MAX_FRAGMENT_SIZE..=MAX_MESSAGE_SIZE).contains(&config.max_message_size) {
+        return Err(invalid(format!(
+            "max_message_size must be between {MAX_FRAGMENT_SIZE} and {MAX_MESSAGE_SIZE}"
+        )));
+    }
+    Ok(())
+}
+
+/// SIGHUP, where there is such a thing.
+pub struct Hangups {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Default for Hangups {
+    fn default() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+            let signal = signal(SignalKind::hangup())
+                .inspect_err(|e| println!("Can't listen for SIGHUP, no reloading: {}", e))
+                .ok();
+            Self { signal }
+        }
+        #[cfg(not(unix))]
+        Self {}
+    }
+}
+
+impl Hangups {
+    /// Waits for the next one. Never returns if there are none to wait for.
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn from_flags(flags: &[&str]) -> Result<Source, ConfigError> {
+        Source::from_args(args(flags))
+    }
+
+    /// A config file of its own for each test, under the system temp dir.
+    fn config_file(test: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("snowball-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{test}.toml"));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn message(result: Result<(), ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid(message)) => message,
+            Err(error) => panic!("expected an invalid setting, got {error:?}"),
+            Ok(()) => panic!("expected an invalid setting"),
+        }
+    }
+
+    #[test]
+    fn flags_are_parsed() {
+        let source = from_flags(&[
+            "--port",
+            "9000",
+            "--name",
+            "Backyard",
+            "--maps",
+            "shapes, arena",
+            "--tick-rate",
+            "30",
+            "--kick-violations",
+            "0",
+        ])
+        .unwrap();
+
+        assert_eq!(source.path(), Path::new(DEFAULT_PATH));
+        assert!(!source.required);
+        assert_eq!(source.flags.port, Some(9000));
+        assert_eq!(source.flags.name.as_deref(), Some("Backyard"));
+        assert_eq!(
+            source.flags.maps,
+            Some(vec!["shapes".to_string(), "arena".to_string()])
+        );
+        assert_eq!(source.flags.tick_rate, Some(30));
+        assert_eq!(source.flags.kick_violations, Some(0));
+        assert_eq!(source.flags.bind, None);
+    }
+
+    #[test]
+    fn bind_takes_an_address() {
+        let source = from_flags(&["--bind", "::1"]).unwrap();
+        assert_eq!(source.flags.bind, Some("::1".parse().unwrap()));
+        assert_eq!(source.flags.port, None);
+    }
+
+    #[test]
+    fn bind_still_takes_an_address_and_port() {
+        let source = from_flags(&["--bind", "127.0.0.1:9000"]).unwrap();
+        assert_eq!(source.flags.bind, Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(source.flags.port, Some(9000));
+
+        let source = from_flags(&["--bind", "[::1]:9001"]).unwrap();
+        assert_eq!(source.flags.bind, Some("::1".parse().unwrap()));
+        assert_eq!(source.flags.port, Some(9001));
+    }
+
+    #[test]
+    fn a_config_flag_makes_the_file_required() {
+        let source = from_flags(&["--config", "elsewhere.toml"]).unwrap();
+        assert_eq!(source.path(), Path::new("elsewhere.toml"));
+        assert!(source.required);
+    }
+
+    #[test]
+    fn bad_flags_are_errors() {
+        assert!(matches!(from_flags(&["--help"]), Err(ConfigError::Help)));
+        assert!(matches!(from_flags(&["-h"]), Err(ConfigError::Help)));
+
+        let Err(ConfigError::Invalid(message)) = from_flags(&["--colour", "blue"]) else {
+            panic!("expected an unknown option");
+        };
+        assert!(message.contains("unknown option"), "{message}");
+
+        let Err(ConfigError::Invalid(message)) = from_flags(&["--port", "9000", "--name"]) else {
+            panic!("expected a missing value");
+        };
+        assert_eq!(message, "--name expects a value");
+
+        let Err(ConfigError::Invalid(message)) = from_flags(&["--port", "lots"]) else {
+            panic!("expected a bad value");
+        };
+        assert!(message.contains("a port number"), "{message}");
+
+        assert!(matches!(
+            from_flags(&["--bind", "localhost"]),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn flags_override_the_file_which_overrides_defaults() {
+        let path = config_file(
+            "layers",
+            "port = 9000\nname = \"From the file\"\ntick_rate = 30\n",
+        );
+        let source =
+            from_flags(&["--config", path.to_str().unwrap(), "--tick-rate", "20"]).unwrap();
+
+        let config = source.load().unwrap();
+        let defaults = ServerConfig::default();
+        assert_eq!(config.max_players, defaults.max_players);
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.name, "From the file");
+        assert_eq!(config.tick_rate, 20);
+    }
+
+    #[test]
+    fn misspelled_settings_are_rejected() {
+        let path = config_file("typo", "tick_rte = 30\n");
+        let source = from_flags(&["--config", path.to_str().unwrap()]).unwrap();
+        let Err(ConfigError::Parse { error, .. }) = source.load() else {
+            panic!("expected a parse error");
+        };
+        assert!(error.to_string().contains("tick_rte"), "{error}");
+    }
+
+    #[test]
+    fn a_missing_file_is_only_an_error_when_asked_for() {
+        let path = std::env::temp_dir().join("snowball-config-that-isnt-there.toml");
+        let source = from_flags(&["--config", path.to_str().unwrap()]).unwrap();
+        assert!(matches!(source.load(), Err(ConfigError::Read { .. })));
+
+        let source = Source {
+            path,
+            required: false,
+            flags: Settings::default(),
+        };
+        assert!(source.load().is_ok());
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        validate(&ServerConfig::default()).unwrap();
+    }
+
+    #[test]
+    fn names_and_modes_need_a_length() {
+        let too_long = "x".repeat(MAX_STRING_LEN + 1);
+        for config in [
+            ServerConfig {
+                name: String::new(),
+                ..Default::default()
+            },
+            ServerConfig {
+                name: too_long.clone(),
+                ..Default::default()
+            },
+            ServerConfig {
+                mode: String::new(),
+                ..Default::default()
+            },
+            ServerConfig {
+                mode: too_long.clone(),
+                ..Default::default()
+            },
+        ] {
+            let message = message(validate(&config));
+            assert!(message.contains("bytes long"), "{message}");
+        }
+
+        let config = ServerConfig {
+            password: Some(too_long),
+            ..Default::default()
+        };
+        assert!(message(validate(&config)).starts_with("password"));
+    }
+
+    #[test]
+    fn maps_have_to_exist() {
+        let config = ServerConfig {
+            maps: Vec::new(),
+            ..Default::default()
+        };
+        assert!(message(validate(&config)).starts_with("maps"));
+
+        // Not a name that could be a file, so nothing is read.
+        let config = ServerConfig {
+            maps: vec!["../secrets".to_string()],
+            ..Default::default()
+        };
+        assert!(message(validate(&config)).contains("no map called"));
+    }
+
+    #[test]
+    fn numbers_have_to_be_in_range() {
+        let cases = [
+            (
+                ServerConfig {
+                    tick_rate: 0,
+                    ..Default::default()
+                },
+                "tick_rate",
+            ),
+            (
+                ServerConfig {
+                    tick_rate: MAX_TICK_RATE + 1,
+                    ..Default::default()
+                },
+                "tick_rate",
+            ),
+            (
+                ServerConfig {
+                    max_players: 0,
+                    ..Default::default()
+                },
+                "max_players",
+            ),
+            (
+                ServerConfig {
+                    max_players: MAX_PLAYERS + 1,
+                    ..Default::default()
+                },
+                "max_players",
+            ),
+            (
+                ServerConfig {
+                    max_rooms: 0,
+                    ..Default::default()
+                },
+                "max_rooms",
+            ),
+            (
+                ServerConfig {
+                    client_timeout: Duration::ZERO,
+                    ..Default::default()
+                },
+                "client_timeout_ms",
+            ),
+            (
+                ServerConfig {
+                    max_rewind: MAX_REWIND + Duration::from_millis(1),
+                    ..Default::default()
+                },
+                "max_rewind_ms",
+            ),
+            (
+                ServerConfig {
+                    max_message_size: MAX_FRAGMENT_SIZE - 1,
+                    ..Default::default()
+                },
+                "max_message_size",
+            ),
+            (
+                ServerConfig {
+                    max_message_size: MAX_MESSAGE_SIZE + 1,
+                    ..Default::default()
+                },
+                "max_message_size",
+            ),
+        ];
+        for (config, setting) in cases {
+            let message = message(validate(&config));
+            assert!(message.starts_with(setting), "{message}");
+        }
+
+        // The ends of each range are fine.
+        validate(&ServerConfig {
+            tick_rate: MAX_TICK_RATE,
+            max_players: MAX_PLAYERS,
+            max_rewind: MAX_REWIND,
+            max_message_size: MAX_FRAGMENT_SIZE,
+            ..Default::default()
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn zero_turns_kicking_and_discovery_off() {
+        let settings = Settings {
+            kick_violations: Some(0),
+            discovery_port: Some(0),
+            ..Default::default()
+        };
+        let mut config = ServerConfig::default();
+        settings.apply(&mut config).unwrap();
+        assert_eq!(config.kick_violations, None);
+        assert_eq!(config.discovery_port, None);
+    }
+}
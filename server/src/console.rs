@@ -0,0 +1,100 @@
+//! The admin console on stdin, with tab completion and history.
+
+use std::{io, thread};
+
+use rustyline::{
+    Context, Editor, Helper, completion::Completer, error::ReadlineError, highlight::Highlighter,
+    hint::Hinter, history::DefaultHistory, validate::Validator,
+};
+
+use crate::admin::{Admin, AdminCommand};
+
+/// Reads commands on a thread of its own until stdin closes or the server
+/// is told to quit.
+pub fn spawn(admin: Admin) -> io::Result<()> {
+    thread::Builder::new()
+        .name("console".to_string())
+        .spawn(move || run(admin))?;
+    Ok(())
+}
+
+fn run(admin: Admin) {
+    let mut editor = match Editor::<ConsoleHelper, DefaultHistory>::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            println!("No admin console: {}", e);
+            return;
+        }
+    };
+    editor.set_helper(Some(ConsoleHelper {
+        admin: admin.clone(),
+    }));
+
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            // The terminal is in raw mode while we read, so Ctrl-C lands
+            // here instead of with the server.
+            Err(ReadlineError::Interrupted) => "quit".to_string(),
+            // Running without a terminal, most likely. The server carries on.
+            Err(ReadlineError::Eof) => return,
+            Err(e) => {
+                println!("Admin console stopped: {}", e);
+                return;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+
+        let quit = AdminCommand::parse(line) == Ok(Some(AdminCommand::Quit));
+        match admin.execute(line).blocking_recv() {
+            Ok(answer) if !answer.is_empty() => println!("{answer}"),
+            Ok(_) => {}
+            // The server has stopped.
+            Err(_) => return,
+        }
+        // Reading again would leave the terminal in raw mode when the
+        // process exits.
+        if quit {
+            return;
+        }
+    }
+}
+
+struct ConsoleHelper {
+    admin: Admin,
+}
+
+impl Completer for ConsoleHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        // Where the last word starts.
+        let start = line.trim_end_matches(|c: char| !c.is_whitespace()).len();
+        let choices = self
+            .admin
+            .complete(line)
+            .blocking_recv()
+            .unwrap_or_default();
+        Ok((start, choices))
+    }
+}
+
+impl Hinter for ConsoleHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ConsoleHelper {}
+
+impl Validator for ConsoleHelper {}
+
+impl Helper for ConsoleHelper {}
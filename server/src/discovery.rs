@@ -0,0 +1,172 @@
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use protocol::{
+    PROTOCOL_VERSION, RECV_BUFFER_SIZE,
+    discovery::{Announcement, DiscoveryMessage},
+};
+use tokio::net::UdpSocket;
+
+use crate::{info::InfoLimiter, server::clamp_u16};
+
+/// How often addresses the limiter has stopped caring about are dropped.
+const FORGET_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What the server tells players looking for a game on the local network.
+pub struct Listing {
+    /// Random for each run, see `Announcement::server_id`.
+    pub server_id: u64,
+    pub game_port: u16,
+    /// Kept up to date by the router.
+    pub players: Arc<AtomicUsize>,
+    /// Kept up to date by the router as admins change settings.
+    pub details: Arc<Mutex<ListingDetails>>,
+}
+
+pub struct ListingDetails {
+    pub name: String,
+    pub map: String,
+    pub max_players: usize,
+}
+
+/// Listens for discovery queries on `port`, next to the game socket at
+/// `game`. A server on every interface hears broadcasts and loopback queries
+/// alike; one bound to a single address only answers queries sent there, so
+/// nobody is told about an address they can't play on.
+pub async fn bind(game: SocketAddr, port: u16) -> std::io::Result<UdpSocket> {
+    let ip = match game.ip() {
+        IpAddr::V4(ip) => ip,
+        // Discovery is IPv4 broadcast only.
+        IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+    };
+    UdpSocket::bind(SocketAddr::from((ip, port))).await
+}
+
+/// Answers queries with the current listing until the task is aborted,
+/// within the same limits as info queries.
+pub async fn answer_queries(socket: UdpSocket, listing: Listing) {
+    let mut buf = [0; RECV_BUFFER_SIZE];
+    let mut limiter = InfoLimiter::new(Instant::now());
+    let mut forgotten = Instant::now();
+    loop {
+        let (len, addr) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            // Windows reports ICMP port unreachable from an earlier send here.
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+            Err(e) => {
+                println!("Discovery stopped: {}", e);
+                return;
+            }
+        };
+        let Ok(DiscoveryMessage::Query { nonce }) = protocol::decode(&buf[..len]) else {
+            continue;
+        };
+        // An answer is a lot bigger than a query, so without a limit anyone
+        // could have us flood an address they forged.
+        let now = Instant::now();
+        if now.duration_since(forgotten) >= FORGET_INTERVAL {
+            limiter.forget_idle(now);
+            forgotten = now;
+        }
+        if !limiter.allow(addr.ip(), now) {
+            continue;
+        }
+
+        let announcement = {
+            let details = listing
+                .details
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            DiscoveryMessage::Announce(Announcement {
+                nonce,
+                server_id: listing.server_id,
+                protocol_version: PROTOCOL_VERSION,
+                game_port: listing.game_port,
+                name: details.name.clone(),
+                map: details.map.clone(),
+                players: clamp_u16(listing.players.load(Ordering::Relaxed)),
+                max_players: clamp_u16(details.max_players),
+            })
+        };
+        match protocol::encode(&announcement) {
+            Ok(bytes) => {
+                if let Err(e) = socket.send_to(&bytes, addr).await {
+                    println!("Answering discovery from {:?} failed: {}", addr, e);
+                }
+            }
+            Err(e) => println!("Can't encode announcement: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing() -> Listing {
+        Listing {
+            server_id: 42,
+            game_port: 7777,
+            players: Arc::new(AtomicUsize::new(3)),
+            details: Arc::new(Mutex::new(ListingDetails {
+                name: "test".to_string(),
+                map: "shapes".to_string(),
+                max_players: 16,
+            })),
+        }
+    }
+
+    /// Sends `count` queries and collects the answers that come back.
+    async fn query(server: SocketAddr, count: u64) -> Vec<Announcement> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        for nonce in 0..count {
+            let query = protocol::encode(&DiscoveryMessage::Query { nonce }).unwrap();
+            socket.send_to(&query, server).await.unwrap();
+        }
+
+        let mut answers = Vec::new();
+        let mut buf = [0; RECV_BUFFER_SIZE];
+        while let Ok(Ok(len)) =
+            tokio::time::timeout(Duration::from_millis(200), socket.recv(&mut buf)).await
+        {
+            match protocol::decode(&buf[..len]) {
+                Ok(DiscoveryMessage::Announce(announcement)) => answers.push(announcement),
+                other => panic!("expected an announcement, got {other:?}"),
+            }
+        }
+        answers
+    }
+
+    #[tokio::test]
+    async fn answers_queries_within_the_limit() {
+        let socket = bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)), 0)
+            .await
+            .unwrap();
+        let server = socket.local_addr().unwrap();
+        let task = tokio::spawn(answer_queries(socket, listing()));
+
+        let answers = query(server, 1).await;
+        assert_eq!(answers.len(), 1);
+        let answer = &answers[0];
+        assert_eq!(answer.nonce, 0);
+        assert_eq!(answer.server_id, 42);
+        assert_eq!(answer.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(answer.game_port, 7777);
+        assert_eq!(answer.name, "test");
+        assert_eq!(answer.players, 3);
+
+        // A burst from one address only gets so far.
+        let answers = query(server, 100).await;
+        assert!(!answers.is_empty());
+        assert!(answers.len() < 20, "{} answers", answers.len());
+
+        task.abort();
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use protocol::{
+    EncodeError,
+    info::{self, MAX_INFO_PLAYERS, ServerInfo},
+};
+
+/// Info queries one address can make per second, once its burst is used up.
+const ADDR_RATE: f32 = 4.0;
+const ADDR_BURST: f32 = 8.0;
+
+/// Info queries answered per second across every address, so spoofing lots of
+/// addresses doesn't get around the per-address limit.
+const TOTAL_RATE: f32 = 200.0;
+const TOTAL_BURST: f32 = 400.0;
+
+/// Decides which info queries to answer. Each address gets a bucket of
+/// tokens that refills over time, and every answer costs one.
+pub struct InfoLimiter {
+    addrs: HashMap<IpAddr, Bucket>,
+    total: Bucket,
+}
+
+impl InfoLimiter {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            addrs: HashMap::new(),
+            total: Bucket::full(TOTAL_BURST, now),
+        }
+    }
+
+    pub fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+        // Checked first so a flood can't fill the map with new addresses.
+        if !self.total.has_token(TOTAL_RATE, TOTAL_BURST, now) {
+            return false;
+        }
+        let addr = self
+            .addrs
+            .entry(ip)
+            .or_insert_with(|| Bucket::full(ADDR_BURST, now));
+        if !addr.has_token(ADDR_RATE, ADDR_BURST, now) {
+            return false;
+        }
+        addr.tokens -= 1.0;
+        self.total.tokens -= 1.0;
+        true
+    }
+
+    /// Forgets addresses whose buckets have refilled, which is no different
+    /// from never having seen them.
+    pub fn forget_idle(&mut self, now: Instant) {
+        let refill = Duration::from_secs_f32(ADDR_BURST / ADDR_RATE);
+        self.addrs
+            .retain(|_, bucket| now.duration_since(bucket.updated) < refill);
+    }
+}
+
+struct Bucket {
+    tokens: f32,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(burst: f32, now: Instant) -> Self {
+        Self {
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    fn has_token(&mut self, rate: f32, burst: f32, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.updated).as_secs_f32();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+        self.tokens >= 1.0
+    }
+}
+
+/// Encodes `info`, leaving out the lowest scoring players until it fits in
+/// a datagram.
+pub fn encode_answer(mut info: ServerInfo) -> Result<Vec<u8>, EncodeError> {
+    info.players.sort_by_key(|player| Reverse(player.score));
+    info.players.truncate(MAX_INFO_PLAYERS);
+    loop {
+        match info::encode_info(&info) {
+            Err(EncodeError::PacketTooLarge { .. }) if !info.players.is_empty() => {
+                info.players.pop();
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 168, 1, last))
+    }
+
+    /// How many of `count` queries at once get answered.
+    fn answered(limiter: &mut InfoLimiter, ip: IpAddr, count: usize, now: Instant) -> usize {
+        (0..count).filter(|_| limiter.allow(ip, now)).count()
+    }
+
+    #[test]
+    fn a_burst_from_one_address_is_throttled() {
+        let now = Instant::now();
+        let mut limiter = InfoLimiter::new(now);
+
+        assert_eq!(answered(&mut limiter, ip(1), 20, now), ADDR_BURST as usize);
+        assert!(!limiter.allow(ip(1), now));
+    }
+
+    #[test]
+    fn other_addresses_are_unaffected() {
+        let now = Instant::now();
+        let mut limiter = InfoLimiter::new(now);
+        answered(&mut limiter, ip(1), 20, now);
+
+        assert_eq!(answered(&mut limiter, ip(2), 20, now), ADDR_BURST as usize);
+        assert!(limiter.allow(
+            IpAddr::V6(Ipv4Addr::new(192, 168, 1, 1).to_ipv6_mapped()),
+            now
+        ));
+    }
+
+    #[test]
+    fn addresses_recover_over_time() {
+        let start = Instant::now();
+        let mut limiter = InfoLimiter::new(start);
+        answered(&mut limiter, ip(1), 20, start);
+
+        // A quarter of a second earns one more.
+        let later = start + Duration::from_millis(250);
+        assert_eq!(answered(&mut limiter, ip(1), 20, later), 1);
+
+        // And long enough to refill the bucket earns the whole burst, but no
+        // more than that.
+        let much_later = later + Duration::from_secs(10);
+        assert_eq!(
+            answered(&mut limiter, ip(1), 20, much_later),
+            ADDR_BURST as usize
+        );
+    }
+
+    #[test]
+    fn many_addresses_share_a_limit() {
+        let now = Instant::now();
+        let mut limiter = InfoLimiter::new(now);
+
+        let addresses = (TOTAL_BURST / ADDR_BURST) as u8;
+        for last in 0..addresses {
+            assert_eq!(
+                answered(&mut limiter, ip(last), 20, now),
+                ADDR_BURST as usize
+            );
+        }
+        assert!(!limiter.allow(ip(addresses), now));
+
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.allow(ip(addresses), later));
+    }
+
+    #[test]
+    fn idle_addresses_are_forgotten() {
+        let start = Instant::now();
+        let mut limiter = InfoLimiter::new(start);
+        limiter.allow(ip(1), start);
+        limiter.allow(ip(2), start + Duration::from_secs(1));
+
+        limiter.forget_idle(start + Duration::from_millis(2500));
+        assert!(!limiter.addrs.contains_key(&ip(1)));
+        assert!(limiter.addrs.contains_key(&ip(2)));
+    }
+}
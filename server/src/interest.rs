@@ -0,0 +1,333 @@
+//! Interest management: which entities each client hears about, and how
+//! often. Whatever is near the client's player goes out every tick, further
+//! away less often, and past the view distance not at all. An entity that's
+//! in view but not due an update goes out as it was last sent, so the client
+//! holds it where it was; dropping out of the set is what despawns it there.
+
+use std::collections::{HashMap, HashSet};
+
+use bevy::math::Vec3;
+use protocol::{
+    ClientId, EntityKind, EntityState, MAX_SNAPSHOT_ENTITIES, NetEntity, delta::SNAPSHOT_HISTORY,
+};
+
+/// Entities this close are updated every tick.
+const NEAR: f32 = 20.0;
+
+/// Entities come into view inside this distance...
+const VIEW_DISTANCE: f32 = 60.0;
+/// ...and go out of it past this one, so nothing flickers at the edge.
+const FORGET_DISTANCE: f32 = 70.0;
+
+/// Ticks between updates for an entity at the view distance.
+const MAX_UPDATE_INTERVAL: f32 = 4.0;
+
+/// Entities a snapshot can carry changes for, so it fits in a datagram.
+/// The client's own player always goes out on top.
+const MAX_UPDATES: usize = 32;
+
+/// Ticks' worth of updates an entity can save up while it waits for room in
+/// a snapshot.
+const MAX_WAITING: f32 = 8.0;
+
+struct Tracked {
+    /// What the client was last sent and the tick it went out on, if it has
+    /// been sent anything yet.
+    sent: Option<(EntityState, u32)>,
+    /// Builds up by the entity's update rate every tick and is spent when
+    /// it's sent; an entity is due an update at 1.
+    waiting: f32,
+}
+
+/// One client's view of the world.
+#[derive(Default)]
+pub struct Interest {
+    tracked: HashMap<NetEntity, Tracked>,
+}
+
+impl Interest {
+    /// Picks what goes in `client`'s snapshot for `tick` out of every entity
+    /// in the world. `acked` is the newest snapshot the client has
+    /// acknowledged, which says what it already has.
+    pub fn select(
+        &mut self,
+        client: ClientId,
+        tick: u32,
+        acked: Option<u32>,
+        entities: &[EntityState],
+    ) -> Vec<EntityState> {
+        // Past the snapshot history it can't be a baseline, and everything
+        // goes out in full.
+        let acked = acked.filter(|acked| tick.wrapping_sub(*acked) < SNAPSHOT_HISTORY as u32);
+        let viewer = entities
+            .iter()
+            .find(|entity| entity.kind == EntityKind::Player(client))
+            .map(|entity| Vec3::from_array(entity.translation));
+
+        let mut in_view: Vec<(&EntityState, f32)> = entities
+            .iter()
+            .filter_map(|entity| {
+                let distance = viewer.map_or(0., |viewer| {
+                    viewer.distance(Vec3::from_array(entity.translation))
+                });
+                let limit = match self.tracked.contains_key(&entity.id) {
+                    true => FORGET_DISTANCE,
+                    false => VIEW_DISTANCE,
+                };
+                let mine = entity.kind == EntityKind::Player(client);
+                (mine || distance < limit).then_some((entity, distance))
+            })
+            .collect();
+        if in_view.len() > MAX_SNAPSHOT_ENTITIES {
+            // More than a snapshot can hold: the client's own player stays,
+            // then whatever is closest for how much it matters. The rest are
+            // forgotten as if they'd gone out of view.
+            let priority = |&(entity, distance): &(&EntityState, f32)| match entity.kind {
+                EntityKind::Player(id) if id == client => f32::NEG_INFINITY,
+                _ => distance / weight(entity, client),
+            };
+            in_view.sort_by(|a, b| priority(a).total_cmp(&priority(b)));
+            in_view.truncate(MAX_SNAPSHOT_ENTITIES);
+        }
+        let ids: HashSet<NetEntity> = in_view.iter().map(|(entity, _)| entity.id).collect();
+        self.tracked.retain(|id, _| ids.contains(id));
+
+        let mut updates = HashSet::new();
+        let mut candidates = Vec::new();
+        for &(entity, distance) in &in_view {
+            let tracked = self.tracked.entry(entity.id).or_insert(Tracked {
+                sent: None,
+                waiting: 0.,
+            });
+            if entity.kind == EntityKind::Player(client) {
+                updates.insert(entity.id);
+                continue;
+            }
+
+            let (known, changed) = match &tracked.sent {
+                Some((state, sent)) => (acked.is_some_and(|acked| acked >= *sent), state != entity),
+                None => (false, true),
+            };
+            if !changed && known {
+                // Nothing new to say, and no bytes to say it in.
+                continue;
+            }
+            tracked.waiting = (tracked.waiting + rate(distance)).min(MAX_WAITING);
+            if tracked.sent.is_some() && !known {
+                // The client hasn't got it yet, so it costs a place whether
+                // it's updated or held.
+                updates.insert(entity.id);
+            } else if tracked.waiting >= 1. {
+                candidates.push((entity.id, tracked.waiting * weight(entity, client)));
+            }
+        }
+
+        // What matters most goes first, but the longer something waits the
+        // more it matters, so nothing is put off for ever.
+        candidates.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        let room = MAX_UPDATES.saturating_sub(updates.len());
+        updates.extend(candidates.into_iter().take(room).map(|(id, _)| id));
+
+        let mut selected = Vec::new();
+        for &(entity, _) in &in_view {
+            let Some(tracked) = self.tracked.get_mut(&entity.id) else {
+                continue;
+            };
+            let due = tracked.waiting >= 1. || entity.kind == EntityKind::Player(client);
+            if due && updates.contains(&entity.id) {
+                tracked.sent = Some((entity.clone(), tick));
+                tracked.waiting = 0.;
+            }
+            if let Some((state, _)) = &tracked.sent {
+                selected.push(state.clone());
+            }
+        }
+        selected.sort_by_key(|entity| entity.id);
+        selected
+    }
+}
+
+/// How much of an update an entity earns each tick: a whole one close up,
+/// falling off with distance to one every `MAX_UPDATE_INTERVAL` ticks at the
+/// edge of view.
+fn rate(distance: f32) -> f32 {
+    let falloff = ((distance - NEAR) / (VIEW_DISTANCE - NEAR)).clamp(0., 1.);
+    1. / (1. + falloff * (MAX_UPDATE_INTERVAL - 1.))
+}
+
+/// How much an update matters next to others waiting for room in the same
+/// snapshot.
+fn weight(entity: &EntityState, client: ClientId) -> f32 {
+    match entity.kind {
+        // Players watch other players, and where their own snowballs go,
+        // most closely.
+        EntityKind::Player(_) => 4.0,
+        EntityKind::Snowball { owner } if owner == client => 4.0,
+        EntityKind::Snowball { .. } => 1.0,
+        // The client has its own copy of the level's props.
+        EntityKind::Prop { .. } => 0.5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEWER: ClientId = ClientId(1);
+
+    fn entity(id: u32, kind: EntityKind, x: f32) -> EntityState {
+        EntityState {
+            id: NetEntity(id),
+            kind,
+            translation: [x, 0., 0.],
+            rotation: [0., 0., 0., 1.],
+            velocity: [0.; 3],
+            components: Vec::new(),
+        }
+    }
+
+    fn viewer() -> EntityState {
+        entity(0, EntityKind::Player(VIEWER), 0.)
+    }
+
+    fn other(id: u32, x: f32) -> EntityState {
+        entity(id, EntityKind::Player(ClientId(id + 100)), x)
+    }
+
+    fn ids(selected: &[EntityState]) -> Vec<u32> {
+        selected.iter().map(|entity| entity.id.0).collect()
+    }
+
+    /// Selects for `ticks` ticks from `start`, with the client acking every
+    /// snapshot as it arrives, and returns the last selection.
+    fn run(
+        interest: &mut Interest,
+        start: u32,
+        ticks: u32,
+        entities: &[EntityState],
+    ) -> Vec<EntityState> {
+        let mut selected = Vec::new();
+        for tick in start..start + ticks {
+            selected = interest.select(VIEWER, tick, tick.checked_sub(1), entities);
+        }
+        selected
+    }
+
+    #[test]
+    fn the_clients_own_player_always_goes_out() {
+        let mut interest = Interest::default();
+        let selected = interest.select(VIEWER, 0, None, &[viewer()]);
+        assert_eq!(ids(&selected), [0]);
+    }
+
+    #[test]
+    fn entities_come_into_view_nearer_than_they_leave_it() {
+        let mut interest = Interest::default();
+
+        // Between the view and forget distances, and never seen: stays out.
+        let selected = run(&mut interest, 0, 10, &[viewer(), other(1, 65.)]);
+        assert_eq!(ids(&selected), [0]);
+
+        let selected = run(&mut interest, 10, 10, &[viewer(), other(1, 55.)]);
+        assert_eq!(ids(&selected), [0, 1]);
+
+        // Once seen, it's kept until past the forget distance.
+        let selected = run(&mut interest, 20, 10, &[viewer(), other(1, 65.)]);
+        assert_eq!(ids(&selected), [0, 1]);
+
+        let selected = run(&mut interest, 30, 1, &[viewer(), other(1, 75.)]);
+        assert_eq!(ids(&selected), [0]);
+
+        // And once forgotten it has to come all the way back in.
+        let selected = run(&mut interest, 31, 10, &[viewer(), other(1, 65.)]);
+        assert_eq!(ids(&selected), [0]);
+    }
+
+    #[test]
+    fn leaving_view_is_a_removal() {
+        let mut interest = Interest::default();
+        let selected = run(
+            &mut interest,
+            0,
+            1,
+            &[viewer(), other(1, 5.), other(2, 10.)],
+        );
+        assert_eq!(ids(&selected), [0, 1, 2]);
+
+        // Gone from the world, and gone out of range: both drop out of the
+        // snapshot, which is what despawns them on the client.
+        let selected = run(&mut interest, 1, 1, &[viewer(), other(2, 100.)]);
+        assert_eq!(ids(&selected), [0]);
+    }
+
+    #[test]
+    fn far_entities_are_updated_less_often() {
+        assert_eq!(rate(0.), 1.);
+        assert_eq!(rate(NEAR), 1.);
+        assert_eq!(rate(VIEW_DISTANCE), 1. / MAX_UPDATE_INTERVAL);
+        assert!(rate(40.) < rate(30.));
+
+        let mut interest = Interest::default();
+        let mut near_updates = 0;
+        let mut far_updates = 0;
+        for tick in 0..40 {
+            // Something new about both on every tick.
+            let mut near = other(1, 10.);
+            let mut far = other(2, 59.);
+            near.velocity[0] = tick as f32;
+            far.velocity[0] = tick as f32;
+
+            let selected =
+                interest.select(VIEWER, tick, tick.checked_sub(1), &[viewer(), near, far]);
+            for entity in &selected {
+                if entity.velocity[0] == tick as f32 {
+                    match entity.id.0 {
+                        1 => near_updates += 1,
+                        _ => far_updates += 1,
+                    }
+                }
+            }
+        }
+
+        assert_eq!(near_updates, 40);
+        assert!((9..=11).contains(&far_updates), "{far_updates}");
+    }
+
+    #[test]
+    fn updates_are_capped_with_players_and_own_snowballs_first() {
+        let mut entities = vec![viewer()];
+        let mut id = 0;
+        let mut add = |kind: EntityKind, count: u32, entities: &mut Vec<EntityState>| {
+            for _ in 0..count {
+                id += 1;
+                entities.push(entity(id, kind, 5.));
+            }
+        };
+        add(EntityKind::Prop { index: 0 }, 30, &mut entities);
+        add(
+            EntityKind::Snowball { owner: ClientId(2) },
+            10,
+            &mut entities,
+        );
+        add(EntityKind::Player(ClientId(2)), 10, &mut entities);
+        add(EntityKind::Snowball { owner: VIEWER }, 5, &mut entities);
+
+        let mut interest = Interest::default();
+        let selected = interest.select(VIEWER, 0, None, &entities);
+        assert_eq!(selected.len(), MAX_UPDATES);
+
+        let count = |kind: fn(&EntityKind) -> bool| {
+            selected.iter().filter(|entity| kind(&entity.kind)).count()
+        };
+        assert_eq!(count(|kind| matches!(kind, EntityKind::Player(_))), 11);
+        assert_eq!(
+            count(|kind| matches!(kind, EntityKind::Snowball { .. })),
+            15
+        );
+        assert_eq!(count(|kind| matches!(kind, EntityKind::Prop { .. })), 6);
+
+        // The props that lost out go on the next tick.
+        let selected = interest.select(VIEWER, 1, Some(0), &entities);
+        assert_eq!(selected.len(), entities.len());
+    }
+}
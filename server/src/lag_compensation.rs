@@ -0,0 +1,323 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use protocol::ClientId;
+use simulation::{
+    player::{CAPSULE_END, CAPSULE_RADIUS, CAPSULE_START},
+    snowball::SNOWBALL_HALF_EXTENT,
+};
+
+use crate::simulation::SnowballState;
+
+/// Snowballs slower than this are lying around, not flying at anyone.
+const MIN_HIT_SPEED: f32 = 2.0;
+
+pub struct Hit {
+    pub snowball: Entity,
+    pub thrower: ClientId,
+    pub target: ClientId,
+}
+
+/// Remembers where every player was over the last few ticks, so a snowball can
+/// be checked against targets as the thrower saw them rather than as they are
+/// on the server now.
+pub struct LagCompensation {
+    max_rewind_ticks: u32,
+    poses: VecDeque<(u32, HashMap<ClientId, Vec3>)>,
+    /// Where each snowball was last tick, so fast ones can't skip through a target.
+    snowballs: HashMap<Entity, Vec3>,
+}
+
+impl LagCompensation {
+    pub fn new(tick_duration: Duration, max_rewind: Duration) -> Self {
+        Self {
+            max_rewind_ticks: rewind_ticks(tick_duration, max_rewind),
+            poses: VecDeque::new(),
+            snowballs: HashMap::new(),
+        }
+    }
+
+    pub fn set_max_rewind(&mut self, tick_duration: Duration, max_rewind: Duration) {
+        self.max_rewind_ticks = rewind_ticks(tick_duration, max_rewind);
+    }
+
+    /// Records player positions at the end of `tick`.
+    pub fn record(&mut self, tick: u32, positions: Vec<(ClientId, Vec3)>) {
+        while self.poses.len() > self.max_rewind_ticks as usize {
+            self.poses.pop_front();
+        }
+        self.poses
+            .push_back((tick, positions.into_iter().collect()));
+    }
+
+    /// Sweeps every moving snowball over its path this tick against the other
+    /// players, as its thrower saw them: `rewind` ticks ago (up to the cap).
+    pub fn detect_hits(
+        &mut self,
+        tick: u32,
+        snowballs: &[SnowballState],
+        rewind: impl Fn(ClientId) -> u32,
+    ) -> Vec<Hit> {
+        let mut hits = Vec::new();
+        let mut previous = HashMap::new();
+
+        for snowball in snowballs {
+            let from = self
+                .snowballs
+                .get(&snowball.entity)
+                .copied()
+                .unwrap_or(snowball.translation);
+            previous.insert(snowball.entity, snowball.translation);
+
+            if snowball.velocity.length() < MIN_HIT_SPEED {
+                continue;
+            }
+
+            let rewind = rewind(snowball.owner).min(self.max_rewind_ticks);
+            let Some(poses) = self.poses_at(tick.wrapping_sub(rewind)) else {
+                continue;
+            };
+
+            let target = poses
+                .iter()
+                .filter(|(client, _)| **client != snowball.owner)
+                .map(|(client, position)| {
+                    let distance = segment_distance(
+                        from,
+                        snowball.translation,
+                        position + CAPSULE_START,
+                        position + CAPSULE_END,
+                    );
+                    (*client, distance)
+                })
+                .filter(|(_, distance)| *distance <= CAPSULE_RADIUS + SNOWBALL_HALF_EXTENT)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+
+            if let Some((target, _)) = target {
+                previous.remove(&snowball.entity);
+                hits.push(Hit {
+                    snowball: snowball.entity,
+                    thrower: snowball.owner,
+                    target,
+                });
+            }
+        }
+
+        self.snowballs = previous;
+        hits
+    }
+
+    /// The newest recorded poses at or before `tick`, or the oldest we have.
+    fn poses_at(&self, tick: u32) -> Option<&HashMap<ClientId, Vec3>> {
+        self.poses
+            .iter()
+            .rev()
+            .find(|(recorded, _)| tick.wrapping_sub(*recorded) < u32::MAX / 2)
+            .or_else(|| self.poses.front())
+            .map(|(_, poses)| poses)
+    }
+}
+
+fn rewind_ticks(tick_duration: Duration, max_rewind: Duration) -> u32 {
+    (max_rewind.as_secs_f64() / tick_duration.as_secs_f64()).ceil() as u32
+}
+
+/// Closest distance between segments `a0..a1` and `b0..b1`.
+fn segment_distance(a0: Vec3, a1: Vec3, b0: Vec3, b1: Vec3) -> f32 {
+    let d1 = a1 - a0;
+    let d2 = b1 - b0;
+    let r = a0 - b0;
+    let a = d1.length_squared();
+    let e = d2.length_squared();
+    let f = d2.dot(r);
+
+    let (s, t) = if a <= f32::EPSILON && e <= f32::EPSILON {
+        (0., 0.)
+    } else if a <= f32::EPSILON {
+        (0., (f / e).clamp(0., 1.))
+    } else {
+        let c = d1.dot(r);
+        if e <= f32::EPSILON {
+            ((-c / a).clamp(0., 1.), 0.)
+        } else {
+            let b = d1.dot(d2);
+            let denom = a * e - b * b;
+            let s = if denom > f32::EPSILON {
+                ((b * f - c * e) / denom).clamp(0., 1.)
+            } else {
+                0.
+            };
+            let t = (b * s + f) / e;
+            if t < 0. {
+                ((-c / a).clamp(0., 1.), 0.)
+            } else if t > 1. {
+                (((b - c) / a).clamp(0., 1.), 1.)
+            } else {
+                (s, t)
+            }
+        }
+    };
+
+    (a0 + d1 * s).distance(b0 + d2 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THROWER: ClientId = ClientId(1);
+    const TARGET: ClientId = ClientId(2);
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    /// A target that runs along x, 10 units a tick, for ticks `0..=10`.
+    fn running_target(max_rewind_ticks: u64) -> LagCompensation {
+        let mut lag = LagCompensation::new(
+            Duration::from_secs(1),
+            Duration::from_secs(max_rewind_ticks),
+        );
+        for tick in 0..=10 {
+            lag.record(
+                tick,
+                vec![
+                    (THROWER, Vec3::new(0., 0., -50.)),
+                    (TARGET, target_at(tick)),
+                ],
+            );
+        }
+        lag
+    }
+
+    fn target_at(tick: u32) -> Vec3 {
+        Vec3::new(tick as f32 * 10., 0., 0.)
+    }
+
+    fn snowball(index: u32, translation: Vec3) -> SnowballState {
+        SnowballState {
+            entity: Entity::from_raw_u32(index).unwrap(),
+            owner: THROWER,
+            translation,
+            velocity: Vec3::new(0., 0., 20.),
+        }
+    }
+
+    /// A snowball sitting in the middle of where the target was on `tick`.
+    fn snowball_in_target(index: u32, tick: u32) -> SnowballState {
+        snowball(index, target_at(tick) + CAPSULE_START)
+    }
+
+    #[test]
+    fn parallel_segments() {
+        let distance = segment_distance(Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::new(1., 1., 0.));
+        assert!(close(distance, 1.));
+
+        // Side by side but not overlapping, so it's end to end.
+        let distance = segment_distance(
+            Vec3::ZERO,
+            Vec3::X,
+            Vec3::new(2., 1., 0.),
+            Vec3::new(3., 1., 0.),
+        );
+        assert!(close(distance, 2f32.sqrt()));
+    }
+
+    #[test]
+    fn crossing_segments() {
+        let distance = segment_distance(-Vec3::X, Vec3::X, -Vec3::Y, Vec3::Y);
+        assert!(close(distance, 0.));
+
+        let distance = segment_distance(
+            -Vec3::X,
+            Vec3::X,
+            Vec3::new(0., -1., 3.),
+            Vec3::new(0., 1., 3.),
+        );
+        assert!(close(distance, 3.));
+    }
+
+    #[test]
+    fn segments_that_would_cross_past_their_ends() {
+        let distance = segment_distance(
+            Vec3::ZERO,
+            Vec3::X,
+            Vec3::new(3., -1., 0.),
+            Vec3::new(3., 1., 0.),
+        );
+        assert!(close(distance, 2.));
+    }
+
+    #[test]
+    fn zero_length_segments() {
+        let point = Vec3::new(0., 2., 0.);
+        let far_point = Vec3::new(3., 0., 0.);
+
+        assert!(close(
+            segment_distance(point, point, far_point, far_point),
+            13f32.sqrt()
+        ));
+        assert!(close(segment_distance(point, point, -Vec3::X, Vec3::X), 2.));
+        assert!(close(segment_distance(-Vec3::X, Vec3::X, point, point), 2.));
+        assert!(close(
+            segment_distance(-Vec3::X, Vec3::X, far_point, far_point),
+            2.
+        ));
+    }
+
+    #[test]
+    fn hits_register_at_the_rewound_pose() {
+        let mut lag = running_target(5);
+
+        // Where the target was three ticks ago: only a hit for a thrower who
+        // was seeing that far back.
+        let snowballs = [snowball_in_target(1, 7)];
+        assert!(lag.detect_hits(10, &snowballs, |_| 0).is_empty());
+
+        let mut lag = running_target(5);
+        let hits = lag.detect_hits(10, &snowballs, |_| 3);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snowball, snowballs[0].entity);
+        assert_eq!(hits[0].thrower, THROWER);
+        assert_eq!(hits[0].target, TARGET);
+    }
+
+    #[test]
+    fn rewind_is_clamped() {
+        let mut lag = running_target(4);
+
+        // Asking for eight ticks gets four.
+        let snowballs = [snowball_in_target(1, 2), snowball_in_target(2, 6)];
+        let hits = lag.detect_hits(10, &snowballs, |_| 8);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snowball, snowballs[1].entity);
+    }
+
+    #[test]
+    fn fast_snowballs_are_swept() {
+        let mut lag = running_target(0);
+        let center = target_at(10) + CAPSULE_START;
+
+        let before = [snowball(1, center - Vec3::Z * 10.)];
+        assert!(lag.detect_hits(10, &before, |_| 0).is_empty());
+
+        // Straight through the target between one tick and the next.
+        let after = [snowball(1, center + Vec3::Z * 10.)];
+        assert_eq!(lag.detect_hits(10, &after, |_| 0).len(), 1);
+    }
+
+    #[test]
+    fn slow_and_own_snowballs_dont_hit() {
+        let mut lag = running_target(0);
+
+        let mut lying = snowball_in_target(1, 10);
+        lying.velocity = Vec3::ZERO;
+        let mut own = snowball_in_target(2, 10);
+        own.owner = TARGET;
+
+        assert!(lag.detect_hits(10, &[lying, own], |_| 0).is_empty());
+    }
+}
@@ -1,27 +1,54 @@
-use std::net::UdpSocket;
-
-use tokio::{io, net::TcpListener, time};
-
-
-fn main() -> io::Result<()> {
-    let sock = UdpSocket::bind("0.0.0.0:8080")?;
-    let mut buf = [0; 1024];
-
-    let mut last_ping = time::Instant::now();
-
-    loop {
-        let (len, addr) = sock.recv_from(&mut buf)?;
-        println!("{:?} bytes received from {:?}", len, addr);
-
-        let len = sock.send_to(&buf[..len], addr)?;
-        println!("{:?} bytes sent", len);
-
-        let now = time::Instant::now();
+use std::{env, io, process::ExitCode};
+
+use crate::{
+    config::{ConfigError, Source},
+    server::{Server, ServerConfig},
+};
+
+pub mod admin;
+pub mod config;
+pub mod console;
+pub mod discovery;
+pub mod info;
+pub mod interest;
+pub mod lag_compensation;
+pub mod maps;
+pub mod room;
+pub mod server;
+pub mod session;
+pub mod simulation;
+pub mod validation;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let loaded = Source::from_args(env::args().skip(1)).and_then(|source| {
+        let config = source.load()?;
+        Ok((source, config))
+    });
+    let (source, config) = match loaded {
+        Ok(loaded) => loaded,
+        Err(ConfigError::Help) => {
+            println!("{}", config::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            eprintln!("Try --help");
+            return ExitCode::from(2);
+        }
+    };
 
-        if now - last_ping > time::Duration::from_secs(1) {
-            last_ping = now;
-            let ping = [4; 10];
-            sock.send_to(&ping, addr)?;
+    match run(source, config).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
         }
     }
-}
\ No newline at end of file
+}
+
+async fn run(source: Source, config: ServerConfig) -> io::Result<()> {
+    let server = Server::bind(config).await?;
+    console::spawn(server.admin())?;
+    server.run(source).await
+}
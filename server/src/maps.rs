@@ -0,0 +1,131 @@
+//! Where rooms' maps come from. A map is either built into the game or a
+//! TOML file named after it in the maps directory, laid out like
+//! `simulation::level::Map`. Custom maps are sent to clients that don't have
+//! them, a few chunks a tick.
+
+use std::{fs, io, path::Path, sync::Arc};
+
+use protocol::{MapChunk, MapHash, MapInfo, map::MAP_CHUNK_SIZE};
+use simulation::level::{self, LEVELS, Map};
+
+/// What map files are called: `<name>.toml`.
+const EXTENSION: &str = "toml";
+
+/// A map ready to build and to send.
+#[derive(Debug)]
+pub struct LoadedMap {
+    pub name: String,
+    pub map: Map,
+    /// How it goes over the wire.
+    pub bytes: Vec<u8>,
+    pub hash: MapHash,
+}
+
+impl LoadedMap {
+    pub fn info(&self) -> MapInfo {
+        MapInfo {
+            name: self.name.clone(),
+            hash: self.hash,
+            size: self.bytes.len() as u32,
+        }
+    }
+}
+
+/// Loads the map called `name`, looking in `dir` if it isn't built in.
+pub fn load(dir: &Path, name: &str) -> Result<LoadedMap, String> {
+    let map = match level::builtin(name) {
+        Some(map) => map,
+        None => load_file(dir, name)?,
+    };
+    let bytes = map
+        .to_bytes()
+        .map_err(|e| format!("map {name:?} is too big to send: {e}"))?;
+    Ok(LoadedMap {
+        name: name.to_string(),
+        hash: MapHash::of(&bytes),
+        map,
+        bytes,
+    })
+}
+
+fn load_file(dir: &Path, name: &str) -> Result<Map, String> {
+    // Names end up in paths, so nothing that could lead out of `dir`.
+    let plain = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !plain {
+        return Err(format!(
+            "no map called {name:?}, map names are letters, digits, - and _"
+        ));
+    }
+
+    let path = dir.join(name).with_extension(EXTENSION);
+    let text = fs::read_to_string(&path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => format!(
+            "no map called {name:?}, the server has {}",
+            available(dir).join(", ")
+        ),
+        _ => format!("can't read {}: {}", path.display(), e),
+    })?;
+    let map: Map = toml::from_str(&text).map_err(|e| format!("in {}: {}", path.display(), e))?;
+    map.validate()
+        .map_err(|e| format!("in {}: {}", path.display(), e))?;
+    Ok(map)
+}
+
+/// The names of every map there is, built in ones first.
+pub fn available(dir: &Path) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != EXTENSION {
+                return None;
+            }
+            Some(path.file_stem()?.to_str()?.to_string())
+        })
+        .filter(|name| !LEVELS.contains(&name.as_str()))
+        .collect();
+    files.sort();
+    LEVELS
+        .iter()
+        .map(|name| name.to_string())
+        .chain(files)
+        .collect()
+}
+
+/// A map on its way to one client.
+pub struct MapTransfer {
+    map: Arc<LoadedMap>,
+    sent: usize,
+}
+
+impl MapTransfer {
+    pub fn new(map: Arc<LoadedMap>) -> Self {
+        Self { map, sent: 0 }
+    }
+
+    /// The next piece to send, or `None` once it has all gone.
+    pub fn next_chunk(&mut self) -> Option<MapChunk> {
+        let rest = self
+            .map
+            .bytes
+            .get(self.sent..)
+            .filter(|rest| !rest.is_empty())?;
+        let data = rest[..rest.len().min(MAP_CHUNK_SIZE)].to_vec();
+        let chunk = MapChunk {
+            hash: self.map.hash,
+            offset: self.sent as u32,
+            data,
+        };
+        self.sent += chunk.data.len();
+        Some(chunk)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.sent == self.map.bytes.len()
+    }
+}
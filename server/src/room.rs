@@ -0,0 +1,762 @@
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use protocol::{
+    ClientId, ClientMessage, ClientPacket, ConnectRequest, DisconnectReason, GameEvent,
+    InputCommand, JoinCode, LobbyPlayer, ResumeToken, RoomState, ServerMessage, SessionId,
+    Snapshot, info::PlayerInfo,
+};
+use tokio::{
+    sync::mpsc,
+    time::{self, MissedTickBehavior},
+};
+
+use crate::{
+    lag_compensation::LagCompensation,
+    maps::{LoadedMap, MapTransfer},
+    server::{Datagram, ServerConfig, send_packets, send_unconnected},
+    session::{Accepted, Session, Sessions},
+    simulation::Simulation,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RoomId(pub u32);
+
+/// Map chunks a client can have waiting to be acknowledged. Keeps a
+/// download from crowding out everything else on the reliable channel.
+const MAX_CHUNKS_IN_FLIGHT: usize = 32;
+
+/// A packet the server has routed to a room.
+pub struct Incoming {
+    pub addr: SocketAddr,
+    pub packet: ClientPacket,
+}
+
+/// Tells the server who a room has taken on or let go, so it knows where to
+/// route their packets, and how the room is doing. A client that resumes
+/// its session leaves under the old one and joins under the new.
+pub enum RoomEvent {
+    Joined {
+        room: RoomId,
+        addr: SocketAddr,
+        session: SessionId,
+        resume: ResumeToken,
+    },
+    Left {
+        room: RoomId,
+        addr: SocketAddr,
+        session: SessionId,
+        resume: ResumeToken,
+    },
+    /// The lobby is over and the match has begun.
+    Started { room: RoomId },
+    /// Who's in the room and their scores, for info queries and admins.
+    /// Sent whenever either changes.
+    Scoreboard {
+        room: RoomId,
+        players: Vec<(SocketAddr, PlayerInfo)>,
+    },
+}
+
+/// What the server's admins can have a room do.
+pub enum RoomCommand {
+    Kick {
+        addr: SocketAddr,
+        why: String,
+    },
+    Say(String),
+    /// Everyone back to the spawn with no points. Lobbies stay lobbies.
+    Restart,
+    /// Rebuild the level from another map, then restart.
+    ChangeMap(Arc<LoadedMap>),
+    /// Settings have changed. Those a running room can't change, like the
+    /// tick rate, are ignored.
+    Configure(Box<ServerConfig>),
+}
+
+/// One match: its own simulation, sessions and tick loop. Rooms share nothing
+/// but the server's socket, which they reach through channels.
+///
+/// A hosted room starts out as a lobby. Players can already run around and
+/// throw snowballs, but hits don't count until the host starts the match,
+/// which puts everyone back at the spawn with no points.
+pub struct Room {
+    id: RoomId,
+    code: JoinCode,
+    host: Option<ClientId>,
+    hosted: bool,
+    started: bool,
+    ready: HashSet<ClientId>,
+    outgoing: mpsc::Sender<Datagram>,
+    events: mpsc::UnboundedSender<RoomEvent>,
+    tick_duration: Duration,
+    tick_rate: u16,
+    tick: u32,
+    kick_violations: Option<u32>,
+    map: Arc<LoadedMap>,
+    sessions: Sessions,
+    simulation: Simulation,
+    lag_compensation: LagCompensation,
+}
+
+impl Room {
+    pub fn new(
+        id: RoomId,
+        code: JoinCode,
+        hosted: bool,
+        config: &ServerConfig,
+        map: Arc<LoadedMap>,
+        outgoing: mpsc::Sender<Datagram>,
+        events: mpsc::UnboundedSender<RoomEvent>,
+    ) -> Self {
+        let tick_duration = Duration::from_secs(1) / config.tick_rate.max(1);
+        Self {
+            id,
+            code,
+            host: None,
+            hosted,
+            // Quick play rooms have nobody to start them.
+            started: !hosted,
+            ready: HashSet::new(),
+            outgoing,
+            events,
+            tick_duration,
+            tick_rate: u16::try_from(config.tick_rate).unwrap_or(u16::MAX),
+            tick: 0,
+            kick_violations: config.kick_violations,
+            sessions: Sessions::new(
+                config.max_players,
+                config.client_timeout,
+                config.reconnect_grace,
+                config.max_message_size,
+            ),
+            simulation: Simulation::new(config.tick_rate, map.map.clone()),
+            map,
+            lag_compensation: LagCompensation::new(tick_duration, config.max_rewind),
+        }
+    }
+
+    /// Ticks until the server closes `inbox`, handling packets and commands
+    /// as they arrive.
+    pub async fn run(
+        mut self,
+        mut inbox: mpsc::Receiver<Incoming>,
+        mut commands: mpsc::UnboundedReceiver<RoomCommand>,
+    ) {
+        let mut ticks = time::interval(self.tick_duration);
+        // We fell behind; don't try to catch up with a burst of ticks.
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => self.tick(),
+                incoming = inbox.recv() => match incoming {
+                    Some(incoming) => self.handle_packet(incoming.addr, incoming.packet),
+                    None => break,
+                },
+                Some(command) = commands.recv() => self.handle_command(command),
+            }
+        }
+
+        self.shut_down();
+    }
+
+    /// Says goodbye to everyone still here.
+    fn shut_down(&mut self) {
+        self.send_to_all(&ServerMessage::Disconnected(
+            DisconnectReason::ServerShutdown,
+        ));
+        self.flush();
+        println!("{:?} closed", self.id);
+    }
+
+    fn handle_command(&mut self, command: RoomCommand) {
+        match command {
+            RoomCommand::Kick { addr, why } => {
+                if let Some(client) = self.sessions.client_at(addr) {
+                    self.kick(client, &why);
+                }
+            }
+            RoomCommand::Say(text) => self.send_to_all(&ServerMessage::Chat { from: None, text }),
+            RoomCommand::Restart => {
+                println!("{:?}: restarting", self.id);
+                self.restart_match();
+            }
+            RoomCommand::ChangeMap(map) => {
+                println!("{:?}: switching to {}", self.id, map.name);
+                self.simulation.set_map(map.map.clone());
+                for session in self.sessions.iter_mut() {
+                    session.map_transfer = None;
+                }
+                self.send_to_all(&ServerMessage::Map(map.info()));
+                self.map = map;
+                self.restart_match();
+            }
+            RoomCommand::Configure(config) => {
+                self.sessions.configure(
+                    config.max_players,
+                    config.client_timeout,
+                    config.reconnect_grace,
+                    config.max_message_size,
+                );
+                self.lag_compensation
+                    .set_max_rewind(self.tick_duration, config.max_rewind);
+                self.kick_violations = config.kick_violations;
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        self.expire_sessions();
+        self.apply_inputs();
+        self.simulation.step();
+        if self.started {
+            self.detect_hits();
+        }
+        self.broadcast();
+        self.send_maps();
+        self.flush();
+        self.tick = self.tick.wrapping_add(1);
+    }
+
+    fn handle_packet(&mut self, addr: SocketAddr, packet: ClientPacket) {
+        let now = Instant::now();
+
+        let Some(client) = self.sessions.verify(addr, packet.session, now) else {
+            // Not connected, or a stale/forged session id. The only thing we
+            // listen to from strangers is a connect request.
+            for message in &packet.packet.messages {
+                if let Ok(ClientMessage::Connect(request)) =
+                    protocol::decode_message::<ClientMessage>(&message.payload)
+                {
+                    return self.handle_connect(addr, &request, now);
+                }
+            }
+            return;
+        };
+
+        let Some(session) = self.sessions.get_mut(client) else {
+            return;
+        };
+        for (_, payload) in session.connection.receive(packet.packet, now) {
+            match protocol::decode_message::<ClientMessage>(&payload) {
+                Ok(message) => self.handle_message(client, message),
+                Err(e) => println!("Dropping message from {:?}: {}", addr, e),
+            }
+        }
+    }
+
+    fn handle_message(&mut self, client: ClientId, message: ClientMessage) {
+        match message {
+            // Already connected; the accept went out on an earlier packet.
+            ClientMessage::Connect(_) => {}
+            ClientMessage::Input(input) => {
+                // Checked when it's applied, so throws are counted against
+                // the ticks they land on rather than when packets arrive.
+                self.simulation.queue_input(client, input);
+            }
+            ClientMessage::Ping { sent } => {
+                if let Some(session) = self.sessions.get_mut(client) {
+                    session.ping = Some((sent, Instant::now()));
+                }
+            }
+            ClientMessage::Chat { text } => {
+                self.send_to_all(&ServerMessage::Chat {
+                    from: Some(client),
+                    text,
+                });
+            }
+            ClientMessage::Heartbeat => {}
+            ClientMessage::SnapshotAck { tick } => {
+                if let Some(session) = self.sessions.get_mut(client)
+                    && session.acked_snapshot.is_none_or(|acked| tick > acked)
+                    && tick <= self.tick
+                {
+                    session.acked_snapshot = Some(tick);
+                }
+            }
+            ClientMessage::Disconnect => {
+                if let Some(session) = self.sessions.remove(client) {
+                    println!(
+                        "{:?}: {:?} ({}) disconnected after {:?}",
+                        self.id,
+                        session.addr,
+                        session.name,
+                        session.connected_at.elapsed()
+                    );
+                    self.player_left(&session);
+                }
+            }
+            // Only asked before joining, and the server answers that itself.
+            ClientMessage::ListRooms => {}
+            ClientMessage::SetReady { ready } => {
+                if self.started {
+                    return;
+                }
+                let changed = if ready {
+                    self.ready.insert(client)
+                } else {
+                    self.ready.remove(&client)
+                };
+                if changed {
+                    self.send_room_state();
+                }
+            }
+            ClientMessage::StartMatch => self.start_match(client),
+            ClientMessage::RequestMap { hash } => {
+                // Asked for before a map change; the client has heard about
+                // the new one by now.
+                if hash != self.map.hash {
+                    return;
+                }
+                if let Some(session) = self.sessions.get_mut(client) {
+                    println!(
+                        "{:?}: sending {} to {:?} ({})",
+                        self.id, self.map.name, session.addr, session.name
+                    );
+                    session.map_transfer = Some(MapTransfer::new(self.map.clone()));
+                }
+            }
+            ClientMessage::LeaveRoom => {
+                if let Some(mut session) = self.sessions.remove(client) {
+                    println!("{:?}: {:?} ({}) left", self.id, session.addr, session.name);
+                    // The session is gone, so this has to go out now.
+                    queue(
+                        &mut session,
+                        &ServerMessage::Disconnected(DisconnectReason::Left),
+                    );
+                    send_packets(
+                        &self.outgoing,
+                        session.addr,
+                        session.connection.flush(Instant::now()),
+                    );
+                    self.player_left(&session);
+                }
+            }
+        }
+    }
+
+    /// Gives each player the next of its queued commands, one per tick, so
+    /// the server moves it as many steps as the client predicted.
+    fn apply_inputs(&mut self) {
+        for (client, mut input) in self.simulation.next_inputs() {
+            if !self.validate_input(client, &mut input) {
+                continue;
+            }
+            if let Some(session) = self.sessions.get_mut(client) {
+                // The input is applied on the coming tick. A view tick ahead
+                // of that is nonsense, so count it as no delay.
+                session.view_delay = Some(self.tick.saturating_sub(input.view_tick));
+            }
+            self.simulation.apply_input(client, &input);
+        }
+    }
+
+    /// Clamps `input` into range and counts it against the client if it
+    /// needed that. Returns false if the input should be dropped, including
+    /// when the client got kicked for it.
+    fn validate_input(&mut self, client: ClientId, input: &mut InputCommand) -> bool {
+        let Some(session) = self.sessions.get_mut(client) else {
+            return false;
+        };
+        let Some(violation) = session
+            .validator
+            .check(input, self.tick, u32::from(self.tick_rate))
+        else {
+            return true;
+        };
+
+        let kick = session.validator.count(self.kick_violations);
+        let violations = session.validator.violations();
+        // Only at 1, 2, 4, 8 and so on, so a client that keeps at it can't
+        // flood the log.
+        if violations.is_power_of_two() {
+            println!(
+                "{:?}: {:?} ({}) sent bad input: {}, violation {}",
+                self.id, session.addr, session.name, violation, violations
+            );
+        }
+        if kick {
+            self.kick(client, &format!("bad input ({violation})"));
+            return false;
+        }
+        !violation.rejects()
+    }
+
+    /// Disconnects `client`, telling it and everyone else why.
+    fn kick(&mut self, client: ClientId, why: &str) {
+        let Some(mut session) = self.sessions.remove(client) else {
+            return;
+        };
+        println!(
+            "{:?}: kicked {:?} ({}): {}",
+            self.id, session.addr, session.name, why
+        );
+        queue(
+            &mut session,
+            &ServerMessage::Disconnected(DisconnectReason::Kicked),
+        );
+        send_packets(
+            &self.outgoing,
+            session.addr,
+            session.connection.flush(Instant::now()),
+        );
+        let text = format!("{} was kicked: {}", session.name, why);
+        self.player_left(&session);
+        self.send_to_all(&ServerMessage::Chat { from: None, text });
+    }
+
+    /// Starts the match if `client` is the host and everyone else is ready.
+    fn start_match(&mut self, client: ClientId) {
+        if self.started || self.host != Some(client) {
+            return;
+        }
+
+        let waiting: Vec<&str> = self
+            .sessions
+            .iter()
+            .filter(|session| session.client != client && !self.ready.contains(&session.client))
+            .map(|session| session.name.as_str())
+            .collect();
+        if !waiting.is_empty() {
+            let text = format!("Waiting for {} to ready up", waiting.join(", "));
+            self.send(client, &ServerMessage::Chat { from: None, text });
+            return;
+        }
+
+        println!("{:?}: match started", self.id);
+        self.started = true;
+        let _ = self.events.send(RoomEvent::Started { room: self.id });
+        self.send_room_state();
+        self.restart_match();
+    }
+
+    /// Puts everyone back at the spawn and wipes the scores.
+    fn restart_match(&mut self) {
+        self.simulation.restart();
+        let clients: Vec<ClientId> = self.sessions.iter().map(|session| session.client).collect();
+        for client in clients {
+            self.send_to_all(&ServerMessage::Event(GameEvent::ScoreChanged {
+                client,
+                score: 0,
+            }));
+        }
+        self.publish_scoreboard();
+    }
+
+    fn publish_scoreboard(&self) {
+        let players = self
+            .sessions
+            .iter()
+            .map(|session| {
+                let info = PlayerInfo {
+                    name: session.name.clone(),
+                    score: self.simulation.score(session.client).unwrap_or(0),
+                };
+                (session.addr, info)
+            })
+            .collect();
+        let _ = self.events.send(RoomEvent::Scoreboard {
+            room: self.id,
+            players,
+        });
+    }
+
+    fn send_room_state(&mut self) {
+        let mut players: Vec<LobbyPlayer> = self
+            .sessions
+            .iter()
+            .map(|session| LobbyPlayer {
+                client: session.client,
+                name: session.name.clone(),
+                ready: self.ready.contains(&session.client),
+            })
+            .collect();
+        players.sort_by_key(|player| player.client);
+
+        self.send_to_all(&ServerMessage::RoomState(RoomState {
+            code: self.code,
+            host: self.host,
+            started: self.started,
+            players,
+        }));
+    }
+
+    fn handle_connect(&mut self, addr: SocketAddr, request: &ConnectRequest, now: Instant) {
+        let (session, resumed) = match self.sessions.connect(addr, request, now) {
+            Ok((session, Accepted::Again)) => {
+                // Our accept got lost; send it again.
+                let accepted = accept(session, self.tick, self.tick_rate);
+                queue(session, &accepted);
+                return;
+            }
+            Ok((session, Accepted::New)) => (session, None),
+            Ok((session, Accepted::Resumed { old, displaced })) => {
+                if let Some(displaced) = displaced {
+                    println!(
+                        "{:?}: {:?} was taken over, holding {:?}'s place",
+                        self.id, addr, displaced
+                    );
+                    self.simulation.idle(displaced);
+                }
+                (session, Some(old))
+            }
+            Err(reason) => {
+                println!(
+                    "{:?}: rejecting {:?} ({}): {:?}",
+                    self.id, addr, request.name, reason
+                );
+                send_unconnected(
+                    &self.outgoing,
+                    addr,
+                    &ServerMessage::ConnectRejected(reason),
+                );
+                return;
+            }
+        };
+        let accepted = accept(session, self.tick, self.tick_rate);
+        let (client, id, resume, name) = (
+            session.client,
+            session.id,
+            session.resume,
+            session.name.clone(),
+        );
+
+        if let Some(old) = &resumed {
+            println!(
+                "{:?}: {:?} ({}) is back as {:?}, was {:?}",
+                self.id, addr, name, client, old.addr
+            );
+            let _ = self.events.send(RoomEvent::Left {
+                room: self.id,
+                addr: old.addr,
+                session: old.id,
+                resume: old.resume,
+            });
+        } else {
+            println!(
+                "{:?}: {:?} ({}) joined as {:?}, {} connected",
+                self.id,
+                addr,
+                name,
+                client,
+                self.sessions.iter().count()
+            );
+        }
+        let _ = self.events.send(RoomEvent::Joined {
+            room: self.id,
+            addr,
+            session: id,
+            resume,
+        });
+
+        self.send(client, &accepted);
+        self.send(client, &ServerMessage::Map(self.map.info()));
+        if resumed.is_some() {
+            // Their player never left, but the client starts from nothing.
+            self.send_scores(client);
+        } else {
+            self.simulation.add_player(client, &name);
+            if self.hosted && self.host.is_none() {
+                self.host = Some(client);
+            }
+            self.send_to_all(&ServerMessage::Event(GameEvent::PlayerJoined {
+                client,
+                name,
+            }));
+        }
+        self.send_room_state();
+        self.publish_scoreboard();
+    }
+
+    /// Tells `client` everyone's score.
+    fn send_scores(&mut self, client: ClientId) {
+        let players: Vec<ClientId> = self.sessions.iter().map(|session| session.client).collect();
+        for player in players {
+            let score = self.simulation.score(player).unwrap_or(0);
+            self.send(
+                client,
+                &ServerMessage::Event(GameEvent::ScoreChanged {
+                    client: player,
+                    score,
+                }),
+            );
+        }
+    }
+
+    fn expire_sessions(&mut self) {
+        let now = Instant::now();
+        // Their players stay put until they come back or the grace period
+        // is up.
+        for session in self.sessions.hold_silent(now) {
+            println!(
+                "{:?}: {:?} ({}) timed out, holding their place",
+                self.id, session.addr, session.name
+            );
+            self.simulation.idle(session.client);
+        }
+        for mut session in self.sessions.expire(now) {
+            println!(
+                "{:?}: {:?} ({}) didn't come back",
+                self.id, session.addr, session.name
+            );
+            queue(
+                &mut session,
+                &ServerMessage::Disconnected(DisconnectReason::TimedOut),
+            );
+            send_packets(&self.outgoing, session.addr, session.connection.flush(now));
+            self.player_left(&session);
+        }
+    }
+
+    fn player_left(&mut self, session: &Session) {
+        let _ = self.events.send(RoomEvent::Left {
+            room: self.id,
+            addr: session.addr,
+            session: session.id,
+            resume: session.resume,
+        });
+        self.simulation.remove_player(session.client);
+        self.ready.remove(&session.client);
+        if self.host == Some(session.client) {
+            // Whoever has been here longest takes over.
+            self.host = self.sessions.iter().map(|session| session.client).min();
+        }
+
+        self.send_to_all(&ServerMessage::Event(GameEvent::PlayerLeft {
+            client: session.client,
+        }));
+        self.send_room_state();
+        self.publish_scoreboard();
+    }
+
+    /// Checks this tick's snowball flight against lag compensated player poses.
+    fn detect_hits(&mut self) {
+        self.lag_compensation
+            .record(self.tick, self.simulation.player_positions());
+
+        let snowballs = self.simulation.snowballs();
+        let sessions = &self.sessions;
+        let tick_duration = self.tick_duration;
+        let hits = self
+            .lag_compensation
+            .detect_hits(self.tick, &snowballs, |client| {
+                let Some(session) = sessions.get(client) else {
+                    return 0;
+                };
+                // Until its first input says otherwise, guess the client sees
+                // the world a round trip late.
+                session.view_delay.unwrap_or_else(|| {
+                    let rtt = session.connection.rtt().rtt();
+                    (rtt.as_secs_f64() / tick_duration.as_secs_f64()).round() as u32
+                })
+            });
+
+        let scored = !hits.is_empty();
+        for hit in hits {
+            self.simulation.remove_snowball(hit.snowball);
+            self.send_to_all(&ServerMessage::Event(GameEvent::SnowballHit {
+                thrower: hit.thrower,
+                target: hit.target,
+            }));
+            if let Some(score) = self.simulation.add_score(hit.thrower, 1) {
+                self.send_to_all(&ServerMessage::Event(GameEvent::ScoreChanged {
+                    client: hit.thrower,
+                    score,
+                }));
+            }
+        }
+        if scored {
+            self.publish_scoreboard();
+        }
+    }
+
+    fn broadcast(&mut self) {
+        let now = Instant::now();
+        let entities = self.simulation.entities();
+        for session in self.sessions.iter_mut() {
+            let snapshot = Snapshot {
+                tick: self.tick,
+                last_input: self.simulation.last_input(session.client),
+                entities: session.interest.select(
+                    session.client,
+                    self.tick,
+                    session.acked_snapshot,
+                    &entities,
+                ),
+            };
+            let delta = session.snapshots.encode(&snapshot, session.acked_snapshot);
+            queue(session, &ServerMessage::Snapshot(delta));
+
+            if let Some((sent, received)) = session.ping.take() {
+                let held = now.duration_since(received).as_micros();
+                let pong = ServerMessage::Pong {
+                    sent,
+                    held: u32::try_from(held).unwrap_or(u32::MAX),
+                    tick: self.tick,
+                };
+                queue(session, &pong);
+            }
+        }
+    }
+
+    /// Sends the next chunks of any maps being downloaded, as far as each
+    /// client has room for them.
+    fn send_maps(&mut self) {
+        for session in self.sessions.iter_mut() {
+            let Some(mut transfer) = session.map_transfer.take() else {
+                continue;
+            };
+            while session.connection.unacked() < MAX_CHUNKS_IN_FLIGHT {
+                let Some(chunk) = transfer.next_chunk() else {
+                    break;
+                };
+                queue(session, &ServerMessage::MapChunk(chunk));
+            }
+            if !transfer.is_done() {
+                session.map_transfer = Some(transfer);
+            }
+        }
+    }
+
+    /// Puts everything queued this tick on the wire.
+    fn flush(&mut self) {
+        let now = Instant::now();
+        for session in self.sessions.iter_mut() {
+            send_packets(&self.outgoing, session.addr, session.connection.flush(now));
+        }
+    }
+
+    fn send_to_all(&mut self, message: &ServerMessage) {
+        for session in self.sessions.iter_mut() {
+            queue(session, message);
+        }
+    }
+
+    fn send(&mut self, client: ClientId, message: &ServerMessage) {
+        if let Some(session) = self.sessions.get_mut(client) {
+            queue(session, message);
+        }
+    }
+}
+
+fn accept(session: &Session, tick: u32, tick_rate: u16) -> ServerMessage {
+    ServerMessage::ConnectAccepted {
+        client: session.client,
+        session: session.id,
+        tick,
+        tick_rate,
+        resume: session.resume,
+    }
+}
+
+/// Queues `message` on the session's connection, on the channel its kind calls for.
+fn queue(session: &mut Session, message: &ServerMessage) {
+    if let Err(e) = session.connection.send_message(message.channel(), message) {
+        println!("Not sending {:?} to {:?}: {}", message, session.addr, e);
+    }
+}
@@ -0,0 +1,1113 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use protocol::{
+    ClientMessage, ClientPacket, ConnectRequest, Connection, DEFAULT_MAX_MESSAGE_SIZE, JoinCode,
+    PROTOCOL_VERSION, Packet, RECV_BUFFER_SIZE, RejectReason, ResumeToken, RoomRequest,
+    RoomSummary, ServerMessage, SessionId,
+    discovery::DISCOVERY_PORT,
+    info::{self, InfoQuery, PlayerInfo, ServerInfo},
+    lobby::MAX_LISTED_ROOMS,
+    netsim::{NetworkConditions, NetworkSimulator},
+};
+use tokio::{
+    net::UdpSocket,
+    runtime, signal,
+    sync::mpsc::{self, error::TrySendError},
+    task,
+    time::{self, MissedTickBehavior},
+};
+
+use crate::{
+    admin::{self, Admin, AdminCommand, AdminRequest, SETTINGS},
+    config::{self, Hangups, Source},
+    discovery::{self, Listing, ListingDetails},
+    info::InfoLimiter,
+    maps,
+    room::{Incoming, Room, RoomCommand, RoomEvent, RoomId},
+};
+
+/// Packets a room can have waiting before newer ones are dropped. A stalled
+/// room loses its own traffic rather than holding up everyone else's.
+const ROOM_INBOX: usize = 1024;
+
+/// Datagrams from every room waiting for the socket.
+const OUTGOING_QUEUE: usize = 4096;
+
+/// How often to look for crashed rooms, empty rooms and abandoned connects.
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Close a room once it has been empty this long.
+const ROOM_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+pub struct ServerConfig {
+    /// IPv4 or IPv6.
+    pub bind: IpAddr,
+    pub port: u16,
+    /// Shown to players looking for a game on the local network.
+    pub name: String,
+    /// Players have to give it to get in, if there is one.
+    pub password: Option<String>,
+    pub mode: String,
+    /// Map rotation. Each room opened takes the next map.
+    pub maps: Vec<String>,
+    /// Where maps that aren't built in are loaded from.
+    pub maps_dir: PathBuf,
+    /// Where to answer LAN discovery queries. `None` keeps the server hidden.
+    pub discovery_port: Option<u16>,
+    /// Simulation and broadcast rate in Hz.
+    pub tick_rate: u32,
+    /// Players per room.
+    pub max_players: usize,
+    /// Rooms open at once, hosted and quick play together.
+    pub max_rooms: usize,
+    /// Drop clients we haven't heard from for this long.
+    pub client_timeout: Duration,
+    /// How long a dropped client's player and score wait for it to reconnect.
+    pub reconnect_grace: Duration,
+    /// Furthest back in time hit detection will look on a thrower's behalf.
+    pub max_rewind: Duration,
+    /// Largest message, in bytes, sent to or accepted from a client. Bigger
+    /// than a packet means sent in fragments.
+    pub max_message_size: usize,
+    /// Kick a client once this much of its input has failed validation.
+    /// `None` only clamps and logs.
+    pub kick_violations: Option<u32>,
+    /// Artificial latency, loss and so on, for testing. Perfect by default.
+    pub network: NetworkConditions,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 8080,
+            name: "Snowball server".to_string(),
+            password: None,
+            mode: "free-for-all".to_string(),
+            maps: vec![simulation::level::LEVEL_NAME.to_string()],
+            maps_dir: PathBuf::from("maps"),
+            discovery_port: Some(DISCOVERY_PORT),
+            tick_rate: 60,
+            max_players: 16,
+            max_rooms: 8,
+            client_timeout: Duration::from_secs(5),
+            reconnect_grace: Duration::from_secs(30),
+            max_rewind: Duration::from_millis(250),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            kick_violations: Some(10),
+            network: NetworkConditions::NONE,
+        }
+    }
+}
+
+/// A datagram on its way out of the server's socket.
+pub struct Datagram {
+    pub addr: SocketAddr,
+    pub bytes: Vec<u8>,
+}
+
+/// Owns the socket and hands each packet to the room its session belongs to.
+pub struct Server {
+    socket: Arc<UdpSocket>,
+    network: NetworkConditions,
+    router: Router,
+    events: mpsc::UnboundedReceiver<RoomEvent>,
+    admin: Admin,
+    admin_requests: mpsc::UnboundedReceiver<AdminRequest>,
+    sender: task::JoinHandle<()>,
+    discovery: Option<task::JoinHandle<()>>,
+}
+
+impl Server {
+    pub async fn bind(config: ServerConfig) -> io::Result<Self> {
+        let addr = SocketAddr::new(config.bind, config.port);
+        let socket = UdpSocket::bind(addr)
+            .await
+            .map_err(|e| io::Error::new(e.kind(), format!("can't listen on {addr}: {e}")))?;
+        let socket = Arc::new(socket);
+        println!(
+            "Listening on {} at {} ticks per second, up to {} rooms of {}",
+            socket.local_addr()?,
+            config.tick_rate,
+            config.max_rooms,
+            config.max_players
+        );
+        if !config.network.is_perfect() {
+            println!("Simulating network conditions: {:?}", config.network);
+        }
+
+        let (outgoing, queue) = mpsc::channel(OUTGOING_QUEUE);
+        let sender = tokio::spawn(send_loop(socket.clone(), queue, config.network));
+        let (room_events, events) = mpsc::unbounded_channel();
+
+        // Players on every room and what the server is called, for discovery
+        // answers.
+        let players_online = Arc::new(AtomicUsize::new(0));
+        let map = config.maps[0].clone();
+        let listing = Arc::new(Mutex::new(listing_details(&config, &map)));
+        let discovery = match config.discovery_port {
+            Some(port) => match discovery::bind(socket.local_addr()?, port).await {
+                Ok(discovery_socket) => {
+                    println!("Answering LAN discovery on port {}", port);
+                    let listing = Listing {
+                        server_id: protocol::random(),
+                        game_port: socket.local_addr()?.port(),
+                        players: players_online.clone(),
+                        details: listing.clone(),
+                    };
+                    Some(tokio::spawn(discovery::answer_queries(
+                        discovery_socket,
+                        listing,
+                    )))
+                }
+                // Most likely another server on this machine has the port.
+                // Players can still connect by address.
+                Err(e) => {
+                    println!("Can't answer LAN discovery on port {}: {}", port, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let network = config.network;
+        let router = Router {
+            config,
+            outgoing,
+            room_events,
+            rooms: BTreeMap::new(),
+            sessions: HashMap::new(),
+            resumable: HashMap::new(),
+            routes: HashMap::new(),
+            next_room: 0,
+            map,
+            rotation: 0,
+            players_online,
+            listing,
+            info_limiter: InfoLimiter::new(Instant::now()),
+            bans: BTreeSet::new(),
+        };
+        let (admin, admin_requests) = Admin::new();
+
+        Ok(Self {
+            socket,
+            network,
+            router,
+            events,
+            admin,
+            admin_requests,
+            sender,
+            discovery,
+        })
+    }
+
+    /// For admin frontends to send commands through.
+    pub fn admin(&self) -> Admin {
+        self.admin.clone()
+    }
+
+    /// Routes packets until Ctrl-C or an admin says quit, then closes every
+    /// room and waits for their goodbyes to go out. Settings are loaded again
+    /// from `source` on SIGHUP.
+    pub async fn run(self, source: Source) -> io::Result<()> {
+        let Server {
+            socket,
+            network,
+            mut router,
+            mut events,
+            admin: _,
+            mut admin_requests,
+            sender,
+            discovery,
+        } = self;
+
+        let mut buf = [0; RECV_BUFFER_SIZE];
+        let mut incoming = NetworkSimulator::new(network);
+        let mut housekeeping = time::interval(HOUSEKEEPING_INTERVAL);
+        housekeeping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let shutdown = signal::ctrl_c();
+        tokio::pin!(shutdown);
+        let mut hangups = Hangups::default();
+
+        loop {
+            let due = incoming.next_due();
+            tokio::select! {
+                received = socket.recv_from(&mut buf) => match received {
+                    Ok((len, addr)) => incoming.push(&buf[..len], addr, Instant::now()),
+                    // Windows reports ICMP port unreachable from an earlier send here.
+                    Err(e) if e.kind() == ErrorKind::ConnectionReset => {}
+                    Err(e) => return Err(e),
+                },
+                _ = sleep_until(due), if due.is_some() => {}
+                Some(event) = events.recv() => router.handle_event(event),
+                Some(request) = admin_requests.recv() => {
+                    if router.handle_admin(request) {
+                        break;
+                    }
+                }
+                _ = housekeeping.tick() => router.housekeeping(),
+                _ = hangups.recv() => {
+                    println!("Reloading {}", source.path().display());
+                    match source.load() {
+                        Ok(config) => router.reconfigure(config),
+                        Err(e) => println!("Keeping the old settings: {}", e),
+                    }
+                }
+                result = &mut shutdown => {
+                    if let Err(e) = result {
+                        println!("Can't listen for Ctrl-C: {}", e);
+                    }
+                    break;
+                }
+            }
+
+            while let Some((bytes, addr)) = incoming.pop(Instant::now()) {
+                if protocol::info::is_info(&bytes) {
+                    router.answer_info(addr, &bytes);
+                    continue;
+                }
+                match protocol::decode::<ClientPacket>(&bytes) {
+                    Ok(packet) => router.route(addr, packet),
+                    Err(e) => println!("Dropping packet from {:?}: {}", addr, e),
+                }
+            }
+        }
+
+        println!("Shutting down");
+        if let Some(discovery) = discovery {
+            discovery.abort();
+        }
+        router.close_all().await;
+        // The sender finishes once the last room has let go of the queue.
+        drop(router);
+        sender.await.map_err(io::Error::other)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RoomKind {
+    /// Opened by matchmaking for whoever turns up.
+    QuickPlay,
+    /// Opened by a player, who hosts it.
+    Hosted { private: bool },
+}
+
+struct RoomHandle {
+    code: JoinCode,
+    kind: RoomKind,
+    started: bool,
+    map: String,
+    inbox: mpsc::Sender<Incoming>,
+    commands: mpsc::UnboundedSender<RoomCommand>,
+    thread: JoinHandle<()>,
+    empty_since: Option<Instant>,
+    scoreboard: Vec<(SocketAddr, PlayerInfo)>,
+}
+
+/// Where packets from one address go.
+struct Route {
+    room: RoomId,
+    /// False while the room is still deciding whether to accept them.
+    joined: bool,
+    since: Instant,
+}
+
+/// Keeps track of the rooms and which of them every client belongs to.
+///
+/// Each room runs on a thread of its own, so one that panics or falls behind
+/// only takes its own players down with it.
+struct Router {
+    config: ServerConfig,
+    outgoing: mpsc::Sender<Datagram>,
+    room_events: mpsc::UnboundedSender<RoomEvent>,
+    rooms: BTreeMap<RoomId, RoomHandle>,
+    sessions: HashMap<SessionId, RoomId>,
+    /// Where each player's resume token would take them back to.
+    resumable: HashMap<ResumeToken, RoomId>,
+    routes: HashMap<SocketAddr, Route>,
+    next_room: u32,
+    /// What the newest room is playing.
+    map: String,
+    /// Where in `config.maps` the next room's map comes from.
+    rotation: usize,
+    /// Players who have joined a room, shared with the discovery task.
+    players_online: Arc<AtomicUsize>,
+    listing: Arc<Mutex<ListingDetails>>,
+    info_limiter: InfoLimiter,
+    /// Addresses admins have banned, for as long as the server runs.
+    bans: BTreeSet<IpAddr>,
+}
+
+impl Router {
+    fn open_room(&mut self, kind: RoomKind) -> io::Result<RoomId> {
+        let id = RoomId(self.next_room);
+        self.next_room += 1;
+        let code = self.new_join_code();
+        let hosted = kind != RoomKind::QuickPlay;
+
+        // Validation makes sure there's at least one.
+        let map = self.config.maps[self.rotation % self.config.maps.len()].clone();
+        // The file may have changed since the settings were checked.
+        let loaded = Arc::new(maps::load(&self.config.maps_dir, &map).map_err(io::Error::other)?);
+
+        // The simulation isn't Send, so rather than a task on the shared
+        // runtime, each room gets a thread and runtime of its own.
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()?;
+        let (inbox, packets) = mpsc::channel(ROOM_INBOX);
+        let (commands, command_receiver) = mpsc::unbounded_channel();
+        let config = self.config.clone();
+        let outgoing = self.outgoing.clone();
+        let events = self.room_events.clone();
+        let thread = thread::Builder::new()
+            .name(format!("room-{}", id.0))
+            .spawn(move || {
+                let room = Room::new(id, code, hosted, &config, loaded, outgoing, events);
+                runtime.block_on(room.run(packets, command_receiver));
+            })?;
+
+        self.rotation = (self.rotation + 1) % self.config.maps.len();
+        if map != self.map {
+            self.map = map.clone();
+            self.update_listing();
+        }
+
+        println!("Opened {:?} with code {} on {}", id, code, map);
+        self.rooms.insert(
+            id,
+            RoomHandle {
+                code,
+                kind,
+                map,
+                started: !hosted,
+                inbox,
+                commands,
+                thread,
+                empty_since: Some(Instant::now()),
+                scoreboard: Vec::new(),
+            },
+        );
+        Ok(id)
+    }
+
+    fn new_join_code(&self) -> JoinCode {
+        // Codes only need to be hard to stumble on; a private room is exactly
+        // as private as its code.
+        loop {
+            let code = JoinCode::from_bits(protocol::random() as u32);
+            if self.rooms.values().all(|handle| handle.code != code) {
+                return code;
+            }
+        }
+    }
+
+    /// Sends a packet on to its room. Packets from strangers go to
+    /// `handle_stranger` first.
+    fn route(&mut self, addr: SocketAddr, packet: ClientPacket) {
+        if self.bans.contains(&addr.ip()) {
+            return self.turn_away_banned(addr, &packet.packet);
+        }
+
+        let room = match self.sessions.get(&packet.session) {
+            Some(room) => *room,
+            None => match self.routes.get(&addr) {
+                // Connecting, or connected and asking again because our
+                // accept got lost. Either way the same room should answer.
+                Some(route) => route.room,
+                None => match self.handle_stranger(addr, &packet.packet) {
+                    Some(room) => room,
+                    None => return,
+                },
+            },
+        };
+
+        let Some(handle) = self.rooms.get(&room) else {
+            return;
+        };
+        match handle.inbox.try_send(Incoming { addr, packet }) {
+            Ok(()) => {}
+            // The room is behind; let it catch up on what it already has.
+            Err(TrySendError::Full(_)) => {}
+            Err(TrySendError::Closed(_)) => self.remove_room(room),
+        }
+    }
+
+    /// Answers a room list request, or finds a room for a connect request
+    /// and returns it. Anything else from a stranger is ignored.
+    fn handle_stranger(&mut self, addr: SocketAddr, packet: &Packet) -> Option<RoomId> {
+        for message in &packet.messages {
+            match protocol::decode_message::<ClientMessage>(&message.payload) {
+                Ok(ClientMessage::ListRooms) => {
+                    let rooms = self.list_rooms();
+                    send_unconnected(&self.outgoing, addr, &ServerMessage::RoomList(rooms));
+                }
+                Ok(ClientMessage::Connect(request)) => {
+                    return match self.assign(addr, &request) {
+                        Ok(room) => Some(room),
+                        Err(reason) => {
+                            println!("Rejecting {:?} ({}): {:?}", addr, request.name, reason);
+                            send_unconnected(
+                                &self.outgoing,
+                                addr,
+                                &ServerMessage::ConnectRejected(reason),
+                            );
+                            None
+                        }
+                    };
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Tells a banned address trying to connect that it's banned. Nothing
+    /// else it sends gets anywhere.
+    fn turn_away_banned(&self, addr: SocketAddr, packet: &Packet) {
+        let connecting = packet.messages.iter().any(|message| {
+            matches!(
+                protocol::decode_message::<ClientMessage>(&message.payload),
+                Ok(ClientMessage::Connect(_))
+            )
+        });
+        if connecting {
+            send_unconnected(
+                &self.outgoing,
+                addr,
+                &ServerMessage::ConnectRejected(RejectReason::Banned),
+            );
+        }
+    }
+
+    /// Picks the room a connecting player asked for, opening one if need be.
+    fn assign(
+        &mut self,
+        addr: SocketAddr,
+        request: &ConnectRequest,
+    ) -> Result<RoomId, RejectReason> {
+        // The room would turn them away too, but not before we'd opened it.
+        if request.protocol_version != PROTOCOL_VERSION {
+            return Err(RejectReason::VersionMismatch {
+                server: PROTOCOL_VERSION,
+            });
+        }
+        if self.config.password.is_some() && request.password != self.config.password {
+            return Err(RejectReason::WrongPassword);
+        }
+
+        // Their place is being held, wherever they're connecting from now.
+        let resumed = request
+            .resume
+            .and_then(|token| self.resumable.get(&token).copied());
+        let room = match (resumed, request.room) {
+            (Some(room), _) => room,
+            (None, RoomRequest::QuickPlay) => {
+                let open = self.rooms.iter().find(|(room, handle)| {
+                    handle.kind == RoomKind::QuickPlay
+                        && self.places_taken(**room) < self.config.max_players
+                });
+                match open {
+                    Some((room, _)) => *room,
+                    None => self.open_room_for(RoomKind::QuickPlay)?,
+                }
+            }
+            (None, RoomRequest::Create { private }) => {
+                self.open_room_for(RoomKind::Hosted { private })?
+            }
+            (None, RoomRequest::Join(code)) => {
+                let room = self
+                    .rooms
+                    .iter()
+                    .find(|(_, handle)| handle.code == code)
+                    .map(|(room, _)| *room)
+                    .ok_or(RejectReason::NoSuchRoom)?;
+                if self.places_taken(room) >= self.config.max_players {
+                    return Err(RejectReason::RoomFull);
+                }
+                room
+            }
+        };
+
+        self.routes.insert(
+            addr,
+            Route {
+                room,
+                joined: false,
+                since: Instant::now(),
+            },
+        );
+        Ok(room)
+    }
+
+    /// Opens a room for a player who asked for one, if there's space for it.
+    fn open_room_for(&mut self, kind: RoomKind) -> Result<RoomId, RejectReason> {
+        if self.rooms.len() >= self.config.max_rooms {
+            return Err(RejectReason::ServerFull);
+        }
+        self.open_room(kind).map_err(|e| {
+            println!("Can't open a room: {}", e);
+            RejectReason::ServerFull
+        })
+    }
+
+    /// Every room that isn't private, for the server browser.
+    fn list_rooms(&self) -> Vec<RoomSummary> {
+        self.rooms
+            .iter()
+            .filter(|(_, handle)| handle.kind != RoomKind::Hosted { private: true })
+            .take(MAX_LISTED_ROOMS)
+            .map(|(room, handle)| RoomSummary {
+                code: handle.code,
+                players: self.players(*room).min(u8::MAX as usize) as u8,
+                max_players: self.config.max_players.min(u8::MAX as usize) as u8,
+                started: handle.started,
+            })
+            .collect()
+    }
+
+    /// Answers an info query straight away, from what the rooms last told us.
+    /// Nothing is kept about the asker beyond its rate limit.
+    fn answer_info(&mut self, addr: SocketAddr, bytes: &[u8]) {
+        let Ok(query) = info::decode_info::<InfoQuery>(bytes) else {
+            return;
+        };
+        if !self.info_limiter.allow(addr.ip(), Instant::now()) {
+            return;
+        }
+
+        let players: Vec<PlayerInfo> = self
+            .rooms
+            .values()
+            .flat_map(|handle| handle.scoreboard.iter().map(|(_, player)| player.clone()))
+            .collect();
+        let answer = ServerInfo {
+            timestamp: query.timestamp,
+            protocol_version: PROTOCOL_VERSION,
+            name: self.config.name.clone(),
+            map: self.map.clone(),
+            mode: self.config.mode.clone(),
+            max_players: clamp_u16(self.config.max_players * self.config.max_rooms),
+            player_count: clamp_u16(players.len()),
+            players,
+        };
+        match crate::info::encode_answer(answer) {
+            Ok(bytes) => {
+                // Dropped like any other datagram if the queue is full.
+                let _ = self.outgoing.try_send(Datagram { addr, bytes });
+            }
+            Err(e) => println!("Can't answer info query: {}", e),
+        }
+    }
+
+    /// Players the room has accepted.
+    fn players(&self, room: RoomId) -> usize {
+        self.routes
+            .values()
+            .filter(|route| route.room == room && route.joined)
+            .count()
+    }
+
+    /// Places taken in `room`, counting connects it hasn't answered yet so
+    /// a rush of them can't overfill it.
+    fn places_taken(&self, room: RoomId) -> usize {
+        self.routes
+            .values()
+            .filter(|route| route.room == room)
+            .count()
+    }
+
+    fn handle_event(&mut self, event: RoomEvent) {
+        match event {
+            RoomEvent::Joined {
+                room,
+                addr,
+                session,
+                resume,
+            } => {
+                self.sessions.insert(session, room);
+                self.resumable.insert(resume, room);
+                self.routes.insert(
+                    addr,
+                    Route {
+                        room,
+                        joined: true,
+                        since: Instant::now(),
+                    },
+                );
+            }
+            RoomEvent::Left {
+                room,
+                addr,
+                session,
+                resume,
+            } => {
+                self.sessions.remove(&session);
+                self.resumable.remove(&resume);
+                if self
+                    .routes
+                    .get(&addr)
+                    .is_some_and(|route| route.room == room)
+                {
+                    self.routes.remove(&addr);
+                }
+            }
+            RoomEvent::Started { room } => {
+                if let Some(handle) = self.rooms.get_mut(&room) {
+                    handle.started = true;
+                }
+            }
+            RoomEvent::Scoreboard { room, players } => {
+                if let Some(handle) = self.rooms.get_mut(&room) {
+                    handle.scoreboard = players;
+                }
+            }
+        }
+        self.count_players();
+    }
+
+    /// Carries out an admin request. Returns true if it was to quit.
+    fn handle_admin(&mut self, request: AdminRequest) -> bool {
+        match request {
+            AdminRequest::Run {
+                command: AdminCommand::Quit,
+                reply,
+            } => {
+                // The run loop says so once it has stopped.
+                let _ = reply.send(String::new());
+                return true;
+            }
+            AdminRequest::Run { command, reply } => {
+                let _ = reply.send(self.run_admin(command));
+            }
+            AdminRequest::Complete { line, reply } => {
+                let choices = admin::complete(&line, |command| self.completions(command));
+                let _ = reply.send(choices);
+            }
+        }
+        false
+    }
+
+    fn run_admin(&mut self, command: AdminCommand) -> String {
+        match command {
+            AdminCommand::Status => self.status(),
+            AdminCommand::Kick(player) => {
+                let found = self.find_players(&player);
+                match found.as_slice() {
+                    [] => format!("Nobody called {player:?} is playing"),
+                    [(room, addr, name)] => {
+                        let message = format!("Kicking {name} ({addr})");
+                        self.command_room(
+                            *room,
+                            RoomCommand::Kick {
+                                addr: *addr,
+                                why: "kicked by an admin".to_string(),
+                            },
+                        );
+                        message
+                    }
+                    _ => {
+                        let addrs: Vec<String> =
+                            found.iter().map(|(_, addr, _)| addr.to_string()).collect();
+                        format!(
+                            "{} players are called {player:?}, kick one by address: {}",
+                            found.len(),
+                            addrs.join(", ")
+                        )
+                    }
+                }
+            }
+            AdminCommand::Ban(ip) => {
+                if !self.bans.insert(ip) {
+                    return format!("{ip} is already banned");
+                }
+                let banned: Vec<(RoomId, SocketAddr)> = self
+                    .scoreboards()
+                    .filter(|(_, addr, _)| addr.ip() == ip)
+                    .map(|(room, addr, _)| (room, addr))
+                    .collect();
+                for (room, addr) in &banned {
+                    self.command_room(
+                        *room,
+                        RoomCommand::Kick {
+                            addr: *addr,
+                            why: "banned".to_string(),
+                        },
+                    );
+                }
+                format!("Banned {ip}, kicking {} players", banned.len())
+            }
+            AdminCommand::Unban(ip) => {
+                if !self.bans.remove(&ip) {
+                    return format!("{ip} isn't banned");
+                }
+                format!("Unbanned {ip}")
+            }
+            AdminCommand::Map(map) => {
+                let loaded = match maps::load(&self.config.maps_dir, &map) {
+                    Ok(loaded) => Arc::new(loaded),
+                    Err(e) => return format!("Can't switch maps: {e}"),
+                };
+                // Carry on with the rotation from here.
+                if let Some(index) = self.config.maps.iter().position(|next| *next == map) {
+                    self.rotation = (index + 1) % self.config.maps.len();
+                }
+                for handle in self.rooms.values_mut() {
+                    handle.map = map.clone();
+                }
+                let rooms = self.command_rooms(|| RoomCommand::ChangeMap(loaded.clone()));
+                let answer = format!("Switched to {map}, restarting {rooms} rooms");
+                self.map = map;
+                self.update_listing();
+                answer
+            }
+            AdminCommand::Set {
+                setting: None,
+                value: _,
+            } => SETTINGS
+                .iter()
+                .filter_map(|setting| {
+                    let value = admin::setting(&self.config, setting)?;
+                    Some(format!("{setting} = {value}"))
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            AdminCommand::Set {
+                setting: Some(setting),
+                value: None,
+            } => match admin::setting(&self.config, &setting) {
+                Some(value) => format!("{setting} = {value}"),
+                None => format!("No setting called {setting:?}, try set"),
+            },
+            AdminCommand::Set {
+                setting: Some(setting),
+                value: Some(value),
+            } => {
+                let mut config = self.config.clone();
+                if let Err(e) = admin::change_setting(&mut config, &setting, &value) {
+                    return e;
+                }
+                if let Err(e) = config::validate(&config) {
+                    return e.to_string();
+                }
+                self.reconfigure(config);
+                let value = admin::setting(&self.config, &setting).unwrap_or_default();
+                format!("{setting} = {value}")
+            }
+            AdminCommand::Say(text) => {
+                let rooms = self.command_rooms(|| RoomCommand::Say(text.clone()));
+                format!("Said to {rooms} rooms")
+            }
+            AdminCommand::Restart => {
+                let rooms = self.command_rooms(|| RoomCommand::Restart);
+                format!("Restarting {rooms} rooms")
+            }
+            AdminCommand::Help => admin::help(),
+            // The run loop takes care of that.
+            AdminCommand::Quit => String::new(),
+        }
+    }
+
+    fn status(&self) -> String {
+        let players = self.scoreboards().count();
+        let mut lines = vec![format!(
+            "{}: {}, {}, {} players in {}/{} rooms",
+            self.config.name,
+            self.map,
+            self.config.mode,
+            players,
+            self.rooms.len(),
+            self.config.max_rooms
+        )];
+        for (room, handle) in &self.rooms {
+            let kind = match handle.kind {
+                RoomKind::QuickPlay => "quick play",
+                RoomKind::Hosted { private: false } => "hosted",
+                RoomKind::Hosted { private: true } => "private",
+            };
+            let state = if handle.started { "playing" } else { "lobby" };
+            lines.push(format!(
+                "{:?} {} {} on {}, {}",
+                room, handle.code, kind, handle.map, state
+            ));
+            for (addr, player) in &handle.scoreboard {
+                lines.push(format!("  {} ({}) {}", player.name, addr, player.score));
+            }
+        }
+        if !self.bans.is_empty() {
+            let bans: Vec<String> = self.bans.iter().map(IpAddr::to_string).collect();
+            lines.push(format!("Banned: {}", bans.join(", ")));
+        }
+        lines.join("\n")
+    }
+
+    /// Everyone in a room, by the last scoreboard it sent.
+    fn scoreboards(&self) -> impl Iterator<Item = (RoomId, SocketAddr, &str)> {
+        self.rooms.iter().flat_map(|(room, handle)| {
+            handle
+                .scoreboard
+                .iter()
+                .map(|(addr, player)| (*room, *addr, player.name.as_str()))
+        })
+    }
+
+    /// Players going by `player`, or at that address.
+    fn find_players(&self, player: &str) -> Vec<(RoomId, SocketAddr, String)> {
+        self.scoreboards()
+            .filter(|(_, addr, name)| *name == player || addr.to_string() == player)
+            .map(|(room, addr, name)| (room, addr, name.to_string()))
+            .collect()
+    }
+
+    /// What could follow `command`, for tab completion.
+    fn completions(&self, command: &str) -> Vec<String> {
+        match command {
+            "kick" => self
+                .scoreboards()
+                .flat_map(|(_, addr, name)| [name.to_string(), addr.to_string()])
+                .collect(),
+            "ban" => self
+                .scoreboards()
+                .map(|(_, addr, _)| addr.ip().to_string())
+                .collect(),
+            "unban" => self.bans.iter().map(IpAddr::to_string).collect(),
+            "map" => maps::available(&self.config.maps_dir),
+            "set" => SETTINGS.iter().map(|setting| setting.to_string()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn command_room(&self, room: RoomId, command: RoomCommand) {
+        if let Some(handle) = self.rooms.get(&room) {
+            // A room that's gone is cleaned up at the next housekeeping.
+            let _ = handle.commands.send(command);
+        }
+    }
+
+    /// Sends every room a command, returning how many there were.
+    fn command_rooms(&self, command: impl Fn() -> RoomCommand) -> usize {
+        for handle in self.rooms.values() {
+            let _ = handle.commands.send(command());
+        }
+        self.rooms.len()
+    }
+
+    /// Switches to new settings, as far as a running server can. The rest
+    /// wait for a restart.
+    fn reconfigure(&mut self, mut config: ServerConfig) {
+        let fixed = [
+            ("bind", config.bind != self.config.bind),
+            ("port", config.port != self.config.port),
+            ("tick_rate", config.tick_rate != self.config.tick_rate),
+            (
+                "discovery_port",
+                config.discovery_port != self.config.discovery_port,
+            ),
+            ("netsim", config.network != self.config.network),
+        ];
+        for (setting, changed) in fixed {
+            if changed {
+                println!("{} only changes when the server restarts", setting);
+            }
+        }
+        config.bind = self.config.bind;
+        config.port = self.config.port;
+        config.tick_rate = self.config.tick_rate;
+        config.discovery_port = self.config.discovery_port;
+        config.network = self.config.network;
+
+        // Rooms already open keep their maps.
+        if config.maps != self.config.maps {
+            self.rotation = 0;
+        }
+        self.config = config;
+        self.update_listing();
+        let config = self.config.clone();
+        self.command_rooms(|| RoomCommand::Configure(Box::new(config.clone())));
+    }
+
+    fn update_listing(&self) {
+        let mut listing = self.listing.lock().unwrap_or_else(PoisonError::into_inner);
+        *listing = listing_details(&self.config, &self.map);
+    }
+
+    fn count_players(&self) {
+        let joined = self.routes.values().filter(|route| route.joined).count();
+        self.players_online.store(joined, Ordering::Relaxed);
+    }
+
+    fn housekeeping(&mut self) {
+        let now = Instant::now();
+        self.info_limiter.forget_idle(now);
+
+        // Rooms only stop on their own if something went wrong.
+        let crashed: Vec<RoomId> = self
+            .rooms
+            .iter()
+            .filter(|(_, handle)| handle.thread.is_finished())
+            .map(|(room, _)| *room)
+            .collect();
+        for room in crashed {
+            self.remove_room(room);
+        }
+
+        // Connects the room never accepted: rejected, or given up on.
+        let timeout = self.config.client_timeout;
+        self.routes
+            .retain(|_, route| route.joined || now.duration_since(route.since) < timeout);
+
+        let players: HashMap<RoomId, usize> = self
+            .rooms
+            .keys()
+            .map(|room| (*room, self.places_taken(*room)))
+            .collect();
+        for (room, handle) in &mut self.rooms {
+            if players[room] > 0 {
+                handle.empty_since = None;
+            } else if handle.empty_since.is_none() {
+                handle.empty_since = Some(now);
+            }
+        }
+
+        let idle: Vec<RoomId> = self
+            .rooms
+            .iter()
+            .filter(|(_, handle)| {
+                handle
+                    .empty_since
+                    .is_some_and(|since| now.duration_since(since) > ROOM_IDLE_TIMEOUT)
+            })
+            .map(|(room, _)| *room)
+            .collect();
+        for room in idle {
+            println!("Closing {:?}, nobody has been in it for a while", room);
+            // Dropping the inbox is what tells the room to stop.
+            self.rooms.remove(&room);
+        }
+        self.count_players();
+    }
+
+    /// Forgets a room that stopped without being asked to, along with
+    /// everyone in it. They'll time out and connect again to another room.
+    fn remove_room(&mut self, room: RoomId) {
+        let Some(handle) = self.rooms.remove(&room) else {
+            return;
+        };
+        match handle.thread.join() {
+            Err(_) => println!("{:?} crashed", room),
+            Ok(()) => println!("{:?} stopped", room),
+        }
+        self.sessions
+            .retain(|_, session_room| *session_room != room);
+        self.resumable.retain(|_, resume_room| *resume_room != room);
+        self.routes.retain(|_, route| route.room != room);
+        self.count_players();
+    }
+
+    /// Asks every room to stop and waits until they have.
+    async fn close_all(&mut self) {
+        for (room, handle) in std::mem::take(&mut self.rooms) {
+            drop(handle.inbox);
+            if task::spawn_blocking(move || handle.thread.join())
+                .await
+                .is_ok_and(|joined| joined.is_err())
+            {
+                println!("{:?} crashed", room);
+            }
+        }
+    }
+}
+
+fn listing_details(config: &ServerConfig, map: &str) -> ListingDetails {
+    ListingDetails {
+        name: config.name.clone(),
+        map: map.to_string(),
+        max_players: config.max_players * config.max_rooms,
+    }
+}
+
+/// For counts that go out as u16. Nobody has that many players.
+pub fn clamp_u16(count: usize) -> u16 {
+    count.min(u16::MAX as usize) as u16
+}
+
+/// Sleeps until `due`, which the caller has checked is set.
+async fn sleep_until(due: Option<Instant>) {
+    if let Some(due) = due {
+        time::sleep_until(due.into()).await;
+    }
+}
+
+/// Writes queued datagrams to the socket, through the network simulator in
+/// case it's been asked to make things worse. Runs until every sender is gone
+/// and nothing is left in flight.
+async fn send_loop(
+    socket: Arc<UdpSocket>,
+    mut queue: mpsc::Receiver<Datagram>,
+    network: NetworkConditions,
+) {
+    let mut simulator = NetworkSimulator::new(network);
+    let mut open = true;
+
+    while open || simulator.next_due().is_some() {
+        let due = simulator.next_due();
+        tokio::select! {
+            datagram = queue.recv(), if open => match datagram {
+                Some(datagram) => simulator.push(&datagram.bytes, datagram.addr, Instant::now()),
+                None => open = false,
+            },
+            _ = sleep_until(due), if due.is_some() => {}
+        }
+
+        while let Some((bytes, addr)) = simulator.pop(Instant::now()) {
+            if let Err(e) = socket.send_to(&bytes, addr).await {
+                println!("Sending to {:?} failed: {}", addr, e);
+            }
+        }
+    }
+}
+
+/// Queues `packets` for the socket. If the queue is full they're dropped, as
+/// they would be on a congested link.
+pub fn send_packets(outgoing: &mpsc::Sender<Datagram>, addr: SocketAddr, packets: Vec<Packet>) {
+    for packet in packets {
+        let bytes = match protocol::encode(&packet) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                println!("Not sending packet to {:?}: {}", addr, e);
+                continue;
+            }
+        };
+
+        if outgoing.try_send(Datagram { addr, bytes }).is_err() {
+            println!("Dropping packet to {:?}, the socket is backed up", addr);
+        }
+    }
+}
+
+/// Sends `message` to an address without a session, so there's no connection
+/// to queue it on. Only good for one-off replies.
+pub fn send_unconnected(
+    outgoing: &mpsc::Sender<Datagram>,
+    addr: SocketAddr,
+    message: &ServerMessage,
+) {
+    let now = Instant::now();
+    let mut connection = Connection::new(now);
+    if let Err(e) = connection.send_message(message.channel(), message) {
+        println!("Not sending {:?} to {:?}: {}", message, addr, e);
+    }
+    send_packets(outgoing, addr, connection.flush(now));
+}
@@ -0,0 +1,435 @@
+use std::{
+    collections::HashMap,
+    mem,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use protocol::{
+    ClientId, ConnectRequest, Connection, PROTOCOL_VERSION, RejectReason, ResumeToken, SessionId,
+    SnapshotHistory,
+};
+
+use crate::{interest::Interest, maps::MapTransfer, validation::InputValidator};
+
+pub struct Session {
+    pub client: ClientId,
+    pub id: SessionId,
+    pub resume: ResumeToken,
+    pub addr: SocketAddr,
+    pub name: String,
+    pub connected_at: Instant,
+    pub last_heard: Instant,
+    /// Whether the client has sent anything with its session id, which
+    /// means it got our accept.
+    pub accepted: bool,
+    pub connection: Connection,
+    /// Snapshots we've sent, to delta against once the client acks one.
+    pub snapshots: SnapshotHistory,
+    pub acked_snapshot: Option<u32>,
+    /// Which entities the client hears about.
+    pub interest: Interest,
+    /// The map, if the client asked for it and hasn't been sent all of it.
+    pub map_transfer: Option<MapTransfer>,
+    /// A ping to answer with the next snapshot: when the client sent it, by
+    /// its clock, and when it got here, by ours.
+    pub ping: Option<(u32, Instant)>,
+    /// Ticks between what the client was looking at and the tick its newest
+    /// input was applied on.
+    pub view_delay: Option<u32>,
+    pub validator: InputValidator,
+}
+
+impl Session {
+    fn new(client: ClientId, addr: SocketAddr, name: &str, now: Instant) -> Self {
+        Self {
+            client,
+            // Never 0, so never `SessionId::NONE`.
+            id: SessionId(protocol::random()),
+            resume: ResumeToken(protocol::random()),
+            addr,
+            name: name.to_string(),
+            connected_at: now,
+            last_heard: now,
+            accepted: false,
+            connection: Connection::new(now),
+            snapshots: SnapshotHistory::default(),
+            acked_snapshot: None,
+            interest: Interest::default(),
+            map_transfer: None,
+            ping: None,
+            view_delay: None,
+            validator: InputValidator::default(),
+        }
+    }
+}
+
+/// How a connect request was taken.
+pub enum Accepted {
+    /// From a client we'd already accepted, which missed our answer.
+    Again,
+    New,
+    /// Carries on from an earlier session, which is gone now.
+    Resumed {
+        old: Box<Session>,
+        /// Another client whose session was at the same address. It's held
+        /// as though it had timed out, since the address isn't its any more.
+        displaced: Option<ClientId>,
+    },
+}
+
+/// The connected-clients table: every accepted connection, keyed by client,
+/// and those that dropped recently enough to be resumed.
+pub struct Sessions {
+    sessions: HashMap<ClientId, Session>,
+    by_addr: HashMap<SocketAddr, ClientId>,
+    /// Sessions that timed out, kept until the grace period is over.
+    held: HashMap<ClientId, Session>,
+    next_client: u32,
+    max_players: usize,
+    timeout: Duration,
+    grace: Duration,
+    max_message_size: usize,
+}
+
+impl Sessions {
+    pub fn new(
+        max_players: usize,
+        timeout: Duration,
+        grace: Duration,
+        max_message_size: usize,
+    ) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            by_addr: HashMap::new(),
+            held: HashMap::new(),
+            next_client: 0,
+            max_players,
+            timeout,
+            grace,
+            max_message_size,
+        }
+    }
+
+    /// Accepts or rejects a connect request. Returns the existing session if
+    /// `addr` is already connected, since the client may have missed our
+    /// accept, and a new one in place of the old if the request resumes it.
+    pub fn connect(
+        &mut self,
+        addr: SocketAddr,
+        request: &ConnectRequest,
+        now: Instant,
+    ) -> Result<(&mut Session, Accepted), RejectReason> {
+        if request.protocol_version != PROTOCOL_VERSION {
+            return Err(RejectReason::VersionMismatch {
+                server: PROTOCOL_VERSION,
+            });
+        }
+
+        if let Some(mut old) = request
+            .resume
+            .and_then(|token| self.take_resumable(addr, token))
+        {
+            let mut session = Session::new(old.client, addr, &old.name, now);
+            session.resume = old.resume;
+            session.connected_at = old.connected_at;
+            session.validator = mem::take(&mut old.validator);
+
+            // Left where it is, whatever that client sends next would be
+            // taken for ours, and removing it later would unmap us.
+            let displaced = self.client_at(addr);
+            if let Some(session) = displaced.and_then(|client| self.remove(client)) {
+                self.held.insert(session.client, session);
+            }
+            let session = self.insert(session);
+            let old = Box::new(old);
+            return Ok((session, Accepted::Resumed { old, displaced }));
+        }
+
+        if let Some(client) = self.by_addr.get(&addr) {
+            let session = self.sessions.get_mut(client).expect("by_addr out of sync");
+            return Ok((session, Accepted::Again));
+        }
+
+        // Held places count, or whoever they belong to couldn't come back.
+        if self.sessions.len() + self.held.len() >= self.max_players {
+            return Err(RejectReason::ServerFull);
+        }
+
+        let client = ClientId(self.next_client);
+        self.next_client += 1;
+        let session = self.insert(Session::new(client, addr, &request.name, now));
+        Ok((session, Accepted::New))
+    }
+
+    /// Removes the session `token` resumes, held or still live. A live one
+    /// at `addr` whose client hasn't heard our accept is only being asked
+    /// for again, so it stays.
+    fn take_resumable(&mut self, addr: SocketAddr, token: ResumeToken) -> Option<Session> {
+        if let Some(client) = self.held.values().find(|held| held.resume == token) {
+            let client = client.client;
+            return self.held.remove(&client);
+        }
+        let client = self
+            .sessions
+            .values()
+            .find(|session| session.resume == token && (session.addr != addr || session.accepted))?
+            .client;
+        self.remove(client)
+    }
+
+    fn insert(&mut self, mut session: Session) -> &mut Session {
+        session
+            .connection
+            .set_max_message_size(self.max_message_size);
+        self.by_addr.insert(session.addr, session.client);
+        self.sessions
+            .entry(session.client)
+            .insert_entry(session)
+            .into_mut()
+    }
+
+    /// Looks up the client behind a packet, checking it carries the right
+    /// session id for its address, and marks it as alive.
+    pub fn verify(&mut self, addr: SocketAddr, id: SessionId, now: Instant) -> Option<ClientId> {
+        let client = self.by_addr.get(&addr)?;
+        let session = self.sessions.get_mut(client)?;
+        if session.id != id {
+            return None;
+        }
+        session.last_heard = now;
+        session.accepted = true;
+        Some(session.client)
+    }
+
+    /// Applies new limits. Nobody already connected is turned away.
+    pub fn configure(
+        &mut self,
+        max_players: usize,
+        timeout: Duration,
+        grace: Duration,
+        max_message_size: usize,
+    ) {
+        self.max_players = max_players;
+        self.timeout = timeout;
+        self.grace = grace;
+        self.max_message_size = max_message_size;
+        for session in self.sessions.values_mut() {
+            session.connection.set_max_message_size(max_message_size);
+        }
+    }
+
+    pub fn client_at(&self, addr: SocketAddr) -> Option<ClientId> {
+        self.by_addr.get(&addr).copied()
+    }
+
+    pub fn get(&self, client: ClientId) -> Option<&Session> {
+        self.sessions.get(&client)
+    }
+
+    pub fn get_mut(&mut self, client: ClientId) -> Option<&mut Session> {
+        self.sessions.get_mut(&client)
+    }
+
+    pub fn remove(&mut self, client: ClientId) -> Option<Session> {
+        let session = self.sessions.remove(&client)?;
+        self.by_addr.remove(&session.addr);
+        Some(session)
+    }
+
+    /// Moves every session we haven't heard from within the timeout to the
+    /// held list, returning them.
+    pub fn hold_silent(&mut self, now: Instant) -> Vec<&Session> {
+        let silent: Vec<ClientId> = self
+            .sessions
+            .values()
+            .filter(|session| now.duration_since(session.last_heard) > self.timeout)
+            .map(|session| session.client)
+            .collect();
+
+        for client in &silent {
+            if let Some(session) = self.remove(*client) {
+                self.held.insert(*client, session);
+            }
+        }
+        silent
+            .iter()
+            .filter_map(|client| self.held.get(client))
+            .collect()
+    }
+
+    /// Removes and returns every held session whose grace period is over.
+    pub fn expire(&mut self, now: Instant) -> Vec<Session> {
+        let limit = self.timeout + self.grace;
+        let expired: Vec<ClientId> = self
+            .held
+            .values()
+            .filter(|session| now.duration_since(session.last_heard) > limit)
+            .map(|session| session.client)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|client| self.held.remove(&client))
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Session> {
+        self.sessions.values()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Session> {
+        self.sessions.values_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{DEFAULT_MAX_MESSAGE_SIZE, RoomRequest};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const GRACE: Duration = Duration::from_secs(30);
+
+    fn sessions() -> Sessions {
+        Sessions::new(4, TIMEOUT, GRACE, DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn request(resume: Option<ResumeToken>) -> ConnectRequest {
+        ConnectRequest {
+            protocol_version: PROTOCOL_VERSION,
+            name: "player".to_string(),
+            room: RoomRequest::QuickPlay,
+            password: None,
+            resume,
+        }
+    }
+
+    /// Connects a new client at `addr` and has it answer, returning its
+    /// client, session id and resume token.
+    fn join(
+        sessions: &mut Sessions,
+        addr: SocketAddr,
+        now: Instant,
+    ) -> (ClientId, SessionId, ResumeToken) {
+        let (session, accepted) = sessions.connect(addr, &request(None), now).ok().unwrap();
+        assert!(matches!(accepted, Accepted::New));
+        let joined = (session.client, session.id, session.resume);
+        assert_eq!(sessions.verify(addr, joined.1, now), Some(joined.0));
+        joined
+    }
+
+    /// Resumes with `token` from `addr`, returning the new session id and
+    /// who got displaced.
+    fn resume(
+        sessions: &mut Sessions,
+        addr: SocketAddr,
+        token: ResumeToken,
+        now: Instant,
+    ) -> (ClientId, SessionId, Option<ClientId>) {
+        let (session, accepted) = sessions
+            .connect(addr, &request(Some(token)), now)
+            .ok()
+            .unwrap();
+        let (client, id) = (session.client, session.id);
+        let Accepted::Resumed { old, displaced } = accepted else {
+            panic!("not resumed");
+        };
+        assert_eq!(old.client, client);
+        assert_eq!(old.resume, token);
+        (client, id, displaced)
+    }
+
+    #[test]
+    fn resumes_a_held_session() {
+        let mut sessions = sessions();
+        let now = Instant::now();
+        let (client, old_id, token) = join(&mut sessions, addr(1), now);
+
+        let later = now + TIMEOUT * 2;
+        assert_eq!(sessions.hold_silent(later).len(), 1);
+        assert_eq!(sessions.client_at(addr(1)), None);
+
+        let (resumed, id, displaced) = resume(&mut sessions, addr(2), token, later);
+        assert_eq!(resumed, client);
+        assert_eq!(displaced, None);
+        assert_ne!(id, old_id);
+        assert_eq!(sessions.verify(addr(2), id, later), Some(client));
+        assert!(sessions.expire(later + GRACE * 2).is_empty());
+    }
+
+    #[test]
+    fn resumes_a_live_session_from_a_new_address() {
+        let mut sessions = sessions();
+        let now = Instant::now();
+        let (client, old_id, token) = join(&mut sessions, addr(1), now);
+
+        let (resumed, id, displaced) = resume(&mut sessions, addr(2), token, now);
+        assert_eq!(resumed, client);
+        assert_eq!(displaced, None);
+        assert_eq!(sessions.client_at(addr(1)), None);
+        assert_eq!(sessions.verify(addr(1), old_id, now), None);
+        assert_eq!(sessions.verify(addr(2), id, now), Some(client));
+        assert_eq!(sessions.iter().count(), 1);
+    }
+
+    #[test]
+    fn repeated_connect_gets_the_same_session() {
+        let mut sessions = sessions();
+        let now = Instant::now();
+        let (session, _) = sessions.connect(addr(1), &request(None), now).ok().unwrap();
+        let (client, id, token) = (session.client, session.id, session.resume);
+
+        let (session, accepted) = sessions.connect(addr(1), &request(None), now).ok().unwrap();
+        assert!(matches!(accepted, Accepted::Again));
+        assert_eq!((session.client, session.id), (client, id));
+
+        // Our accept hasn't got through, so resuming from the same place is
+        // only asking again too.
+        let (session, accepted) = sessions
+            .connect(addr(1), &request(Some(token)), now)
+            .ok()
+            .unwrap();
+        assert!(matches!(accepted, Accepted::Again));
+        assert_eq!((session.client, session.id), (client, id));
+        assert_eq!(sessions.iter().count(), 1);
+    }
+
+    #[test]
+    fn resume_displaces_another_client_at_the_address() {
+        let mut sessions = sessions();
+        let now = Instant::now();
+        let (first, _, token) = join(&mut sessions, addr(1), now);
+        let (second, _, _) = join(&mut sessions, addr(2), now);
+
+        let (resumed, id, displaced) = resume(&mut sessions, addr(2), token, now);
+        assert_eq!(resumed, first);
+        assert_eq!(displaced, Some(second));
+        assert_eq!(sessions.client_at(addr(2)), Some(first));
+        assert!(sessions.get(second).is_none());
+
+        // The displaced session running out mustn't take our address with it.
+        let later = now + TIMEOUT + GRACE * 2;
+        assert_eq!(sessions.verify(addr(2), id, later), Some(first));
+        let expired = sessions.expire(later);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].client, second);
+        assert_eq!(sessions.verify(addr(2), id, later), Some(first));
+        assert!(sessions.get(first).is_some());
+
+        // Until then it could still have come back.
+        let mut sessions = self::sessions();
+        let (first, _, token) = join(&mut sessions, addr(1), now);
+        let (second, _, second_token) = join(&mut sessions, addr(2), now);
+        resume(&mut sessions, addr(2), token, now);
+        let (resumed, id, displaced) = resume(&mut sessions, addr(3), second_token, now);
+        assert_eq!((resumed, displaced), (second, None));
+        assert_eq!(sessions.verify(addr(3), id, now), Some(second));
+        assert_eq!(sessions.client_at(addr(2)), Some(first));
+    }
+}
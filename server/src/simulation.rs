@@ -0,0 +1,336 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+use protocol::{ClientId, EntityKind, EntityState, InputCommand, NetEntity};
+use simulation::{
+    clients::{ClientInfo, ConnectedClients},
+    level::{Map, Prop},
+    player::{Player, PlayerInput, SPAWN_POINT, player_bundle},
+    replication::{Replicated, ReplicationRegistry},
+    snowball::{Ammo, Snowball},
+};
+
+pub struct SnowballState {
+    pub entity: Entity,
+    pub owner: ClientId,
+    pub translation: Vec3,
+    pub velocity: Vec3,
+}
+
+/// An entity's id on the wire. Handed out in order, and never again once
+/// the entity is gone, unlike Bevy's entity indices.
+#[derive(Component, Clone, Copy)]
+struct NetId(NetEntity);
+
+/// Commands a client can have waiting. Past this the oldest are dropped, so
+/// a client that runs fast can't build up a backlog that plays out as lag.
+const MAX_QUEUED_INPUTS: usize = 8;
+
+struct PlayerSlot {
+    entity: Entity,
+    /// The sequence of the last command applied.
+    last_input: u32,
+    /// Commands waiting for their tick, by sequence.
+    queued: BTreeMap<u32, InputCommand>,
+}
+
+/// Owns the headless Bevy world and maps connected clients onto its player entities.
+pub struct Simulation {
+    app: App,
+    players: HashMap<ClientId, PlayerSlot>,
+    /// The last `NetEntity` handed out.
+    last_net_entity: u32,
+}
+
+impl Simulation {
+    pub fn new(tick_rate: u32, map: Map) -> Self {
+        let mut app = simulation::headless_app(tick_rate);
+        app.insert_resource(map);
+        Self {
+            app,
+            players: HashMap::new(),
+            last_net_entity: 0,
+        }
+    }
+
+    /// Builds the level from `map` instead, at the start of the next step.
+    pub fn set_map(&mut self, map: Map) {
+        self.app.insert_resource(map);
+    }
+
+    pub fn add_player(&mut self, client: ClientId, name: &str) {
+        let world = self.app.world_mut();
+        let entity = world.spawn(player_bundle(SPAWN_POINT)).id();
+        world.resource_mut::<ConnectedClients>().insert(
+            client,
+            ClientInfo {
+                name: name.to_string(),
+                entity,
+                score: 0,
+            },
+        );
+
+        self.players.insert(
+            client,
+            PlayerSlot {
+                entity,
+                last_input: 0,
+                queued: BTreeMap::new(),
+            },
+        );
+    }
+
+    pub fn remove_player(&mut self, client: ClientId) {
+        if let Some(slot) = self.players.remove(&client) {
+            let world = self.app.world_mut();
+            world.resource_mut::<ConnectedClients>().remove(client);
+            world.despawn(slot.entity);
+        }
+    }
+
+    /// Queues a command for `client`'s player, to be applied on a tick of its
+    /// own. Older or duplicate commands are ignored.
+    pub fn queue_input(&mut self, client: ClientId, command: InputCommand) {
+        let Some(slot) = self.players.get_mut(&client) else {
+            return;
+        };
+        if command.sequence <= slot.last_input || slot.queued.contains_key(&command.sequence) {
+            return;
+        }
+        slot.queued.insert(command.sequence, command);
+        while slot.queued.len() > MAX_QUEUED_INPUTS {
+            slot.queued.pop_first();
+        }
+    }
+
+    /// Takes the next queued command for every client that has one, marking
+    /// it applied. The client keeps its last input until the next one arrives.
+    pub fn next_inputs(&mut self) -> Vec<(ClientId, InputCommand)> {
+        self.players
+            .iter_mut()
+            .filter_map(|(client, slot)| {
+                let (sequence, command) = slot.queued.pop_first()?;
+                slot.last_input = sequence;
+                Some((*client, command))
+            })
+            .collect()
+    }
+
+    /// Feeds a command to `client`'s player for the coming tick.
+    pub fn apply_input(&mut self, client: ClientId, command: &InputCommand) {
+        let Some(slot) = self.players.get(&client) else {
+            return;
+        };
+        if let Some(mut input) = self.app.world_mut().get_mut::<PlayerInput>(slot.entity) {
+            *input = PlayerInput::from_command(command);
+        }
+    }
+
+    /// Leaves the client's player standing where it is, facing the same way,
+    /// until its input comes in again.
+    pub fn idle(&mut self, client: ClientId) {
+        let Some(slot) = self.players.get_mut(&client) else {
+            return;
+        };
+        slot.queued.clear();
+        if let Some(mut input) = self.app.world_mut().get_mut::<PlayerInput>(slot.entity) {
+            input.movement = Vec3::ZERO;
+            input.throw = false;
+        }
+    }
+
+    pub fn last_input(&self, client: ClientId) -> u32 {
+        self.players.get(&client).map_or(0, |slot| slot.last_input)
+    }
+
+    pub fn player_positions(&self) -> Vec<(ClientId, Vec3)> {
+        let world = self.app.world();
+        world
+            .resource::<ConnectedClients>()
+            .iter()
+            .filter_map(|(client, info)| {
+                Some((client, world.get::<Transform>(info.entity)?.translation))
+            })
+            .collect()
+    }
+
+    pub fn snowballs(&mut self) -> Vec<SnowballState> {
+        let world = self.app.world_mut();
+        let mut snowballs = world.query::<(Entity, &Snowball, &Transform, &Velocity)>();
+
+        let world = &*world;
+        let clients = world.resource::<ConnectedClients>();
+        snowballs
+            .iter(world)
+            .filter_map(|(entity, snowball, transform, velocity)| {
+                Some(SnowballState {
+                    entity,
+                    owner: clients.client_of(snowball.owner)?,
+                    translation: transform.translation,
+                    velocity: velocity.linvel,
+                })
+            })
+            .collect()
+    }
+
+    pub fn remove_snowball(&mut self, entity: Entity) {
+        self.app.world_mut().despawn(entity);
+    }
+
+    /// Adds `points` to the client's score and returns the new total.
+    pub fn add_score(&mut self, client: ClientId, points: i32) -> Option<i32> {
+        let mut clients = self.app.world_mut().resource_mut::<ConnectedClients>();
+        let info = clients.get_mut(client)?;
+        info.score += points;
+        Some(info.score)
+    }
+
+    pub fn score(&self, client: ClientId) -> Option<i32> {
+        let clients = self.app.world().resource::<ConnectedClients>();
+        clients.get(client).map(|info| info.score)
+    }
+
+    /// Back to the start of a match: everyone at the spawn point with full
+    /// hands, no snowballs in the air and no points on the board.
+    pub fn restart(&mut self) {
+        let world = self.app.world_mut();
+
+        let mut snowballs = world.query_filtered::<Entity, With<Snowball>>();
+        let snowballs: Vec<Entity> = snowballs.iter(world).collect();
+        for entity in snowballs {
+            world.despawn(entity);
+        }
+
+        for slot in self.players.values() {
+            if let Some(mut transform) = world.get_mut::<Transform>(slot.entity) {
+                transform.translation = SPAWN_POINT;
+            }
+            if let Some(mut player) = world.get_mut::<Player>(slot.entity) {
+                player.velocity = Vec3::ZERO;
+            }
+            if let Some(mut ammo) = world.get_mut::<Ammo>(slot.entity) {
+                *ammo = Ammo::default();
+            }
+        }
+
+        let mut clients = world.resource_mut::<ConnectedClients>();
+        for client in self.players.keys() {
+            if let Some(info) = clients.get_mut(*client) {
+                info.score = 0;
+            }
+        }
+    }
+
+    /// Advances the world by one tick.
+    pub fn step(&mut self) {
+        self.app.update();
+    }
+
+    /// Every replicated entity, sorted by id.
+    pub fn entities(&mut self) -> Vec<EntityState> {
+        self.assign_net_ids();
+        let world = self.app.world_mut();
+        let mut replicated = world.query_filtered::<EntityRef, With<Replicated>>();
+
+        let world = &*world;
+        let clients = world.resource::<ConnectedClients>();
+        let registry = world.resource::<ReplicationRegistry>();
+        let mut entities: Vec<EntityState> = replicated
+            .iter(world)
+            .filter_map(|entity| {
+                let (kind, velocity) = if let Some(player) = entity.get::<Player>() {
+                    (
+                        EntityKind::Player(clients.client_of(entity.id())?),
+                        player.velocity,
+                    )
+                } else if let Some(snowball) = entity.get::<Snowball>() {
+                    let owner = clients.client_of(snowball.owner)?;
+                    (
+                        EntityKind::Snowball { owner },
+                        entity.get::<Velocity>()?.linvel,
+                    )
+                } else if let Some(Prop(index)) = entity.get::<Prop>() {
+                    (
+                        EntityKind::Prop { index: *index },
+                        entity.get::<Velocity>()?.linvel,
+                    )
+                } else {
+                    return None;
+                };
+                let transform = entity.get::<Transform>()?;
+                Some(EntityState {
+                    id: entity.get::<NetId>()?.0,
+                    kind,
+                    translation: transform.translation.to_array(),
+                    rotation: transform.rotation.to_array(),
+                    velocity: velocity.to_array(),
+                    components: registry.read(entity),
+                })
+            })
+            .collect();
+
+        entities.sort_by_key(|entity| entity.id);
+        entities
+    }
+
+    /// Gives replicated entities spawned since the last call their ids.
+    fn assign_net_ids(&mut self) {
+        let world = self.app.world_mut();
+        let new: Vec<Entity> = world
+            .query_filtered::<Entity, (With<Replicated>, Without<NetId>)>()
+            .iter(world)
+            .collect();
+        for entity in new {
+            self.last_net_entity += 1;
+            world
+                .entity_mut(entity)
+                .insert(NetId(NetEntity(self.last_net_entity)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use simulation::level::{self, LEVEL_NAME};
+
+    use super::*;
+
+    const TICK_RATE: u32 = 60;
+
+    fn throw_for(simulation: &mut Simulation, client: ClientId, ticks: u32) {
+        let command = InputCommand {
+            throw: true,
+            ..Default::default()
+        };
+        for _ in 0..ticks {
+            simulation.apply_input(client, &command);
+            simulation.step();
+        }
+    }
+
+    #[test]
+    fn throwing_every_tick_runs_out_of_ammo() {
+        let mut simulation = Simulation::new(TICK_RATE, level::builtin(LEVEL_NAME).unwrap());
+        let client = ClientId(1);
+        simulation.add_player(client, "thrower");
+
+        // A full hand of five plus the one that refills after a second, each
+        // a quarter of a second apart.
+        throw_for(&mut simulation, client, 100);
+        assert_eq!(simulation.snowballs().len(), 6);
+
+        // Out now, so nothing more until the next refill.
+        throw_for(&mut simulation, client, 15);
+        assert_eq!(simulation.snowballs().len(), 6);
+
+        throw_for(&mut simulation, client, 15);
+        assert_eq!(simulation.snowballs().len(), 7);
+        assert!(
+            simulation
+                .snowballs()
+                .iter()
+                .all(|snowball| snowball.owner == client)
+        );
+    }
+}
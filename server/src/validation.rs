@@ -0,0 +1,327 @@
+//! Checks on player input before it reaches the simulation.
+//!
+//! The server already owns every position, so a client can't teleport by
+//! claiming to be somewhere else. What it can do is send input the real client
+//! never would: a movement vector longer than a key press, a jump strength of
+//! a hundred, or a throw on every tick. Those are clamped back into range, or
+//! dropped when there's no sensible value to clamp to, and counted against the
+//! session. Throw cooldown and ammo are game rules and live in the simulation;
+//! asking for a throw too soon is something honest players do all the time.
+
+use std::{collections::VecDeque, f32::consts::FRAC_PI_2, fmt};
+
+use protocol::InputCommand;
+
+/// More throws asked for in a second than anyone can click.
+const MAX_THROWS_PER_SECOND: usize = 15;
+
+/// Something about an input command the real client can't have sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// NaN or infinity somewhere. The command is dropped.
+    NotFinite,
+    /// Moving forward or sideways faster than a key press.
+    Movement,
+    /// Jumping higher than a jump, or a negative one.
+    Jump,
+    /// Looking further up or down than straight up or down.
+    Pitch,
+    /// Throws asked for faster than a person can click.
+    ThrowRate,
+}
+
+impl Violation {
+    /// Whether the whole command has to go, rather than being clamped.
+    pub fn rejects(self) -> bool {
+        matches!(self, Violation::NotFinite)
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::NotFinite => write!(f, "input that isn't a number"),
+            Violation::Movement => write!(f, "moving too fast"),
+            Violation::Jump => write!(f, "jumping too high"),
+            Violation::Pitch => write!(f, "looking past straight up or down"),
+            Violation::ThrowRate => write!(f, "throwing too often"),
+        }
+    }
+}
+
+/// What one session has been up to, for the checks that span commands.
+#[derive(Default)]
+pub struct InputValidator {
+    /// Ticks the last few throws were asked for on, oldest first.
+    throws: VecDeque<u32>,
+    /// Input the real client can't have sent, since connecting.
+    violations: u32,
+}
+
+impl InputValidator {
+    /// Clamps `command` into range, returning the first thing that needed
+    /// fixing. Only call it for commands that will be applied, so resends
+    /// aren't counted twice.
+    pub fn check(
+        &mut self,
+        command: &mut InputCommand,
+        tick: u32,
+        tick_rate: u32,
+    ) -> Option<Violation> {
+        let mut violation = None;
+
+        if !command.movement.iter().all(|value| value.is_finite())
+            || !command.yaw.is_finite()
+            || !command.pitch.is_finite()
+        {
+            return Some(Violation::NotFinite);
+        }
+
+        let [forward, right, jump] = &mut command.movement;
+        for value in [forward, right] {
+            violation = violation.or(clamp(value, -1., 1., Violation::Movement));
+        }
+        violation = violation.or(clamp(jump, 0., 1., Violation::Jump));
+        violation = violation.or(clamp(
+            &mut command.pitch,
+            -FRAC_PI_2,
+            FRAC_PI_2,
+            Violation::Pitch,
+        ));
+
+        if command.throw {
+            while self
+                .throws
+                .front()
+                .is_some_and(|&thrown| tick.wrapping_sub(thrown) >= tick_rate)
+            {
+                self.throws.pop_front();
+            }
+            if self.throws.len() >= MAX_THROWS_PER_SECOND {
+                command.throw = false;
+                violation = violation.or(Some(Violation::ThrowRate));
+            } else {
+                self.throws.push_back(tick);
+            }
+        }
+
+        violation
+    }
+
+    /// Counts a violation against the session. True once there have been
+    /// `kick_after` of them.
+    pub fn count(&mut self, kick_after: Option<u32>) -> bool {
+        self.violations += 1;
+        kick_after.is_some_and(|limit| self.violations >= limit)
+    }
+
+    /// How many violations have been counted.
+    pub fn violations(&self) -> u32 {
+        self.violations
+    }
+}
+
+fn clamp(value: &mut f32, min: f32, max: f32, violation: Violation) -> Option<Violation> {
+    if (min..=max).contains(value) {
+        return None;
+    }
+    *value = value.clamp(min, max);
+    Some(violation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK_RATE: u32 = 60;
+
+    fn command(movement: [f32; 3], pitch: f32) -> InputCommand {
+        InputCommand {
+            movement,
+            pitch,
+            ..Default::default()
+        }
+    }
+
+    fn throw() -> InputCommand {
+        InputCommand {
+            throw: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn honest_input_passes_untouched() {
+        let mut validator = InputValidator::default();
+        let mut input = command([1., -1., 1.], -FRAC_PI_2);
+        let before = input;
+
+        assert_eq!(validator.check(&mut input, 0, TICK_RATE), None);
+        assert_eq!(input, before);
+    }
+
+    #[test]
+    fn speed_is_clamped() {
+        let mut validator = InputValidator::default();
+        let mut input = command([5., -3., 0.], 0.);
+
+        assert_eq!(
+            validator.check(&mut input, 0, TICK_RATE),
+            Some(Violation::Movement)
+        );
+        assert_eq!(input.movement, [1., -1., 0.]);
+    }
+
+    #[test]
+    fn jump_is_clamped() {
+        let mut validator = InputValidator::default();
+
+        let mut input = command([0., 0., 100.], 0.);
+        assert_eq!(
+            validator.check(&mut input, 0, TICK_RATE),
+            Some(Violation::Jump)
+        );
+        assert_eq!(input.movement[2], 1.);
+
+        let mut input = command([0., 0., -1.], 0.);
+        assert_eq!(
+            validator.check(&mut input, 1, TICK_RATE),
+            Some(Violation::Jump)
+        );
+        assert_eq!(input.movement[2], 0.);
+    }
+
+    #[test]
+    fn look_is_clamped() {
+        let mut validator = InputValidator::default();
+
+        let mut input = command([0.; 3], 4.);
+        assert_eq!(
+            validator.check(&mut input, 0, TICK_RATE),
+            Some(Violation::Pitch)
+        );
+        assert_eq!(input.pitch, FRAC_PI_2);
+
+        let mut input = command([0.; 3], -4.);
+        assert_eq!(
+            validator.check(&mut input, 1, TICK_RATE),
+            Some(Violation::Pitch)
+        );
+        assert_eq!(input.pitch, -FRAC_PI_2);
+    }
+
+    #[test]
+    fn everything_out_of_range_is_clamped_at_once() {
+        let mut validator = InputValidator::default();
+        let mut input = command([2., 2., 2.], 2.);
+
+        assert_eq!(
+            validator.check(&mut input, 0, TICK_RATE),
+            Some(Violation::Movement)
+        );
+        assert_eq!(input.movement, [1., 1., 1.]);
+        assert_eq!(input.pitch, FRAC_PI_2);
+    }
+
+    #[test]
+    fn numbers_that_arent_are_rejected() {
+        let mut validator = InputValidator::default();
+        for mut input in [
+            command([f32::NAN, 0., 0.], 0.),
+            command([0., f32::INFINITY, 0.], 0.),
+            command([0.; 3], f32::NEG_INFINITY),
+            InputCommand {
+                yaw: f32::NAN,
+                ..Default::default()
+            },
+        ] {
+            let violation = validator.check(&mut input, 0, TICK_RATE);
+            assert_eq!(violation, Some(Violation::NotFinite));
+            assert!(violation.unwrap().rejects());
+        }
+        assert!(!Violation::Movement.rejects());
+        assert!(!Violation::ThrowRate.rejects());
+    }
+
+    #[test]
+    fn throws_are_limited_to_fifteen_a_second() {
+        let mut validator = InputValidator::default();
+
+        for tick in 0..MAX_THROWS_PER_SECOND as u32 {
+            let mut input = throw();
+            assert_eq!(validator.check(&mut input, tick, TICK_RATE), None);
+            assert!(input.throw);
+        }
+
+        let mut input = throw();
+        assert_eq!(
+            validator.check(&mut input, 15, TICK_RATE),
+            Some(Violation::ThrowRate)
+        );
+        assert!(!input.throw);
+
+        // Still within a second of the first throw.
+        let mut input = throw();
+        assert_eq!(
+            validator.check(&mut input, TICK_RATE - 1, TICK_RATE),
+            Some(Violation::ThrowRate)
+        );
+
+        // A second on, the first throw has aged out and makes room for one.
+        let mut input = throw();
+        assert_eq!(validator.check(&mut input, TICK_RATE, TICK_RATE), None);
+        assert!(input.throw);
+        let mut input = throw();
+        assert_eq!(
+            validator.check(&mut input, TICK_RATE, TICK_RATE),
+            Some(Violation::ThrowRate)
+        );
+    }
+
+    #[test]
+    fn throwing_at_a_clicking_pace_is_fine() {
+        let mut validator = InputValidator::default();
+        for tick in (0..10 * TICK_RATE).step_by(5) {
+            let mut input = throw();
+            assert_eq!(validator.check(&mut input, tick, TICK_RATE), None);
+        }
+    }
+
+    #[test]
+    fn throw_limit_survives_the_tick_wrapping() {
+        let mut validator = InputValidator::default();
+        let start = u32::MAX - 5;
+        for i in 0..MAX_THROWS_PER_SECOND as u32 {
+            let mut input = throw();
+            let tick = start.wrapping_add(i);
+            assert_eq!(validator.check(&mut input, tick, TICK_RATE), None);
+        }
+
+        let mut input = throw();
+        let tick = start.wrapping_add(MAX_THROWS_PER_SECOND as u32);
+        assert_eq!(
+            validator.check(&mut input, tick, TICK_RATE),
+            Some(Violation::ThrowRate)
+        );
+    }
+
+    #[test]
+    fn kick_once_the_threshold_is_reached() {
+        let mut validator = InputValidator::default();
+
+        assert!(!validator.count(Some(3)));
+        assert!(!validator.count(Some(3)));
+        assert!(validator.count(Some(3)));
+        assert!(validator.count(Some(3)));
+        assert_eq!(validator.violations(), 4);
+    }
+
+    #[test]
+    fn no_threshold_never_kicks() {
+        let mut validator = InputValidator::default();
+        for _ in 0..1000 {
+            assert!(!validator.count(None));
+        }
+        assert_eq!(validator.violations(), 1000);
+    }
+}
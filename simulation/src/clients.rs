@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use protocol::ClientId;
+
+pub struct ClientInfo {
+    pub name: String,
+    pub entity: Entity,
+    pub score: i32,
+}
+
+/// Who is connected and which player entity they control. The server keeps
+/// this in sync with its sessions so gameplay systems can look players up.
+#[derive(Resource, Default)]
+pub struct ConnectedClients {
+    clients: HashMap<ClientId, ClientInfo>,
+}
+
+impl ConnectedClients {
+    pub fn insert(&mut self, client: ClientId, info: ClientInfo) {
+        self.clients.insert(client, info);
+    }
+
+    pub fn remove(&mut self, client: ClientId) -> Option<ClientInfo> {
+        self.clients.remove(&client)
+    }
+
+    pub fn get(&self, client: ClientId) -> Option<&ClientInfo> {
+        self.clients.get(&client)
+    }
+
+    pub fn get_mut(&mut self, client: ClientId) -> Option<&mut ClientInfo> {
+        self.clients.get_mut(&client)
+    }
+
+    /// Reverse lookup from a player entity to the client controlling it.
+    pub fn client_of(&self, entity: Entity) -> Option<ClientId> {
+        self.clients
+            .iter()
+            .find(|(_, info)| info.entity == entity)
+            .map(|(client, _)| *client)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, &ClientInfo)> {
+        self.clients.iter().map(|(client, info)| (*client, info))
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
@@ -0,0 +1,300 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use protocol::{
+    Decode, DecodeError, Encode, EncodeError, Reader, Writer, codec, map::MAX_MAP_SIZE,
+};
+use serde::Deserialize;
+
+use crate::replication::Replicated;
+
+const SHAPES_X_EXTENT: f32 = 14.0;
+const Z_EXTENT: f32 = 5.0;
+
+/// What servers call this level when telling players which map they're on.
+pub const LEVEL_NAME: &str = "shapes";
+
+/// Every map a server can be asked to run.
+pub const LEVELS: &[&str] = &[LEVEL_NAME];
+
+pub const GROUND_HEIGHT: f32 = 0.1;
+pub const GROUND_SIZE: f32 = 200.1;
+
+/// How many kinds of rotating shape there are. `Shape` indexes into these.
+pub const SHAPE_COUNT: usize = 9;
+
+/// Most shapes a map can have.
+pub const MAX_MAP_SHAPES: usize = 1024;
+
+/// Most props a map can have. Every one goes out in snapshots.
+pub const MAX_MAP_PROPS: usize = 32;
+
+/// What a level is made of. Put one in the world as a resource and the level
+/// is built from it, replacing whatever was there. Servers can load maps
+/// other than the built in ones from TOML files with the same fields.
+#[derive(Resource, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Map {
+    /// How far the ground reaches from the origin.
+    pub ground_size: f32,
+    pub shapes: Vec<MapShape>,
+    pub props: Vec<MapProp>,
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        Self {
+            ground_size: GROUND_SIZE,
+            shapes: Vec::new(),
+            props: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MapShape {
+    /// Which of the `SHAPE_COUNT` kinds it is.
+    pub shape: u8,
+    pub translation: [f32; 3],
+    /// A quaternion, x, y, z then w. It doesn't have to be normalized.
+    #[serde(default = "identity")]
+    pub rotation: [f32; 4],
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MapProp {
+    pub translation: [f32; 3],
+    #[serde(default)]
+    pub velocity: [f32; 3],
+    #[serde(default)]
+    pub angular_velocity: [f32; 3],
+}
+
+fn identity() -> [f32; 4] {
+    Quat::IDENTITY.to_array()
+}
+
+impl Map {
+    /// The map's encoding, as sent to clients and hashed to tell maps apart.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodeError> {
+        let mut writer = Writer::new();
+        self.encode(&mut writer)?;
+        writer.finish(MAX_MAP_SIZE)
+    }
+
+    /// Says what's wrong with a map that can't be built, if anything.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.ground_size.is_finite() && self.ground_size > 0.) {
+            return Err("ground_size must be more than 0".to_string());
+        }
+        if self.shapes.len() > MAX_MAP_SHAPES {
+            return Err(format!("a map can have at most {MAX_MAP_SHAPES} shapes"));
+        }
+        if self.props.len() > MAX_MAP_PROPS {
+            return Err(format!("a map can have at most {MAX_MAP_PROPS} props"));
+        }
+        for (index, shape) in self.shapes.iter().enumerate() {
+            if usize::from(shape.shape) >= SHAPE_COUNT {
+                return Err(format!(
+                    "shape {index} is of kind {}, there are only {SHAPE_COUNT}",
+                    shape.shape
+                ));
+            }
+            let rotation = Quat::from_array(shape.rotation);
+            let finite = shape.translation.iter().all(|value| value.is_finite())
+                && rotation.is_finite()
+                && rotation.length_squared() > 0.;
+            if !finite {
+                return Err(format!(
+                    "shape {index} needs a finite translation and a rotation that isn't 0"
+                ));
+            }
+        }
+        for (index, prop) in self.props.iter().enumerate() {
+            let finite = [prop.translation, prop.velocity, prop.angular_velocity]
+                .iter()
+                .flatten()
+                .all(|value| value.is_finite());
+            if !finite {
+                return Err(format!("prop {index} has a value that isn't finite"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The built in map called `name`, if there is one.
+pub fn builtin(name: &str) -> Option<Map> {
+    match name {
+        LEVEL_NAME => Some(shapes()),
+        _ => None,
+    }
+}
+
+/// A row of spinning shapes and a cone to knock around.
+fn shapes() -> Map {
+    let shapes = (0..SHAPE_COUNT)
+        .map(|i| MapShape {
+            shape: i as u8,
+            translation: [
+                -SHAPES_X_EXTENT / 2. + i as f32 / (SHAPE_COUNT - 1) as f32 * SHAPES_X_EXTENT,
+                2.0,
+                Z_EXTENT / 2.,
+            ],
+            rotation: Quat::from_rotation_x(-PI / 4.).to_array(),
+        })
+        .collect();
+    Map {
+        ground_size: GROUND_SIZE,
+        shapes,
+        props: vec![MapProp {
+            translation: [5.0, 5.0, 5.0],
+            velocity: [0.0, 2.0, 0.0],
+            angular_velocity: [0.2, 0.0, 0.0],
+        }],
+    }
+}
+
+/// Everything built from the `Map`, to clear away when it changes.
+#[derive(Component)]
+pub struct LevelEntity;
+
+#[derive(Component)]
+pub struct Ground;
+
+#[derive(Component)]
+pub struct Shape(pub usize);
+
+/// Loose dynamic objects that anyone can knock around, by their index in the
+/// map's props.
+#[derive(Component)]
+pub struct Prop(pub u16);
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PreUpdate,
+            build_level.run_if(resource_exists_and_changed::<Map>),
+        )
+        .add_systems(Update, rotate);
+    }
+}
+
+pub fn build_level(mut commands: Commands, map: Res<Map>, old: Query<Entity, With<LevelEntity>>) {
+    for entity in &old {
+        commands.entity(entity).despawn();
+    }
+
+    // Ground plane
+    commands.spawn((
+        LevelEntity,
+        Ground,
+        Transform::from_xyz(0.0, -GROUND_HEIGHT, 0.0),
+        Collider::cuboid(map.ground_size, GROUND_HEIGHT, map.ground_size),
+    ));
+
+    for shape in &map.shapes {
+        commands.spawn((
+            LevelEntity,
+            Shape(usize::from(shape.shape)),
+            Transform::from_translation(Vec3::from_array(shape.translation))
+                .with_rotation(Quat::from_array(shape.rotation).normalize()),
+            // Every shape collides like the default 1x1x1 cuboid.
+            Collider::cuboid(0.5, 0.5, 0.5),
+        ));
+    }
+
+    for (index, prop) in map.props.iter().enumerate() {
+        // Fits: a map has at most `MAX_MAP_PROPS`.
+        let index = index as u16;
+        commands
+            .spawn((LevelEntity, Prop(index), Replicated, RigidBody::Dynamic))
+            .insert(Transform::from_translation(Vec3::from_array(
+                prop.translation,
+            )))
+            .insert(Velocity {
+                linvel: Vec3::from_array(prop.velocity),
+                angvel: Vec3::from_array(prop.angular_velocity),
+            })
+            .insert(GravityScale(0.5))
+            .insert(Sleeping::disabled())
+            .insert(ColliderMassProperties::Density(2.0))
+            .insert(Ccd::enabled());
+    }
+
+    info!("Finished making level!");
+}
+
+fn rotate(mut query: Query<&mut Transform, With<Shape>>, time: Res<Time>) {
+    for mut transform in &mut query {
+        transform.rotate_y(time.delta_secs() / 2.);
+    }
+}
+
+impl Encode for Map {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_f32(self.ground_size);
+        codec::encode_list(writer, &self.shapes, MAX_MAP_SHAPES)?;
+        codec::encode_list(writer, &self.props, MAX_MAP_PROPS)
+    }
+}
+
+impl Decode for Map {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Map {
+            ground_size: reader.read_f32()?,
+            shapes: codec::decode_list(reader, MAX_MAP_SHAPES)?,
+            props: codec::decode_list(reader, MAX_MAP_PROPS)?,
+        })
+    }
+}
+
+impl Encode for MapShape {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_u8(self.shape);
+        writer.write_vec3(self.translation);
+        writer.write_quat(self.rotation);
+        Ok(())
+    }
+}
+
+impl Decode for MapShape {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let shape = reader.read_u8()?;
+        if usize::from(shape) >= SHAPE_COUNT {
+            return Err(DecodeError::UnknownTag {
+                kind: "Shape",
+                tag: shape,
+            });
+        }
+        Ok(MapShape {
+            shape,
+            translation: reader.read_vec3()?,
+            rotation: reader.read_quat()?,
+        })
+    }
+}
+
+impl Encode for MapProp {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_vec3(self.translation);
+        writer.write_vec3(self.velocity);
+        writer.write_vec3(self.angular_velocity);
+        Ok(())
+    }
+}
+
+impl Decode for MapProp {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(MapProp {
+            translation: reader.read_vec3()?,
+            velocity: reader.read_vec3()?,
+            angular_velocity: reader.read_vec3()?,
+        })
+    }
+}
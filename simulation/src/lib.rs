@@ -0,0 +1,70 @@
+//! The authoritative game rules: level colliders, player movement and snowballs.
+//!
+//! The client adds meshes, cameras and input on top of this; the server runs it
+//! headless through [`headless_app`].
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use bevy::{app::ScheduleRunnerPlugin, log::LogPlugin, prelude::*, time::TimeUpdateStrategy};
+use bevy_rapier3d::plugin::{NoUserData, RapierPhysicsPlugin};
+
+use crate::replication::ReplicationAppExt;
+
+pub mod clients;
+pub mod level;
+pub mod player;
+pub mod replication;
+pub mod snowball;
+
+/// Fixed tick systems that consume `PlayerInput`. Whoever writes the input
+/// (local keyboard, network) should run before this.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SimulationSystems;
+
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<clients::ConnectedClients>()
+            .add_plugins((
+                RapierPhysicsPlugin::<NoUserData>::default(),
+                level::LevelPlugin,
+                player::PlayerPlugin,
+                snowball::SnowballPlugin,
+            ))
+            // Client and server both come through here, so they agree on
+            // the order.
+            .replicate::<snowball::Ammo>();
+    }
+}
+
+/// The global logger can only be installed once per process, and a server
+/// runs one headless app per room.
+static LOGGER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Builds a windowless app running only the simulation.
+///
+/// Nothing drives it on its own: every call to `App::update` advances the
+/// world by exactly one tick of `1 / tick_rate` seconds.
+pub fn headless_app(tick_rate: u32) -> App {
+    let tick = Duration::from_secs(1) / tick_rate.max(1);
+
+    let mut app = App::new();
+    if !LOGGER_INSTALLED.swap(true, Ordering::Relaxed) {
+        app.add_plugins(LogPlugin::default());
+    }
+    app.add_plugins((
+        MinimalPlugins.build().disable::<ScheduleRunnerPlugin>(),
+        TransformPlugin,
+        SimulationPlugin,
+    ))
+    .insert_resource(Time::<Fixed>::from_duration(tick))
+    .insert_resource(TimeUpdateStrategy::ManualDuration(tick));
+
+    app.finish();
+    app.cleanup();
+    app
+}
@@ -0,0 +1,234 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use protocol::InputCommand;
+
+use crate::{SimulationSystems, replication::Replicated, snowball::Ammo};
+
+pub const SPAWN_POINT: Vec3 = Vec3::new(10., 10., 10.);
+
+/// Ends of the player's capsule collider, relative to its transform.
+pub const CAPSULE_START: Vec3 = Vec3::new(1., 1., 1.);
+pub const CAPSULE_END: Vec3 = Vec3::new(1., 1., 1.);
+pub const CAPSULE_RADIUS: f32 = 1.;
+
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        // physics timestep
+        app.add_systems(FixedUpdate, update_movement.in_set(SimulationSystems));
+    }
+}
+
+#[derive(Component)]
+pub struct Player {
+    pub velocity: Vec3,
+    pub gravity: f32,
+    pub speed: f32,
+}
+
+/// What a player wants to do this tick. The client keeps one as a resource
+/// for its own keyboard and mouse; every player entity carries one as well.
+#[derive(Component, Resource, Default, Clone, Copy, Debug)]
+pub struct PlayerInput {
+    //x component is forward and y direction is right and z is up
+    pub movement: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub throw: bool,
+}
+
+impl PlayerInput {
+    pub fn to_command(&self, sequence: u32, view_tick: u32) -> InputCommand {
+        InputCommand {
+            sequence,
+            view_tick,
+            movement: self.movement.to_array(),
+            yaw: self.yaw,
+            pitch: self.pitch,
+            throw: self.throw,
+        }
+    }
+
+    pub fn from_command(command: &InputCommand) -> Self {
+        Self {
+            movement: Vec3::from_array(command.movement),
+            yaw: command.yaw,
+            pitch: command.pitch,
+            throw: command.throw,
+        }
+    }
+}
+
+/// Everything a player needs to be simulated. Cameras and meshes are the client's business.
+pub fn player_bundle(translation: Vec3) -> impl Bundle {
+    (
+        Player {
+            velocity: Vec3::ZERO,
+            gravity: 9.8,
+            speed: 20.0,
+        },
+        PlayerInput::default(),
+        Ammo::default(),
+        Replicated,
+        Transform::from_translation(translation),
+        Collider::capsule(CAPSULE_START, CAPSULE_END, CAPSULE_RADIUS),
+        LockedAxes::ROTATION_LOCKED,
+        RigidBody::Dynamic,
+        KinematicCharacterController {
+            up: Vec3::Y,
+            offset: CharacterLength::Absolute(0.01),
+            ..default()
+        },
+    )
+}
+
+pub fn update_movement(
+    time: Res<Time<Fixed>>,
+    mut player_query: Query<(
+        &mut Player,
+        &mut PlayerInput,
+        &mut KinematicCharacterController,
+        Option<&KinematicCharacterControllerOutput>,
+    )>,
+) {
+    for (mut player, mut input, mut controller, controller_output) in player_query.iter_mut() {
+        let grounded = controller_output.is_some_and(|output| output.grounded);
+
+        //delta
+        controller.translation = Some(step_player(
+            &mut player,
+            &input,
+            grounded,
+            time.timestep().as_secs_f32(),
+        ));
+
+        // A jump is only good for one tick.
+        input.movement.z = 0.;
+    }
+}
+
+/// Applies one tick of `input` to the player's velocity and returns how far it
+/// wants to move. Pure, so the client can replay inputs with it when predicting.
+pub fn step_player(player: &mut Player, input: &PlayerInput, grounded: bool, dt: f32) -> Vec3 {
+    if grounded {
+        player.velocity = Vec3::ZERO;
+
+        // Can only jump on ground
+        player.velocity.y += input.movement.z * 10.0;
+    }
+
+    let camera_x = input.yaw - PI;
+
+    let forward = Vec2::new(f32::sin(camera_x), f32::cos(camera_x));
+
+    let right = Vec2::new(-forward.y, forward.x);
+
+    if let Some(movement_direction) =
+        (forward * input.movement.x + right * input.movement.y).try_normalize()
+    {
+        player.velocity.x = movement_direction.x * player.speed;
+        player.velocity.z = movement_direction.y * player.speed;
+    }
+
+    // Gravity
+    player.velocity.y -= player.gravity * dt;
+
+    player.velocity * dt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless_app;
+
+    const DT: f32 = 0.125;
+
+    fn new_player() -> Player {
+        Player {
+            velocity: Vec3::ZERO,
+            gravity: 9.8,
+            speed: 20.0,
+        }
+    }
+
+    /// Facing along +z.
+    fn input(forward: f32, right: f32, jump: f32) -> PlayerInput {
+        PlayerInput {
+            movement: Vec3::new(forward, right, jump),
+            yaw: PI,
+            ..default()
+        }
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        a.distance(b) < 1e-4
+    }
+
+    #[test]
+    fn walks_the_way_its_facing() {
+        let mut player = new_player();
+        let step = step_player(&mut player, &input(1., 0., 0.), true, DT);
+
+        assert!(close(player.velocity, Vec3::new(0., -9.8 * DT, 20.)));
+        assert!(close(step, player.velocity * DT));
+
+        let mut player = new_player();
+        step_player(&mut player, &input(0., 1., 0.), true, DT);
+        assert!(close(player.velocity, Vec3::new(-20., -9.8 * DT, 0.)));
+    }
+
+    #[test]
+    fn diagonals_are_no_faster() {
+        let mut player = new_player();
+        step_player(&mut player, &input(1., 1., 0.), true, DT);
+        assert!((player.velocity.xz().length() - player.speed).abs() < 1e-4);
+    }
+
+    #[test]
+    fn stops_on_the_ground_but_not_in_the_air() {
+        let mut player = new_player();
+        step_player(&mut player, &input(1., 0., 0.), true, DT);
+
+        let mut standing = new_player();
+        standing.velocity = player.velocity;
+        step_player(&mut standing, &input(0., 0., 0.), true, DT);
+        assert!(close(standing.velocity, Vec3::new(0., -9.8 * DT, 0.)));
+
+        let mut flying = new_player();
+        flying.velocity = player.velocity;
+        step_player(&mut flying, &input(0., 0., 0.), false, DT);
+        assert_eq!(flying.velocity.z, 20.);
+        assert!(flying.velocity.y < player.velocity.y);
+    }
+
+    #[test]
+    fn jumps_only_from_the_ground() {
+        let mut player = new_player();
+        step_player(&mut player, &input(0., 0., 1.), true, DT);
+        assert!((player.velocity.y - (10. - 9.8 * DT)).abs() < 1e-4);
+
+        // Still going up, but slower, and jumping again in the air does nothing.
+        let rising = player.velocity.y;
+        step_player(&mut player, &input(0., 0., 1.), false, DT);
+        assert!((player.velocity.y - (rising - 9.8 * DT)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn players_move_each_tick_and_jumps_are_spent() {
+        let mut app = headless_app(60);
+        let entity = app.world_mut().spawn(player_bundle(SPAWN_POINT)).id();
+        *app.world_mut().get_mut::<PlayerInput>(entity).unwrap() = input(1., 0., 1.);
+
+        // The first update only starts the clock.
+        app.update();
+        app.update();
+
+        let world = app.world();
+        assert_eq!(world.get::<Player>(entity).unwrap().velocity.z, 20.);
+        let input = world.get::<PlayerInput>(entity).unwrap();
+        assert_eq!(input.movement, Vec3::new(1., 0., 0.));
+    }
+}
@@ -0,0 +1,110 @@
+//! Which entities the server tells clients about, and what it tells them.
+//!
+//! Tag an entity `Replicated` and it goes out in snapshots. Every replicated
+//! entity carries its transform and velocity, which are quantized, delta
+//! compressed and interpolated. Anything else it should carry is a component
+//! registered with [`ReplicationAppExt::replicate`]. Its `Encode` and
+//! `Decode` impls say how it travels, and it's sent whole whenever its
+//! encoding changes. Components are numbered in the order they're
+//! registered, so the client and server have to register the same ones in
+//! the same order. [`SimulationPlugin`](crate::SimulationPlugin) does that
+//! for the game's own.
+
+use bevy::prelude::*;
+use protocol::{
+    ComponentId, ComponentState, Decode, DecodeError, Encode, EncodeError, MAX_COMPONENT_SIZE,
+    MAX_ENTITY_COMPONENTS, Writer,
+};
+
+/// Marks an entity clients are told about.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Replicated;
+
+struct Registration {
+    name: &'static str,
+    read: fn(EntityRef) -> Option<Result<Vec<u8>, EncodeError>>,
+    write: fn(&mut EntityWorldMut, &[u8]) -> Result<(), DecodeError>,
+    remove: fn(&mut EntityWorldMut),
+}
+
+/// The components that travel with replicated entities, indexed by
+/// `ComponentId`.
+#[derive(Resource, Default)]
+pub struct ReplicationRegistry {
+    components: Vec<Registration>,
+}
+
+impl ReplicationRegistry {
+    /// Encodes every registered component `entity` has, sorted by id.
+    pub fn read(&self, entity: EntityRef) -> Vec<ComponentState> {
+        self.components
+            .iter()
+            .enumerate()
+            .filter_map(|(index, registration)| match (registration.read)(entity)? {
+                Ok(data) => Some(ComponentState {
+                    id: ComponentId(index as u8),
+                    data,
+                }),
+                Err(e) => {
+                    warn!(
+                        "Not replicating {} of {}: {}",
+                        registration.name,
+                        entity.id(),
+                        e
+                    );
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Makes `entity`'s registered components match `components`, inserting
+    /// or overwriting the ones there and removing the ones that aren't.
+    pub fn write(&self, entity: &mut EntityWorldMut, components: &[ComponentState]) {
+        for (index, registration) in self.components.iter().enumerate() {
+            let id = ComponentId(index as u8);
+            let Some(component) = components.iter().find(|component| component.id == id) else {
+                (registration.remove)(entity);
+                continue;
+            };
+            if let Err(e) = (registration.write)(entity, &component.data) {
+                warn!("Bad {} for {}: {}", registration.name, entity.id(), e);
+            }
+        }
+    }
+}
+
+pub trait ReplicationAppExt {
+    /// Sends `C` along with every replicated entity that has one.
+    fn replicate<C: Component + Encode + Decode>(&mut self) -> &mut Self;
+}
+
+impl ReplicationAppExt for App {
+    fn replicate<C: Component + Encode + Decode>(&mut self) -> &mut Self {
+        let mut registry = self
+            .world_mut()
+            .get_resource_or_init::<ReplicationRegistry>();
+        assert!(
+            registry.components.len() < MAX_ENTITY_COMPONENTS,
+            "at most {MAX_ENTITY_COMPONENTS} components can be replicated"
+        );
+        registry.components.push(Registration {
+            name: std::any::type_name::<C>(),
+            read: |entity| Some(encode_component(entity.get::<C>()?)),
+            write: |entity, data| {
+                entity.insert(protocol::decode_message::<C>(data)?);
+                Ok(())
+            },
+            remove: |entity| {
+                entity.remove::<C>();
+            },
+        });
+        self
+    }
+}
+
+fn encode_component(component: &impl Encode) -> Result<Vec<u8>, EncodeError> {
+    let mut writer = Writer::new();
+    component.encode(&mut writer)?;
+    writer.finish(MAX_COMPONENT_SIZE)
+}
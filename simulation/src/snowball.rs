@@ -0,0 +1,278 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::{
+    Ccd, Collider, ColliderMassProperties, GravityScale, RigidBody, Velocity,
+};
+
+use protocol::{Decode, DecodeError, Encode, EncodeError, Reader, Writer};
+
+use crate::{
+    SimulationSystems,
+    player::{Player, PlayerInput, update_movement},
+    replication::Replicated,
+};
+
+pub const THROW_SPEED: f32 = 10.0;
+
+/// Half the side of a snowball's cube collider.
+pub const SNOWBALL_HALF_EXTENT: f32 = 1.;
+
+/// Where snowballs appear relative to the thrower.
+pub const THROW_SPAWN_OFFSET: Vec3 = Vec3::new(2., 2., 2.);
+
+/// Shortest time between two throws, in seconds.
+pub const THROW_COOLDOWN: f32 = 0.25;
+
+/// Snowballs a player can carry.
+pub const MAX_AMMO: u32 = 5;
+
+/// Seconds to make another snowball when not carrying the most.
+pub const AMMO_REFILL: f32 = 1.0;
+
+/// Seconds a snowball lasts before it melts away.
+pub const SNOWBALL_LIFETIME: f32 = 10.0;
+
+/// Snowballs that fall below this height have left the level and are removed.
+pub const KILL_PLANE: f32 = -50.0;
+
+pub struct SnowballPlugin;
+
+impl Plugin for SnowballPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (throw_snowballs.after(update_movement), expire_snowballs).in_set(SimulationSystems),
+        );
+    }
+}
+
+#[derive(Component)]
+pub struct Snowball {
+    pub owner: Entity,
+}
+
+/// Seconds until a snowball is removed, if nothing else removes it first.
+#[derive(Component)]
+pub struct Lifetime(pub f32);
+
+/// Snowballs in hand, and how long until the player can throw or has made
+/// another. Throws asked for without either are dropped.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Ammo {
+    pub count: u32,
+    pub cooldown: f32,
+    pub refill: f32,
+}
+
+impl Default for Ammo {
+    fn default() -> Self {
+        Self {
+            count: MAX_AMMO,
+            cooldown: 0.,
+            refill: AMMO_REFILL,
+        }
+    }
+}
+
+impl Ammo {
+    fn tick(&mut self, dt: f32) {
+        self.cooldown = (self.cooldown - dt).max(0.);
+        if self.count >= MAX_AMMO {
+            self.refill = AMMO_REFILL;
+            return;
+        }
+        self.refill -= dt;
+        if self.refill <= 0. {
+            self.count += 1;
+            self.refill += AMMO_REFILL;
+        }
+    }
+
+    /// Takes a snowball if one can be thrown right now.
+    fn take(&mut self) -> bool {
+        if self.cooldown > 0. || self.count == 0 {
+            return false;
+        }
+        self.count -= 1;
+        self.cooldown = THROW_COOLDOWN;
+        true
+    }
+}
+
+/// Only the count is replicated; the timers are the server's business.
+impl Encode for Ammo {
+    fn encode(&self, writer: &mut Writer) -> Result<(), EncodeError> {
+        writer.write_varint(self.count);
+        Ok(())
+    }
+}
+
+impl Decode for Ammo {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Ammo {
+            count: reader.read_varint()?,
+            ..default()
+        })
+    }
+}
+
+pub fn throw_vector(yaw: f32, pitch: f32) -> Vec3 {
+    let camera_x = f32::sin(yaw - PI);
+    let camera_z = f32::cos(yaw - PI);
+    let camera_y = f32::sin(pitch);
+
+    Vec3::new(camera_x, camera_y, camera_z) * THROW_SPEED
+}
+
+fn throw_snowballs(
+    mut commands: Commands,
+    time: Res<Time<Fixed>>,
+    mut players: Query<(Entity, &Transform, &mut PlayerInput, &mut Ammo), With<Player>>,
+) {
+    for (entity, transform, mut input, mut ammo) in &mut players {
+        ammo.tick(time.timestep().as_secs_f32());
+        if !input.throw {
+            continue;
+        }
+        input.throw = false;
+        if !ammo.take() {
+            continue;
+        }
+
+        info!("Throwing!");
+        commands
+            .spawn((
+                Snowball { owner: entity },
+                Lifetime(SNOWBALL_LIFETIME),
+                Replicated,
+                RigidBody::Dynamic,
+                Collider::cuboid(
+                    SNOWBALL_HALF_EXTENT,
+                    SNOWBALL_HALF_EXTENT,
+                    SNOWBALL_HALF_EXTENT,
+                ),
+            ))
+            .insert(Transform::from_translation(
+                transform.translation + THROW_SPAWN_OFFSET,
+            ))
+            .insert(Velocity {
+                linvel: throw_vector(input.yaw, input.pitch),
+                angvel: Vec3::new(0.0, 0.0, 0.0),
+            })
+            .insert(GravityScale(1.0))
+            .insert(ColliderMassProperties::Density(2.0))
+            .insert(Ccd::enabled());
+    }
+}
+
+/// Removes snowballs that have lasted long enough or fallen off the level, so
+/// misses don't pile up.
+fn expire_snowballs(
+    mut commands: Commands,
+    time: Res<Time<Fixed>>,
+    mut snowballs: Query<(Entity, &Transform, &mut Lifetime), With<Snowball>>,
+) {
+    for (entity, transform, mut lifetime) in &mut snowballs {
+        lifetime.0 -= time.timestep().as_secs_f32();
+        if lifetime.0 <= 0. || transform.translation.y < KILL_PLANE {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{headless_app, player::player_bundle};
+
+    /// A power of two, so the timers land exactly on zero.
+    const DT: f32 = 0.125;
+
+    fn ticks(ammo: &mut Ammo, count: usize) {
+        for _ in 0..count {
+            ammo.tick(DT);
+        }
+    }
+
+    #[test]
+    fn throws_are_a_cooldown_apart() {
+        let mut ammo = Ammo::default();
+        assert!(ammo.take());
+        assert!(!ammo.take());
+
+        ticks(&mut ammo, 1);
+        assert!(!ammo.take());
+        ticks(&mut ammo, 1);
+        assert!(ammo.take());
+        assert_eq!(ammo.count, MAX_AMMO - 2);
+    }
+
+    #[test]
+    fn hands_run_empty() {
+        let mut ammo = Ammo::default();
+        for _ in 0..MAX_AMMO {
+            assert!(ammo.take());
+            ammo.cooldown = 0.;
+        }
+        assert_eq!(ammo.count, 0);
+        assert!(!ammo.take());
+    }
+
+    #[test]
+    fn snowballs_are_made_one_a_second() {
+        let mut ammo = Ammo {
+            count: 0,
+            ..default()
+        };
+
+        ticks(&mut ammo, 7);
+        assert_eq!(ammo.count, 0);
+        assert!(!ammo.take());
+        ticks(&mut ammo, 1);
+        assert_eq!(ammo.count, 1);
+
+        // Up to a full hand, and no further.
+        ticks(&mut ammo, 100);
+        assert_eq!(ammo.count, MAX_AMMO);
+        assert_eq!(ammo.refill, AMMO_REFILL);
+    }
+
+    #[test]
+    fn a_full_hand_starts_the_refill_over() {
+        let mut ammo = Ammo::default();
+        ticks(&mut ammo, 6);
+        assert!(ammo.take());
+
+        // Being full all that time saved nothing up.
+        ticks(&mut ammo, 7);
+        assert_eq!(ammo.count, MAX_AMMO - 1);
+        ticks(&mut ammo, 1);
+        assert_eq!(ammo.count, MAX_AMMO);
+    }
+
+    #[test]
+    fn throwing_every_tick_is_held_back() {
+        let mut app = headless_app(8);
+        let player = app.world_mut().spawn(player_bundle(Vec3::ZERO)).id();
+
+        let throw_for = |app: &mut App, ticks: usize| {
+            for _ in 0..ticks {
+                app.world_mut()
+                    .get_mut::<PlayerInput>(player)
+                    .unwrap()
+                    .throw = true;
+                app.update();
+            }
+            let world = app.world_mut();
+            world.query::<&Snowball>().iter(world).count()
+        };
+
+        // One every other tick until the hand is empty, with one made along
+        // the way...
+        assert_eq!(throw_for(&mut app, 12), 6);
+        // ...then one a second.
+        assert_eq!(throw_for(&mut app, 4), 6);
+        assert_eq!(throw_for(&mut app, 4), 7);
+    }
+}